@@ -1,112 +1,593 @@
 use std::collections::HashMap;
+use std::env;
+use std::fs;
 use std::path::PathBuf;
 
 use itertools::Itertools;
 
 use crate::common::GenericResult;
-use crate::controller::Action;
-use crate::email::{Mailer, EmailTemplate};
+use crate::consumer::{ConsumerOptions, MissingFilePolicy, MoveConflictPolicy, ShutdownTimeoutAction};
+use crate::controller::{Action, LowSpaceAction, ScheduleScope};
+use crate::email::{self, Mailer, EmailTemplate};
+use crate::mqtt::MqttPublisher;
+use crate::statsd::StatsdClient;
+use crate::transmissionrpc::{AddressFamily, BandwidthPriority, ProcessedMarker, TorrentStatus};
 use crate::util;
-use crate::util::time::{Duration, WeekPeriods};
+use crate::util::fs::{CopyOptions, FileOwner};
+use crate::util::net::Network;
+use crate::util::size::Size;
+use crate::util::time::{DateOverrides, Duration, WeekPeriods};
+
+// Environment variable with configuration file path which is used when it's not specified in command line
+const CONFIG_ENV_VAR: &str = "TC_CONFIG";
+
+// Torrent metadata files which are useless after downloading
+const DEFAULT_IGNORED_EXTENSIONS: [&str; 3] = ["nfo", "sfv", "url"];
 
 pub struct Arguments {
-    pub config: PathBuf,
+    pub configs: Vec<PathBuf>,
     pub debug_level: usize,
+    pub quiet_level: usize,
+    pub list_periods_now: bool,
+    pub list_processed: bool,
+    pub reset_processed: bool,
+    pub explain_cleanup: bool,
+    pub test_email: bool,
+    pub yes: bool,
+    pub log_targets: Vec<(String, log::Level)>,
+    pub log_hashes: bool,
+    pub rpc_connect_timeout: Option<Duration>,
+    pub rpc_timeout: Option<Duration>,
+    pub rpc_max_response_size: Option<u64>,
+    pub rpc_socks_proxy: Option<String>,
+    pub rpc_address_family: AddressFamily,
+    pub rpc_query_params: Vec<(String, String)>,
+    pub trusted_networks: Vec<Network>,
+    pub processed_marker: ProcessedMarker,
 
     pub action: Option<Action>,
     pub action_periods: WeekPeriods,
+    pub date_overrides: DateOverrides,
+    pub schedule_scope: ScheduleScope,
+    pub maintenance_periods: Option<WeekPeriods>,
+    pub active_only_in_periods: bool,
+    pub managed_statuses: Option<Vec<TorrentStatus>>,
+    pub tracker_hosts: Vec<String>,
+    pub managed_label: Option<String>,
+    pub priority_rules: Vec<(String, BandwidthPriority)>,
 
+    pub path_remaps: Vec<(PathBuf, PathBuf)>,
     pub copy_to: Option<PathBuf>,
+    pub copy_to_labels: Vec<(String, PathBuf)>,
+    pub copy_owner: Option<FileOwner>,
+    pub copy_options: CopyOptions,
+    pub copy_all_files: bool,
+    pub ignored_extensions: Vec<String>,
+    pub missing_file_policy: MissingFilePolicy,
     pub move_to: Option<PathBuf>,
+    pub move_conflict_policy: MoveConflictPolicy,
+    pub abandoned_files_check_period: Option<Duration>,
+    pub scrub_interval: Option<Duration>,
+    pub scrub_remove_empty_dirs: bool,
+    pub verify_before_consume: bool,
+    pub not_found_retries: usize,
+    pub not_found_retry_delay: Duration,
+    pub seed_location: Option<PathBuf>,
+    pub deletable_dirs: Vec<PathBuf>,
+    pub min_consume_size: Option<Size>,
+    pub max_consume_size: Option<Size>,
+    pub consume_delay: Option<Duration>,
+    pub reconsume_if_missing: bool,
 
     pub seed_time_limit: Option<Duration>,
+    pub max_age: Option<Duration>,
+    pub reannounce_interval: Option<Duration>,
     pub upload_ratio_limit: Option<f64>,
+    pub protect_private: bool,
+    pub min_other_seeders: Option<u64>,
+    pub remove_missing_data: bool,
+    pub seed_ratio_limit: Option<f64>,
     pub free_space_threshold: Option<u8>,
+    pub free_space_headroom: u8,
+    pub free_space_warn_threshold: Option<u8>,
+    pub free_space_check_retries: usize,
+    pub max_removals_per_cycle: Option<usize>,
+    pub on_low_space: LowSpaceAction,
+    pub observe_cycles: usize,
+    pub warmup_cycles: usize,
 
     pub error_mailer: Option<Mailer>,
+    pub email_state_file: Option<PathBuf>,
+    pub startup_error_grace: Duration,
+    pub shutdown_timeout: Option<Duration>,
+    pub on_shutdown_timeout: ShutdownTimeoutAction,
     pub notifications_mailer: Option<Mailer>,
-    pub torrent_downloaded_email_template: EmailTemplate,
+    pub torrent_downloaded_email_template: Option<EmailTemplate>,
+    pub torrent_failed_email_template: EmailTemplate,
+
+    pub mqtt_publisher: Option<MqttPublisher>,
+    pub statsd_client: Option<StatsdClient>,
 }
 
-pub fn parse() -> GenericResult<Arguments> {
-    let default_config_path = "~/.config/transmission-daemon/settings.json";
+impl Default for Arguments {
+    fn default() -> Arguments {
+        Arguments {
+            configs: Vec::new(),
+            debug_level: 0,
+            quiet_level: 0,
+            list_periods_now: false,
+            list_processed: false,
+            reset_processed: false,
+            explain_cleanup: false,
+            test_email: false,
+            yes: false,
+            log_targets: Vec::new(),
+            log_hashes: false,
+            rpc_connect_timeout: None,
+            rpc_timeout: None,
+            rpc_max_response_size: None,
+            rpc_socks_proxy: None,
+            rpc_address_family: AddressFamily::Any,
+            rpc_query_params: Vec::new(),
+            trusted_networks: Vec::new(),
+            processed_marker: ProcessedMarker::DownloadLimit,
+
+            action: None,
+            action_periods: WeekPeriods::new(),
+            date_overrides: DateOverrides::new(),
+            schedule_scope: ScheduleScope::All,
+            maintenance_periods: None,
+            active_only_in_periods: false,
+            managed_statuses: None,
+            tracker_hosts: Vec::new(),
+            managed_label: None,
+            priority_rules: Vec::new(),
 
-    let mut args = Arguments {
-        config: PathBuf::from(shellexpand::tilde(default_config_path).to_string()),
-        debug_level: 0,
+            path_remaps: Vec::new(),
+            copy_to: None,
+            copy_to_labels: Vec::new(),
+            copy_owner: None,
+            copy_options: CopyOptions::default(),
+            copy_all_files: false,
+            ignored_extensions: Vec::new(),
+            missing_file_policy: MissingFilePolicy::Fail,
+            move_to: None,
+            move_conflict_policy: MoveConflictPolicy::Rename,
+            abandoned_files_check_period: None,
+            scrub_interval: None,
+            scrub_remove_empty_dirs: false,
+            verify_before_consume: false,
+            not_found_retries: 2,
+            not_found_retry_delay: 5,
+            seed_location: None,
+            deletable_dirs: Vec::new(),
+            min_consume_size: None,
+            max_consume_size: None,
+            consume_delay: None,
+            reconsume_if_missing: false,
 
-        action: None,
-        action_periods: WeekPeriods::new(),
+            seed_time_limit: None,
+            max_age: None,
+            reannounce_interval: None,
+            upload_ratio_limit: None,
+            protect_private: false,
+            min_other_seeders: None,
+            remove_missing_data: false,
+            seed_ratio_limit: None,
+            free_space_threshold: None,
+            free_space_headroom: 0,
+            free_space_warn_threshold: None,
+            free_space_check_retries: 2,
+            max_removals_per_cycle: None,
+            on_low_space: LowSpaceAction::Delete,
+            observe_cycles: 0,
+            warmup_cycles: 0,
 
-        copy_to: None,
-        move_to: None,
+            error_mailer: None,
+            email_state_file: None,
+            startup_error_grace: 60,
+            shutdown_timeout: None,
+            on_shutdown_timeout: ShutdownTimeoutAction::Force,
+            notifications_mailer: None,
+            torrent_downloaded_email_template: None,
+            torrent_failed_email_template: ConsumerOptions::default().torrent_failed_email_template,
 
-        seed_time_limit: None,
-        upload_ratio_limit: None,
-        free_space_threshold: None,
+            mqtt_publisher: None,
+            statsd_client: None,
+        }
+    }
+}
+
+pub fn parse() -> GenericResult<Arguments> {
+    let default_config_path = "~/.config/transmission-daemon/settings.json";
 
-        error_mailer: None,
-        notifications_mailer: None,
-        torrent_downloaded_email_template: EmailTemplate::new(
-            "Downloaded: {{name}}", "{{name}} torrent has been downloaded."),
-    };
+    let mut args = Arguments::default();
 
+    let mut log_target_strings: Vec<String> = Vec::new();
+    let mut rpc_connect_timeout: Option<String> = None;
+    let mut rpc_timeout: Option<String> = None;
+    let mut rpc_address_family: Option<String> = None;
+    let mut startup_error_grace: Option<String> = None;
+    let mut shutdown_timeout: Option<String> = None;
+    let mut on_shutdown_timeout: Option<String> = None;
+    let mut processed_marker: Option<String> = None;
+    let mut trusted_network_strings: Vec<String> = Vec::new();
+    let mut rpc_query_param_strings: Vec<String> = Vec::new();
     let mut action_string: Option<String> = None;
     let mut period_strings: Vec<String> = Vec::new();
+    let mut maintenance_period_strings: Vec<String> = Vec::new();
+    let mut period_files: Vec<String> = Vec::new();
+    let mut date_override_strings: Vec<String> = Vec::new();
+    let mut schedule_scope: Option<String> = None;
+    let mut managed_status_strings: Vec<String> = Vec::new();
+    let mut path_remap_strings: Vec<String> = Vec::new();
+    let mut priority_rule_strings: Vec<String> = Vec::new();
     let mut copy_to_string: Option<String> = None;
+    let mut copy_to_label_strings: Vec<String> = Vec::new();
+    let mut copy_user: Option<String> = None;
+    let mut copy_group: Option<String> = None;
+    let mut copy_buffer_size: Option<String> = None;
+    let mut copy_bandwidth: Option<String> = None;
+    let mut ignored_extensions: Vec<String> = Vec::new();
+    let mut ignore_default_extensions = false;
+    let mut missing_file_policy: Option<String> = None;
+    let mut not_found_retry_delay: Option<String> = None;
     let mut move_to_string: Option<String> = None;
+    let mut move_conflict_policy: Option<String> = None;
+    let mut on_low_space: Option<String> = None;
+    let mut abandoned_files_check_period: Option<String> = None;
+    let mut scrub_interval: Option<String> = None;
+    let mut seed_location_string: Option<String> = None;
+    let mut deletable_dir_strings: Vec<String> = Vec::new();
+    let mut min_consume_size: Option<String> = None;
+    let mut max_consume_size: Option<String> = None;
+    let mut consume_delay: Option<String> = None;
     let mut seed_time_limit: Option<String> = None;
+    let mut max_age: Option<String> = None;
+    let mut auto_reannounce = false;
+    let mut reannounce_interval: Option<String> = None;
 
     let mut email_from: Option<String> = None;
+    let mut email_subject_prefix: Option<String> = None;
+    let mut smtp_retries: Option<usize> = None;
+    let mut smtp_timeout: Option<String> = None;
     let mut email_errors_to: Option<String> = None;
     let mut email_notifications_to: Option<String> = None;
     let mut torrent_downloaded_email_template: Option<String> = None;
+    let mut torrent_failed_email_template: Option<String> = None;
+    let mut mqtt_broker: Option<String> = None;
+    let mut mqtt_topic: Option<String> = None;
+    let mut statsd_address: Option<String> = None;
+    let mut statsd_prefix: Option<String> = None;
 
     let action_map: HashMap<String, Action> =
         [Action::StartOrPause, Action::PauseOrStart]
         .iter().map(|&action| (action.to_string(), action)).collect();
 
     {
-        use argparse::{ArgumentParser, Store, StoreOption, IncrBy, Collect};
+        use argparse::{ArgumentParser, Store, StoreOption, StoreTrue, IncrBy, Collect};
 
-        let config_help = format!("configuration file path ({})", default_config_path);
+        let config_help = format!(
+            "configuration file path ({} environment variable or {} by default), may be specified multiple times \
+             to manage several Transmission daemons", CONFIG_ENV_VAR, default_config_path);
+        let processed_marker_help = format!(
+            "a way to mark processed torrents ({}): a special download limit value (default), a hidden file in \
+             torrent's download directory or a torrent label (Transmission 3.00+). Torrents marked with download \
+             limit are migrated to the selected marker automatically",
+            ProcessedMarker::ALL.iter().join("|"));
+        let rpc_address_family_help = format!(
+            "IP protocol version to connect to Transmission RPC with when its host resolves to both IPv4 and IPv6 \
+             addresses ({}): any of them (default), IPv4 only or IPv6 only",
+            AddressFamily::ALL.iter().join("|"));
+        let on_missing_file_help = format!(
+            "what to do when some of torrent files are missing on the disk during copying ({}): fail the \
+             consuming (default) or copy the existing files and consider the torrent consumed",
+            MissingFilePolicy::ALL.iter().join("|"));
+        let move_conflict_help = format!(
+            "what to do when a moved file already exists ({}): move it under DUP_N. prefixed name (default), \
+             replace the existing file or leave the file in the copy directory",
+            MoveConflictPolicy::ALL.iter().join("|"));
+        let on_shutdown_timeout_help = format!(
+            "what to do when the torrent being consumed hasn't been copied within the shutdown timeout ({}): \
+             exit leaving the partially copied files (default) or wait until the copying finishes",
+            ShutdownTimeoutAction::ALL.iter().join("|"));
+        let smtp_retries_help = format!(
+            "number of retries when an email can't be sent due to a temporary SMTP error ({} by default)",
+            email::DEFAULT_RETRIES);
+        let smtp_timeout_help = format!(
+            "timeout (in $number{{s|m|h|d|w}}[...] format) of SMTP operations ({}s by default)",
+            email::DEFAULT_TIMEOUT.as_secs());
+        let ignore_default_ext_help = format!(
+            "don't copy the files with the commonly useless extensions ({})", DEFAULT_IGNORED_EXTENSIONS.join(", "));
+        let priority_label_help = format!(
+            "set the specified bandwidth priority ({}) for the torrents being downloaded which have the \
+             specified label once (the first matching rule wins, * label matches any torrent)",
+            BandwidthPriority::ALL.iter().join("|"));
+        let schedule_scope_help = format!(
+            "torrents to pause/resume according to the schedule ({}): all (default), only the torrents which are \
+             being downloaded or only the downloaded ones",
+            ScheduleScope::ALL.iter().join("|"));
+        let on_low_space_help = format!(
+            "what to do when free space drops below the threshold ({}): delete the downloaded torrents (default), \
+             pause the torrents which are being downloaded or only report the problem",
+            LowSpaceAction::ALL.iter().join("|"));
+        let managed_status_help = format!(
+            "manage only torrents in the specified status ({}), all torrents are managed by default",
+            TorrentStatus::ALL.iter().join("|"));
 
         let mut parser = ArgumentParser::new();
         parser.set_description("Transmission controller daemon.");
 
-        parser.refer(&mut args.config).metavar("PATH").add_option(
-            &["--config"], Store, &config_help);
+        parser.refer(&mut args.configs).metavar("PATH").add_option(
+            &["--config"], Collect, &config_help);
         parser.refer(&mut action_string).metavar(&action_map.keys().join("|")).add_option(
             &["-a", "--action"], StoreOption, "action that will be taken according to the specified time periods");
         parser.refer(&mut period_strings).metavar("PERIOD").add_option(
             &["-p", "--period"], Collect, "time period in D[-D]/HH:MM-HH:MM format to start/stop the torrents at");
+        parser.refer(&mut period_files).metavar("PATH").add_option(
+            &["--period-file"], Collect,
+            "file with time periods (one per line, empty lines and lines starting with # are ignored)");
+        parser.refer(&mut maintenance_period_strings).metavar("PERIOD").add_option(
+            &["--maintenance-period"], Collect,
+            "time period in D[-D]/HH:MM-HH:MM format during which the controller takes no actions at all \
+             (e.g. during backups)");
+        parser.refer(&mut args.active_only_in_periods).add_option(
+            &["--active-only-in-periods"], StoreTrue,
+            "outside of the time periods take no actions (including consuming and cleanup) except pausing/resuming \
+             the torrents according to the schedule");
+        parser.refer(&mut date_override_strings).metavar("DATE_OVERRIDE").add_option(
+            &["--date-override"], Collect,
+            "state of the torrents in YYYY-MM-DD:{active|paused} format for the specified date which takes \
+             precedence over the time periods (e.g. for holidays)");
+        parser.refer(&mut schedule_scope).metavar("SCOPE").add_option(
+            &["--schedule-scope"], StoreOption, &schedule_scope_help);
+        parser.refer(&mut args.list_periods_now).add_option(
+            &["--list-periods-now"], StoreTrue,
+            "print whether the current moment is inside the time periods and the resulting state, then exit");
+        parser.refer(&mut args.list_processed).add_option(
+            &["--list-processed"], StoreTrue, "print the torrents marked as processed, then exit");
+        parser.refer(&mut args.reset_processed).add_option(
+            &["--reset-processed"], StoreTrue,
+            "clear the processed marker on all torrents (so they will be consumed again), then exit");
+        parser.refer(&mut args.explain_cleanup).add_option(
+            &["--explain-cleanup"], StoreTrue,
+            "print the torrents which would be removed to free space on the disk (without removing them), then exit");
+        parser.refer(&mut args.test_email).add_option(
+            &["--test-email"], StoreTrue, "send a test email via each of the configured mailers, then exit");
+        parser.refer(&mut args.yes).add_option(
+            &["--yes"], StoreTrue, "don't ask for confirmation of --reset-processed");
+        parser.refer(&mut managed_status_strings).metavar("STATUS").add_option(
+            &["--manage-status"], Collect, &managed_status_help);
+        parser.refer(&mut args.tracker_hosts).metavar("HOST").add_option(
+            &["--tracker-host"], Collect,
+            "manage only torrents which have a tracker on the specified host (or its subdomain)");
+        parser.refer(&mut args.managed_label).metavar("LABEL").add_option(
+            &["--managed-label"], StoreOption,
+            "manage only torrents with the specified label (requires Transmission 3.00+)");
+        parser.refer(&mut priority_rule_strings).metavar("PRIORITY=LABEL").add_option(
+            &["--priority-label"], Collect, &priority_label_help);
+        parser.refer(&mut path_remap_strings).metavar("PREFIX=LOCAL_PREFIX").add_option(
+            &["--remap"], Collect,
+            "map paths of Transmission host to the local ones (when Transmission is running on another host)");
         parser.refer(&mut copy_to_string).metavar("PATH").add_option(
             &["-c", "--copy-to"], StoreOption, "directory to copy the torrents to");
+        parser.refer(&mut copy_to_label_strings).metavar("LABEL=PATH").add_option(
+            &["--copy-to-label"], Collect,
+            "directory to copy the torrents with the specified first label to instead of --copy-to directory \
+             (requires Transmission 3.00+)");
+        parser.refer(&mut copy_user).metavar("USER").add_option(
+            &["--copy-uid"], StoreOption, "user (name or ID) to set as owner of the copied files");
+        parser.refer(&mut copy_group).metavar("GROUP").add_option(
+            &["--copy-gid"], StoreOption, "group (name or ID) to set as owner of the copied files");
+        parser.refer(&mut copy_buffer_size).metavar("SIZE").add_option(
+            &["--copy-buffer-size"], StoreOption,
+            "buffer size (in $number[K|M|G|T] format) which is used when files can't be copied by the kernel \
+             without passing the data through user space (8K by default)");
+        parser.refer(&mut copy_bandwidth).metavar("SIZE").add_option(
+            &["--copy-bandwidth"], StoreOption,
+            "maximum copying speed (in $number[K|M|G|T] bytes per second format) to not degrade seeding performance");
+        parser.refer(&mut args.copy_all_files).add_option(
+            &["--copy-all-files"], StoreTrue,
+            "copy all torrent files including the ones which are not selected for downloading in Transmission \
+             (if they have been fully downloaded)");
+        parser.refer(&mut ignored_extensions).metavar("EXTENSION").add_option(
+            &["--ignore-ext"], Collect, "extension of the files (case-insensitive) to not copy");
+        parser.refer(&mut ignore_default_extensions).add_option(
+            &["--ignore-default-ext"], StoreTrue, &ignore_default_ext_help);
+        parser.refer(&mut missing_file_policy).metavar("POLICY").add_option(
+            &["--on-missing-file"], StoreOption, &on_missing_file_help);
         parser.refer(&mut move_to_string).metavar("PATH").add_option(
             &["-m", "--move-to"], StoreOption, "directory to move the copied torrents to");
+        parser.refer(&mut move_conflict_policy).metavar("POLICY").add_option(
+            &["--move-conflict"], StoreOption, &move_conflict_help);
+        parser.refer(&mut abandoned_files_check_period).metavar("DURATION").add_option(
+            &["--abandoned-files-check-period"], StoreOption,
+            "period (in $number{s|m|h|d|w}[...] format) of checking copy directory for abandoned files \
+             (by default it's checked only on startup)");
+        parser.refer(&mut scrub_interval).metavar("DURATION").add_option(
+            &["--scrub-interval"], StoreOption,
+            "period (in $number{s|m|h|d|w}[...] format) of scrubbing copy and move directories: report the files \
+             in the copy directories not associated with any torrent and empty directories");
+        parser.refer(&mut args.scrub_remove_empty_dirs).add_option(
+            &["--scrub-remove-empty-dirs"], StoreTrue, "remove the empty directories found during scrubbing");
+        parser.refer(&mut args.verify_before_consume).add_option(
+            &["--verify-before-consume"], StoreTrue,
+            "ask Transmission to verify downloaded torrents and consume them only if verification succeeds");
+        parser.refer(&mut args.not_found_retries).metavar("RETRIES").add_option(
+            &["--not-found-retries"], Store,
+            "number of retries when the torrent to consume isn't found, which may happen right after Transmission \
+             restart (2 by default)");
+        parser.refer(&mut not_found_retry_delay).metavar("DURATION").add_option(
+            &["--not-found-retry-delay"], StoreOption,
+            "delay (in $number{s|m|h|d|w}[...] format) between retries when the torrent to consume isn't found \
+             (5s by default)");
+        parser.refer(&mut processed_marker).metavar("MARKER").add_option(
+            &["--processed-marker"], StoreOption, &processed_marker_help);
+        parser.refer(&mut min_consume_size).metavar("SIZE").add_option(
+            &["--min-consume-size"], StoreOption,
+            "minimum size (in $number[K|M|G|T] format) of selected files of the torrent to consume it, \
+             smaller torrents are marked as processed without copying (ignored files are taken into account)");
+        parser.refer(&mut max_consume_size).metavar("SIZE").add_option(
+            &["--max-consume-size"], StoreOption,
+            "maximum size (in $number[K|M|G|T] format) of selected files of the torrent to consume it, \
+             larger torrents are marked as processed without copying (ignored files are taken into account)");
+        parser.refer(&mut consume_delay).metavar("DURATION").add_option(
+            &["--consume-delay"], StoreOption,
+            "time (in $number{s|m|h|d|w}[...] format) during which a torrent must stay downloaded before consuming it \
+             (the countdown starts over if it becomes not downloaded, e.g. when its file selection is changed)");
+        parser.refer(&mut args.reconsume_if_missing).add_option(
+            &["--reconsume-if-missing"], StoreTrue,
+            "periodically check that the data of the torrents copied since the controller start still exists in \
+             the copy/move directory and consume them again if it has been deleted");
+        parser.refer(&mut seed_location_string).metavar("PATH").add_option(
+            &["--seed-location"], StoreOption,
+            "directory to move the processed torrents to by Transmission to continue seeding them from it");
+        parser.refer(&mut deletable_dir_strings).metavar("PATH").add_option(
+            &["--deletable-dir"], Collect,
+            "allow the controller to delete only torrents which are downloaded to the specified directory");
         parser.refer(&mut seed_time_limit).metavar("DURATION").add_option(
             &["-l", "--seed-time-limit"], StoreOption,
-            "seeding time (in $number{m|h|d} format) after which downloaded torrents will be deleted");
+            "seeding time (in $number{s|m|h|d|w}[...] format, e.g. 1d12h) after which downloaded torrents will be deleted");
+        parser.refer(&mut max_age).metavar("DURATION").add_option(
+            &["--max-age"], StoreOption,
+            "time (in $number{s|m|h|d|w}[...] format) since adding after which any torrent will be deleted \
+             regardless of its state (works independently of --seed-time-limit: whichever comes first)");
+        parser.refer(&mut auto_reannounce).add_option(
+            &["--auto-reannounce-on-stall"], StoreTrue,
+            "reannounce the torrents which have lost connection with their trackers (have a tracker error)");
+        parser.refer(&mut reannounce_interval).metavar("DURATION").add_option(
+            &["--reannounce-interval"], StoreOption,
+            "minimum time (in $number{s|m|h|d|w}[...] format) between reannounces of a torrent (1h)");
         parser.refer(&mut args.upload_ratio_limit).metavar("RATIO").add_option(
             &["-r", "--upload-ratio-limit"], StoreOption,
             "upload ratio after which downloaded torrents will be deleted");
+        parser.refer(&mut args.protect_private).add_option(
+            &["--protect-private"], StoreTrue,
+            "don't remove private torrents (to free space on the disk, by seed time, ratio or age limits, etc.)");
+        parser.refer(&mut args.min_other_seeders).metavar("NUMBER").add_option(
+            &["--min-other-seeders"], StoreOption,
+            "don't delete downloaded torrents if trackers report less than the specified number of other seeders");
+        parser.refer(&mut args.remove_missing_data).add_option(
+            &["--remove-missing-data"], StoreTrue,
+            "remove torrents which data has been deleted from the disk (Transmission reports 'No data found' error)");
+        parser.refer(&mut args.seed_ratio_limit).metavar("RATIO").add_option(
+            &["--set-seed-ratio"], StoreOption,
+            "set seed ratio limit of consumed torrents in Transmission to let it stop seeding them at this ratio");
         parser.refer(&mut args.free_space_threshold).metavar("THRESHOLD").add_option(
             &["-s", "--free-space-threshold"], StoreOption,
-            "free space threshold (%) after which downloaded torrents will be deleted until it won't be satisfied");
+            "free space threshold (%): when free space drops below it, downloaded torrents are deleted until it \
+             reaches the threshold (plus headroom)");
+        parser.refer(&mut on_low_space).metavar("ACTION").add_option(
+            &["--on-low-space"], StoreOption, &on_low_space_help);
+        parser.refer(&mut args.free_space_headroom).metavar("HEADROOM").add_option(
+            &["--free-space-headroom"], Store,
+            "additional free space (%) above the free space threshold to free when cleanup is triggered (0)");
+        parser.refer(&mut args.free_space_warn_threshold).metavar("THRESHOLD").add_option(
+            &["--free-space-warn-threshold"], StoreOption,
+            "free space threshold (%) below which a warning is reported (without deleting anything)");
+        parser.refer(&mut args.free_space_check_retries).metavar("NUMBER").add_option(
+            &["--free-space-check-retries"], Store,
+            "number of retries of free space check on failure before skipping the cleanup (2)");
+        parser.refer(&mut args.max_removals_per_cycle).metavar("NUMBER").add_option(
+            &["--max-removals-per-cycle"], StoreOption,
+            "maximum number of torrents to remove during one cleanup cycle to free space on the disk");
+        parser.refer(&mut args.observe_cycles).metavar("N").add_option(
+            &["--observe-cycles"], Store,
+            "only log the decisions without making any changes during the first N control cycles \
+             and switch to live operation after that");
+        parser.refer(&mut args.warmup_cycles).metavar("N").add_option(
+            &["--warmup-cycles"], Store,
+            "number of consecutive successful control cycles after startup during which torrents aren't \
+             removed or consumed to not act on incomplete data while Transmission is starting (disabled by default)");
         parser.refer(&mut email_from).metavar("ADDRESS").add_option(
             &["-f", "--email-from"], StoreOption, "address to send mail from");
+        parser.refer(&mut email_subject_prefix).metavar("PREFIX").add_option(
+            &["--email-subject-prefix"], StoreOption, "prefix for subject of all sent emails (e.g. [seedbox1])");
+        parser.refer(&mut smtp_retries).metavar("RETRIES").add_option(
+            &["--smtp-retries"], StoreOption, &smtp_retries_help);
+        parser.refer(&mut smtp_timeout).metavar("DURATION").add_option(
+            &["--smtp-timeout"], StoreOption, &smtp_timeout_help);
         parser.refer(&mut email_errors_to).metavar("ADDRESS").add_option(
             &["-e", "--email-errors"], StoreOption, "address to send errors to");
+        parser.refer(&mut args.email_state_file).metavar("PATH").add_option(
+            &["--email-state-file"], StoreOption,
+            "file to save the time of the last error email to, to limit email sending rate across restarts");
+        parser.refer(&mut startup_error_grace).metavar("DURATION").add_option(
+            &["--startup-error-grace"], StoreOption,
+            "time (in $number{s|m|h|d|w}[...] format) after startup during which control cycle errors (e.g. \
+             unavailable Transmission RPC) are logged as warnings and aren't sent via email (1m by default)");
+        parser.refer(&mut shutdown_timeout).metavar("DURATION").add_option(
+            &["--shutdown-timeout"], StoreOption,
+            "time (in $number{s|m|h|d|w}[...] format) to wait for the torrent being consumed on shutdown \
+             (waits without any limit by default)");
+        parser.refer(&mut on_shutdown_timeout).metavar("ACTION").add_option(
+            &["--shutdown-on-timeout"], StoreOption, &on_shutdown_timeout_help);
         parser.refer(&mut email_notifications_to).metavar("ADDRESS").add_option(
             &["-n", "--email-notifications"], StoreOption, "address to send notifications to");
         parser.refer(&mut torrent_downloaded_email_template).metavar("PATH").add_option(
-            &["-t", "--torrent-downloaded-email-template"], StoreOption, "template of 'torrent downloaded' notification");
+            &["-t", "--torrent-downloaded-email-template"], StoreOption, "template of 'torrent downloaded' notification ({{name}}, {{dest_paths}} and {{files}} variables are available; has precedence over the inline template from the config)");
+        parser.refer(&mut torrent_failed_email_template).metavar("PATH").add_option(
+            &["--torrent-failed-email-template"], StoreOption,
+            "template of 'torrent failed' notification which is sent when torrent consuming fails or is cancelled \
+             ({{hash}}, {{name}} and {{error}} variables are available)");
+        parser.refer(&mut mqtt_broker).metavar("HOST[:PORT]").add_option(
+            &["--mqtt-broker"], StoreOption,
+            "MQTT broker to publish events (torrent downloaded/removed, low free space) to");
+        parser.refer(&mut mqtt_topic).metavar("TOPIC").add_option(
+            &["--mqtt-topic"], StoreOption, "MQTT topic to publish the events to (transmission-controller)");
+        parser.refer(&mut statsd_address).metavar("HOST[:PORT]").add_option(
+            &["--statsd-address"], StoreOption,
+            "statsd daemon to send free space and torrent metrics to after each control cycle");
+        parser.refer(&mut statsd_prefix).metavar("PREFIX").add_option(
+            &["--statsd-prefix"], StoreOption, "prefix of statsd metric names (transmission_controller)");
+        parser.refer(&mut rpc_connect_timeout).metavar("DURATION").add_option(
+            &["--rpc-connect-timeout"], StoreOption,
+            "timeout (in $number{s|m|h|d|w}[...] format) for connecting to Transmission RPC (10s)");
+        parser.refer(&mut rpc_timeout).metavar("DURATION").add_option(
+            &["--rpc-timeout"], StoreOption,
+            "timeout (in $number{s|m|h|d|w}[...] format) for the whole Transmission RPC request (10s)");
+        parser.refer(&mut args.rpc_max_response_size).metavar("BYTES").add_option(
+            &["--rpc-max-response-size"], StoreOption, "maximum allowed size of Transmission RPC response");
+        parser.refer(&mut args.rpc_socks_proxy).metavar("URL").add_option(
+            &["--rpc-socks-proxy"], StoreOption,
+            "SOCKS5 proxy (socks5://HOST:PORT or socks5h://HOST:PORT) to connect to Transmission RPC through");
+        parser.refer(&mut rpc_address_family).metavar("FAMILY").add_option(
+            &["--rpc-address-family"], StoreOption, &rpc_address_family_help);
+        parser.refer(&mut rpc_query_param_strings).metavar("NAME=VALUE").add_option(
+            &["--rpc-query-param"], Collect,
+            "query string parameter to add to Transmission RPC URL (e.g. an auth token required by a reverse proxy)");
+        parser.refer(&mut trusted_network_strings).metavar("CIDR").add_option(
+            &["--trusted-network"], Collect,
+            "allow removing torrents only if Transmission RPC host resolves to the specified network \
+             (e.g. 192.168.0.0/16)");
         parser.refer(&mut args.debug_level).add_option(
             &["-d", "--debug"], IncrBy(1usize), "debug mode");
+        parser.refer(&mut args.quiet_level).add_option(
+            &["-q", "--quiet"], IncrBy(1usize), "quiet mode: log only warnings (-q) or only errors (-qq)");
+        parser.refer(&mut log_target_strings).metavar("TARGET=LEVEL").add_option(
+            &["--log-target"], Collect,
+            "additionally log messages of the specified target (e.g. reqwest=debug) with the specified level \
+             (error|warn|info|debug|trace)");
+        parser.refer(&mut args.log_hashes).add_option(
+            &["--log-hashes"], StoreTrue, "add a short hash prefix to torrent names in log messages");
 
         parser.parse_args_or_exit();
     }
 
+    for log_target in log_target_strings {
+        let (target, level) = match log_target.split_once('=') {
+            Some((target, level)) if !target.is_empty() => (target, level),
+            _ => return Err!("Invalid log target specification: {}", log_target),
+        };
+
+        let level = level.parse().map_err(|_| format!("Invalid log level in '{}'", log_target))?;
+        args.log_targets.push((s!(target), level));
+    }
+
+    for path in period_files {
+        period_strings.extend(read_period_file(&path)?);
+    }
+
     if let Some(action_string) = action_string {
         match action_map.get(&action_string) {
             Some(action) => {
@@ -125,10 +606,76 @@ pub fn parse() -> GenericResult<Arguments> {
 
     args.action_periods = util::time::parse_periods(&period_strings)?;
 
+    if args.active_only_in_periods && period_strings.is_empty() {
+        return Err!("--active-only-in-periods must be specified with time periods");
+    }
+
+    if !maintenance_period_strings.is_empty() {
+        args.maintenance_periods = Some(util::time::parse_periods(&maintenance_period_strings)?);
+    }
+
+    for date_override in &date_override_strings {
+        if args.action.is_none() {
+            return Err!("Date overrides must be specified with action");
+        }
+
+        let (date, active) = util::time::parse_date_override(date_override)?;
+        if args.date_overrides.insert(date, active).is_some() {
+            return Err!("Duplicated date override for {}", date);
+        }
+    }
+
+    if let Some(ref scope) = schedule_scope {
+        if args.action.is_none() {
+            return Err!("--schedule-scope must be specified with action");
+        }
+        args.schedule_scope = scope.parse()?;
+    }
+
+    if args.list_processed && args.reset_processed {
+        return Err!("--list-processed and --reset-processed can't be used together");
+    }
+
+    if args.yes && !args.reset_processed {
+        return Err!("--yes must be specified only with --reset-processed");
+    }
+
+    if args.list_periods_now && args.action.is_none() {
+        return Err!("--list-periods-now must be specified with action");
+    }
+
+    if !managed_status_strings.is_empty() {
+        args.managed_statuses = Some(managed_status_strings.iter()
+            .map(|status| status.parse())
+            .collect::<GenericResult<_>>()?);
+    }
+
+    for host in &mut args.tracker_hosts {
+        *host = host.trim().trim_end_matches('.').to_lowercase();
+        if host.is_empty() {
+            return Err!("Invalid tracker host: it mustn't be empty");
+        }
+    }
+
+    if let Some(ref label) = args.managed_label {
+        if label.trim().is_empty() {
+            return Err!("Invalid managed label: it mustn't be empty");
+        }
+    }
+
+    for rule in priority_rule_strings {
+        let (priority, label) = match rule.split_once('=') {
+            Some((priority, label)) if !label.trim().is_empty() => (priority, label),
+            _ => return Err!("Invalid priority rule specification: {}", rule),
+        };
+        args.priority_rules.push((s!(label), priority.parse()?));
+    }
+
     {
         let paths: Vec<(&mut Option<String>, &mut Option<PathBuf>)> = vec![
             (&mut copy_to_string, &mut args.copy_to),
             (&mut move_to_string, &mut args.move_to),
+            (&mut seed_location_string, &mut args.seed_location),
         ];
 
         for (path_string, path) in paths {
@@ -147,20 +694,258 @@ pub fn parse() -> GenericResult<Arguments> {
         }
     }
 
+    if args.configs.is_empty() {
+        let path = match env::var_os(CONFIG_ENV_VAR) {
+            Some(path) if path.is_empty() => return Err!("{} environment variable is empty", CONFIG_ENV_VAR),
+            Some(path) => PathBuf::from(path),
+            None => PathBuf::from(shellexpand::tilde(default_config_path).to_string()),
+        };
+        args.configs.push(path);
+    }
+
+    if args.debug_level != 0 && args.quiet_level != 0 {
+        return Err!("--debug and --quiet can't be used together");
+    }
+
+    // Options which depend on --copy-to and --move-to are checked against the resolved per-daemon
+    // directories when the controllers are created
+
+    for spec in copy_to_label_strings {
+        let (label, path) = match spec.split_once('=') {
+            Some((label, path)) if !label.trim().is_empty() => (label, PathBuf::from(path)),
+            _ => return Err!("Invalid label copy destination specification: {}", spec),
+        };
+
+        if path.is_relative() {
+            return Err!("You must specify only absolute paths in command line arguments");
+        }
+        util::fs::check_directory(&path)?;
+
+        if args.copy_to_labels.iter().any(|(other, _)| other == label) {
+            return Err!("Copy destination for {:?} label is specified multiple times", label);
+        }
+        args.copy_to_labels.push((s!(label), path));
+    }
+
+    if copy_user.is_some() || copy_group.is_some() {
+        let uid = copy_user.as_deref().map(util::fs::get_user_id).transpose()?;
+        let gid = copy_group.as_deref().map(util::fs::get_group_id).transpose()?;
+        args.copy_owner = Some(FileOwner::new(uid, gid));
+    }
+
+    if let Some(ref duration) = not_found_retry_delay {
+        args.not_found_retry_delay = util::time::parse_duration(duration)?;
+    }
+
+    if let Some(ref size) = copy_buffer_size {
+        let size = util::size::parse_size(size)?;
+        args.copy_options.buffer_size = match usize::try_from(size) {
+            Ok(size) if size != 0 && size <= 1024 * 1024 * 1024 => size,
+            _ => return Err!("Invalid copy buffer size: {}", size),
+        };
+    }
+
+    if ignore_default_extensions {
+        ignored_extensions.extend(DEFAULT_IGNORED_EXTENSIONS.iter().map(|&extension| s!(extension)));
+    }
+
+    for extension in ignored_extensions {
+        let extension = extension.trim_start_matches('.').to_lowercase();
+        if extension.is_empty() {
+            return Err!("Invalid file extension: it mustn't be empty");
+        }
+
+        if !args.ignored_extensions.contains(&extension) {
+            args.ignored_extensions.push(extension);
+        }
+    }
+
+    if let Some(ref policy) = missing_file_policy {
+        args.missing_file_policy = policy.parse()?;
+    }
+
+    if let Some(ref bandwidth) = copy_bandwidth {
+        match util::size::parse_size(bandwidth)? {
+            0 => return Err!("Invalid copy bandwidth: {}", bandwidth),
+            bandwidth => args.copy_options.bandwidth = Some(bandwidth),
+        }
+    }
+
+    if let Some(ref action) = on_low_space {
+        if args.free_space_threshold.is_none() {
+            return Err!("--on-low-space must be specified only with --free-space-threshold");
+        }
+        args.on_low_space = action.parse()?;
+    }
+
+    if let Some(ref policy) = move_conflict_policy {
+        args.move_conflict_policy = policy.parse()?;
+    }
+
+    if let Some(ref period) = abandoned_files_check_period {
+        args.abandoned_files_check_period = Some(util::time::parse_duration(period)?);
+    }
+
+    if let Some(ref interval) = scrub_interval {
+        args.scrub_interval = Some(util::time::parse_duration(interval)?);
+    }
+
+    if args.scrub_remove_empty_dirs && args.scrub_interval.is_none() {
+        return Err!("--scrub-remove-empty-dirs must be specified only with --scrub-interval");
+    }
+
+    for remap in path_remap_strings {
+        let (prefix, local_prefix) = match remap.split_once('=') {
+            Some((prefix, local_prefix)) => (PathBuf::from(prefix), PathBuf::from(local_prefix)),
+            None => return Err!("Invalid path remapping specification: {}", remap),
+        };
+
+        if prefix.is_relative() || local_prefix.is_relative() {
+            return Err!("You must specify only absolute paths in command line arguments");
+        }
+
+        args.path_remaps.push((prefix, local_prefix));
+    }
+
+    for path in deletable_dir_strings {
+        let path = PathBuf::from(path);
+        if path.is_relative() {
+            return Err!("You must specify only absolute paths in command line arguments");
+        }
+        args.deletable_dirs.push(path);
+    }
+
+    if let Some(ref family) = rpc_address_family {
+        args.rpc_address_family = family.parse()?;
+    }
+
+    if let Some(ref marker) = processed_marker {
+        args.processed_marker = marker.parse()?;
+    }
+
+    if let Some(ref size) = min_consume_size {
+        args.min_consume_size = Some(util::size::parse_size(size)?);
+    }
+
+    if let Some(ref size) = max_consume_size {
+        args.max_consume_size = Some(util::size::parse_size(size)?);
+    }
+
+    if let Some(ref delay) = consume_delay {
+        args.consume_delay = Some(util::time::parse_duration(delay)?);
+    }
+
+    if let (Some(min_size), Some(max_size)) = (args.min_consume_size, args.max_consume_size) {
+        if min_size > max_size {
+            return Err!("Minimum consume size is greater than maximum consume size");
+        }
+    }
+
     if let Some(ref duration) = seed_time_limit {
         args.seed_time_limit = Some(util::time::parse_duration(duration)?);
     }
 
+    if let Some(ref duration) = max_age {
+        args.max_age = Some(util::time::parse_duration(duration)?);
+    }
+
+    if auto_reannounce {
+        args.reannounce_interval = Some(match reannounce_interval {
+            Some(ref interval) => util::time::parse_duration(interval)?,
+            None => 60 * 60,
+        });
+    } else if reannounce_interval.is_some() {
+        return Err!("--reannounce-interval must be specified only with --auto-reannounce-on-stall");
+    }
+
     if let Some(ratio) = args.upload_ratio_limit {
         if ratio <= 0.0 {
             return Err!("Invalid seed ratio limit: {}", ratio);
         }
     }
 
+    if let Some(ratio) = args.seed_ratio_limit {
+        if ratio <= 0.0 {
+            return Err!("Invalid seed ratio: {}", ratio);
+        }
+    }
+
     if let Some(ref threshold) = args.free_space_threshold {
         if *threshold > 100 {
             return Err!("Invalid free space threshold value: {}", threshold);
         }
+
+        if *threshold as u16 + args.free_space_headroom as u16 > 100 {
+            return Err!("Invalid free space headroom value: {}", args.free_space_headroom);
+        }
+    } else if args.free_space_headroom != 0 {
+        return Err!("--free-space-headroom must be specified only with --free-space-threshold");
+    }
+
+    if args.explain_cleanup && args.free_space_threshold.is_none() {
+        return Err!("--explain-cleanup must be specified with --free-space-threshold");
+    }
+
+    if let Some(warn_threshold) = args.free_space_warn_threshold {
+        if warn_threshold > 100 {
+            return Err!("Invalid free space warning threshold value: {}", warn_threshold);
+        }
+
+        if let Some(threshold) = args.free_space_threshold {
+            if warn_threshold <= threshold {
+                return Err!("Free space warning threshold must be greater than free space threshold");
+            }
+        }
+    }
+
+    if args.max_removals_per_cycle == Some(0) {
+        return Err!("Invalid maximum number of removals per cycle: 0");
+    }
+
+    if let Some(ref duration) = rpc_connect_timeout {
+        args.rpc_connect_timeout = Some(util::time::parse_duration(duration)?);
+    }
+
+    if let Some(ref duration) = rpc_timeout {
+        args.rpc_timeout = Some(util::time::parse_duration(duration)?);
+    }
+
+    if let Some(ref duration) = startup_error_grace {
+        args.startup_error_grace = util::time::parse_duration(duration)?;
+    }
+
+    if let Some(ref duration) = shutdown_timeout {
+        args.shutdown_timeout = Some(util::time::parse_duration(duration)?);
+    }
+
+    if let Some(ref action) = on_shutdown_timeout {
+        if args.shutdown_timeout.is_none() {
+            return Err!("--shutdown-on-timeout must be specified only with --shutdown-timeout");
+        }
+        args.on_shutdown_timeout = action.parse()?;
+    }
+
+    if let Some(size) = args.rpc_max_response_size {
+        if size == 0 {
+            return Err!("Invalid maximum RPC response size: {}", size);
+        }
+    }
+
+    for param in rpc_query_param_strings {
+        match param.split_once('=') {
+            Some((name, value)) if !name.is_empty() => args.rpc_query_params.push((s!(name), s!(value))),
+            _ => return Err!("Invalid RPC query parameter specification: {}", param),
+        };
+    }
+
+    for network in &trusted_network_strings {
+        args.trusted_networks.push(util::net::parse_network(network)?);
+    }
+
+    if cfg!(not(feature = "email")) && (
+        email_from.is_some() || email_errors_to.is_some() || email_notifications_to.is_some()
+    ) {
+        return Err!("Email options are unavailable: the program has been built without email support");
     }
 
     if let Some(ref to) = email_errors_to {
@@ -171,6 +956,10 @@ pub fn parse() -> GenericResult<Arguments> {
         }
     }
 
+    if args.email_state_file.is_some() && args.error_mailer.is_none() {
+        return Err!("--email-state-file must be specified only with --email-errors");
+    }
+
     if let Some(to) = email_notifications_to {
         args.notifications_mailer = match email_from {
             Some(ref from) => Some(Mailer::new(from, &to)?),
@@ -178,10 +967,66 @@ pub fn parse() -> GenericResult<Arguments> {
         };
     }
 
+    let smtp_timeout = smtp_timeout.as_deref().map(util::time::parse_duration).transpose()?;
+
+    if (smtp_retries.is_some() || smtp_timeout.is_some()) &&
+        args.error_mailer.is_none() && args.notifications_mailer.is_none() {
+        return Err!("--smtp-retries and --smtp-timeout must be specified only with --email-errors or --email-notifications");
+    }
+
+    for mailer in [args.error_mailer.as_mut(), args.notifications_mailer.as_mut()].into_iter().flatten() {
+        if let Some(ref prefix) = email_subject_prefix {
+            mailer.set_subject_prefix(prefix);
+        }
+        if let Some(retries) = smtp_retries {
+            mailer.set_retries(retries);
+        }
+        if let Some(timeout) = smtp_timeout {
+            mailer.set_timeout(std::time::Duration::from_secs(timeout as u64));
+        }
+    }
+
+    if args.test_email && args.error_mailer.is_none() && args.notifications_mailer.is_none() {
+        return Err!("--test-email must be specified with --email-errors or --email-notifications");
+    }
+
     if let Some(path) = torrent_downloaded_email_template {
-        args.torrent_downloaded_email_template = EmailTemplate::new_from_file(&path)
+        args.torrent_downloaded_email_template = Some(EmailTemplate::new_from_file(&path)
+            .map_err(|e| format!("Error while reading email template: {}", e))?);
+    }
+
+    if let Some(path) = torrent_failed_email_template {
+        args.torrent_failed_email_template = EmailTemplate::new_from_file(&path)
             .map_err(|e| format!("Error while reading email template: {}", e))?;
     }
 
+    if let Some(ref broker) = mqtt_broker {
+        let topic = mqtt_topic.as_deref().unwrap_or("transmission-controller");
+        args.mqtt_publisher = Some(MqttPublisher::new(broker, topic)?);
+    } else if mqtt_topic.is_some() {
+        return Err!("--mqtt-topic must be specified only with --mqtt-broker");
+    }
+
+    if let Some(ref address) = statsd_address {
+        let prefix = statsd_prefix.as_deref().unwrap_or("transmission_controller");
+        if args.configs.len() > 1 {
+            return Err!("--statsd-address can't be used with multiple --config");
+        }
+        args.statsd_client = Some(StatsdClient::new(address, prefix)?);
+    } else if statsd_prefix.is_some() {
+        return Err!("--statsd-prefix must be specified only with --statsd-address");
+    }
+
     Ok(args)
 }
+
+fn read_period_file(path: &str) -> GenericResult<Vec<String>> {
+    let contents = fs::read_to_string(path).map_err(|e| format!(
+        "Error while reading '{}' period file: {}", path, e))?;
+
+    Ok(contents.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| s!(line))
+        .collect())
+}
@@ -4,10 +4,11 @@ use std::error::Error;
 use std::fmt;
 use std::fs::File;
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use serde::Deserialize;
 
+use crate::email::EmailTemplate;
 use crate::util;
 
 #[derive(Debug, Deserialize)]
@@ -28,6 +29,36 @@ pub struct Config {
     pub rpc_username: String,
     #[serde(rename = "rpc-plain-password")]
     pub rpc_plain_password: Option<String>,
+
+    // Per-daemon overrides of the copy and move directories specified in command line arguments
+    #[serde(rename = "tc-copy-to", default)]
+    pub copy_to: Option<PathBuf>,
+    #[serde(rename = "tc-move-to", default)]
+    pub move_to: Option<PathBuf>,
+
+    // Inline 'torrent downloaded' notification template (the body may be specified as a list of lines)
+    #[serde(rename = "tc-torrent-downloaded-email-subject", default)]
+    torrent_downloaded_email_subject: Option<String>,
+    #[serde(rename = "tc-torrent-downloaded-email-body", default)]
+    torrent_downloaded_email_body: Option<TemplateBody>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum TemplateBody {
+    Text(String),
+    Lines(Vec<String>),
+}
+
+impl Config {
+    pub fn get_torrent_downloaded_email_template(&self) -> Option<EmailTemplate> {
+        let subject = self.torrent_downloaded_email_subject.as_ref()?;
+        let body = match self.torrent_downloaded_email_body.as_ref()? {
+            TemplateBody::Text(text) => text.clone(),
+            TemplateBody::Lines(lines) => lines.join("\n"),
+        };
+        Some(EmailTemplate::new(subject, &body))
+    }
 }
 
 #[derive(Debug)]
@@ -40,23 +71,23 @@ use self::ConfigReadingError::*;
 
 pub type Result<T> = ::std::result::Result<T, ConfigReadingError>;
 
-pub fn read_config<P: AsRef<Path>>(path: P) -> Result<Config> {
+pub fn read_config<P: AsRef<Path>>(path: P, path_remaps: &[(PathBuf, PathBuf)]) -> Result<Config> {
     let mut file = File::open(path)?;
 
     let config: Config = serde_json::from_reader(&mut file)?;
-    validate_config(&config)?;
+    validate_config(&config, path_remaps)?;
 
     Ok(config)
 }
 
-fn validate_config(config: &Config) -> Result<()> {
+fn validate_config(config: &Config, path_remaps: &[(PathBuf, PathBuf)]) -> Result<()> {
     let error = |e: &str| Err(Validation(s!(e)));
 
     if !config.download_dir.starts_with('/') {
         return error("Invalid 'download-dir' value: it must be an absolute path");
     }
 
-    util::fs::check_directory(&config.download_dir).map_err(|e| Validation(format!(
+    util::fs::check_directory(util::fs::remap_path(&config.download_dir, path_remaps)).map_err(|e| Validation(format!(
         "Invalid 'download-dir': {}", e)))?;
 
     if !config.rpc_enabled {
@@ -71,6 +102,21 @@ fn validate_config(config: &Config) -> Result<()> {
         return error("'rpc-plain-password' is a required option when authentication is enabled");
     }
 
+    for (name, path) in [("tc-copy-to", &config.copy_to), ("tc-move-to", &config.move_to)] {
+        if let Some(path) = path {
+            if path.is_relative() {
+                return Err(Validation(format!("Invalid '{}' value: it must be an absolute path", name)));
+            }
+
+            util::fs::check_directory(path).map_err(|e| Validation(format!("Invalid '{}': {}", name, e)))?;
+        }
+    }
+
+    if config.torrent_downloaded_email_subject.is_some() != config.torrent_downloaded_email_body.is_some() {
+        return error(
+            "'tc-torrent-downloaded-email-subject' and 'tc-torrent-downloaded-email-body' must be specified together");
+    }
+
     Ok(())
 }
 
@@ -1,19 +1,89 @@
+use std::cmp;
 use std::collections::{HashMap, HashSet};
 use std::ffi::OsString;
+use std::fmt;
 use std::fs;
 use std::io;
 use std::mem;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use itertools::Itertools;
+use serde_json::json;
 
-use crate::common::{EmptyResult, GenericResult};
-use crate::email::{Mailer, EmailTemplate};
-use crate::transmissionrpc::{TransmissionClient, Torrent, TransmissionClientError, TransmissionRpcError};
+use crate::common::{EmptyResult, GenericError, GenericResult};
+use crate::email::{self, Mailer, EmailTemplate};
+use crate::mqtt::MqttPublisher;
+use crate::transmissionrpc::{
+    TransmissionClient, Torrent, TorrentStatus, TransmissionClientError, TransmissionRpcError};
 use crate::util;
+use crate::util::fs::{CopyOptions, EmptyFsResult, FileOwner, FsError, FsResult};
+
+// Maximum number of files listed in 'torrent downloaded' notification
+const MAX_NOTIFICATION_FILES: usize = 100;
+
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(6 * 60 * 60);
+const VERIFY_CHECK_PERIOD: Duration = Duration::from_secs(10);
+const SHUTDOWN_CHECK_PERIOD: Duration = Duration::from_millis(100);
+
+/// Consumer settings. By default the torrents are only marked as processed.
+#[derive(Clone)]
+pub struct ConsumerOptions {
+    pub path_remaps: Vec<(PathBuf, PathBuf)>,
+    pub copy_to: Option<PathBuf>,
+    pub copy_to_labels: Vec<(String, PathBuf)>,
+    pub copy_owner: Option<FileOwner>,
+    pub copy_options: CopyOptions,
+    pub copy_all_files: bool,
+    pub ignored_extensions: Vec<String>,
+    pub missing_file_policy: MissingFilePolicy,
+    pub move_to: Option<PathBuf>,
+    pub move_conflict_policy: MoveConflictPolicy,
+    pub abandoned_files_check_period: Option<Duration>,
+    pub scrub_interval: Option<Duration>,
+    pub scrub_remove_empty_dirs: bool,
+    pub seed_ratio_limit: Option<f64>,
+    pub verify_before_consume: bool,
+    pub not_found_retries: usize,
+    pub not_found_retry_delay: Duration,
+
+    pub notifications_mailer: Option<Mailer>,
+    pub torrent_downloaded_email_template: EmailTemplate,
+    pub torrent_failed_email_template: EmailTemplate,
+}
+
+impl Default for ConsumerOptions {
+    fn default() -> ConsumerOptions {
+        ConsumerOptions {
+            path_remaps: Vec::new(),
+            copy_to: None,
+            copy_to_labels: Vec::new(),
+            copy_owner: None,
+            copy_options: CopyOptions::default(),
+            copy_all_files: false,
+            ignored_extensions: Vec::new(),
+            missing_file_policy: MissingFilePolicy::Fail,
+            move_to: None,
+            move_conflict_policy: MoveConflictPolicy::Rename,
+            abandoned_files_check_period: None,
+            scrub_interval: None,
+            scrub_remove_empty_dirs: false,
+            seed_ratio_limit: None,
+            verify_before_consume: false,
+            not_found_retries: 2,
+            not_found_retry_delay: Duration::from_secs(5),
+
+            notifications_mailer: None,
+            torrent_downloaded_email_template: EmailTemplate::new(
+                "Downloaded: {{name}}", "{{name}} torrent has been downloaded."),
+            torrent_failed_email_template: EmailTemplate::new(
+                "Failed to consume {{name}} torrent", "{{error}}."),
+        }
+    }
+}
 
 pub struct Consumer {
     data: Arc<Mutex<SharedData>>,
@@ -21,21 +91,141 @@ pub struct Consumer {
 }
 
 struct ConsumerThread {
-    copy_to: Option<PathBuf>,
-    move_to: Option<PathBuf>,
-
-    notifications_mailer: Option<Mailer>,
-    torrent_downloaded_email_template: EmailTemplate,
+    options: ConsumerOptions,
+    mqtt_publisher: Option<Arc<MqttPublisher>>,
 
     client: Arc<TransmissionClient>,
 
     failed: HashSet<String>,
+    // Hashes of the torrents for which 'torrent failed' notification has been sent due to cancelled consuming
+    cancel_notified: HashSet<String>,
     data: Arc<Mutex<SharedData>>,
 }
 
 struct SharedData {
     stop: bool,
-    in_process: HashSet<String>,
+    paused: bool,
+    // Maps hashes of the torrents to consume to their names
+    in_process: HashMap<String, String>,
+    // Paths where the data of the torrents copied since the consumer start has been placed to
+    consumed: HashMap<String, Vec<PathBuf>>,
+    // Number of torrents consumed since the last request
+    consumed_count: usize,
+}
+
+/// Specifies what to do when the moved file already exists in the destination directory.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MoveConflictPolicy {
+    /// Move the file under DUP_N. prefixed name
+    Rename,
+    /// Replace the existing file
+    Overwrite,
+    /// Keep the existing file and leave the source file in place
+    Skip,
+}
+
+impl MoveConflictPolicy {
+    pub const ALL: [MoveConflictPolicy; 3] = [
+        MoveConflictPolicy::Rename, MoveConflictPolicy::Overwrite, MoveConflictPolicy::Skip];
+
+    fn name(&self) -> &'static str {
+        match *self {
+            MoveConflictPolicy::Rename    => "rename",
+            MoveConflictPolicy::Overwrite => "overwrite",
+            MoveConflictPolicy::Skip      => "skip",
+        }
+    }
+}
+
+impl fmt::Display for MoveConflictPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for MoveConflictPolicy {
+    type Err = GenericError;
+
+    fn from_str(string: &str) -> Result<MoveConflictPolicy, GenericError> {
+        MoveConflictPolicy::ALL.iter()
+            .find(|policy| policy.name() == string)
+            .cloned()
+            .ok_or_else(|| format_to!("Invalid move conflict policy: {:?}", string))
+    }
+}
+
+/// Specifies what to do when a torrent file is missing on the disk during copying.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MissingFilePolicy {
+    /// Fail the torrent consuming
+    Fail,
+    /// Copy the existing files and consume the torrent anyway
+    Skip,
+}
+
+impl MissingFilePolicy {
+    pub const ALL: [MissingFilePolicy; 2] = [MissingFilePolicy::Fail, MissingFilePolicy::Skip];
+
+    fn name(&self) -> &'static str {
+        match *self {
+            MissingFilePolicy::Fail => "fail",
+            MissingFilePolicy::Skip => "skip",
+        }
+    }
+}
+
+impl fmt::Display for MissingFilePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for MissingFilePolicy {
+    type Err = GenericError;
+
+    fn from_str(string: &str) -> Result<MissingFilePolicy, GenericError> {
+        MissingFilePolicy::ALL.iter()
+            .find(|policy| policy.name() == string)
+            .cloned()
+            .ok_or_else(|| format_to!("Invalid missing file policy: {:?}", string))
+    }
+}
+
+/// Specifies what to do when the consuming thread hasn't stopped within the shutdown timeout.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ShutdownTimeoutAction {
+    /// Exit abandoning the torrents being consumed (partially copied files are left on the disk)
+    Force,
+    /// Wait until the torrent being consumed is copied
+    Extend,
+}
+
+impl ShutdownTimeoutAction {
+    pub const ALL: [ShutdownTimeoutAction; 2] = [ShutdownTimeoutAction::Force, ShutdownTimeoutAction::Extend];
+
+    fn name(&self) -> &'static str {
+        match *self {
+            ShutdownTimeoutAction::Force  => "force",
+            ShutdownTimeoutAction::Extend => "extend",
+        }
+    }
+}
+
+impl fmt::Display for ShutdownTimeoutAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for ShutdownTimeoutAction {
+    type Err = GenericError;
+
+    fn from_str(string: &str) -> Result<ShutdownTimeoutAction, GenericError> {
+        ShutdownTimeoutAction::ALL.iter()
+            .find(|action| action.name() == string)
+            .cloned()
+            .ok_or_else(|| format_to!("Invalid shutdown timeout action: {:?}", string))
+    }
 }
 
 enum ProcessError {
@@ -45,23 +235,38 @@ enum ProcessError {
 }
 type ProcessResult = Result<(), ProcessError>;
 
+impl ProcessError {
+    fn from_fs_error(error: FsError) -> ProcessError {
+        match error {
+            // There is a chance that free space will appear later (for example after the download
+            // directory cleanup), so retry the operation.
+            FsError::NoSpace(error) => ProcessError::Temporary(error),
+            FsError::NotFound(error) | FsError::Permission(error) | FsError::Other(error) => {
+                ProcessError::Persistent(error)
+            },
+        }
+    }
+}
+
 impl Consumer {
-    pub fn new(client: Arc<TransmissionClient>, copy_to: Option<PathBuf>, move_to: Option<PathBuf>,
-               notifications_mailer: Option<Mailer>, torrent_downloaded_email_template: EmailTemplate) -> Consumer {
+    pub fn new(
+        client: Arc<TransmissionClient>, options: ConsumerOptions, mqtt_publisher: Option<Arc<MqttPublisher>>,
+    ) -> Consumer {
         let data = Arc::new(Mutex::new(SharedData {
             stop: false,
-            in_process: HashSet::new(),
+            paused: false,
+            in_process: HashMap::new(),
+            consumed: HashMap::new(),
+            consumed_count: 0,
         }));
 
         let mut consumer_thread = ConsumerThread {
-            copy_to: copy_to,
-            move_to: move_to,
-
-            notifications_mailer: notifications_mailer,
-            torrent_downloaded_email_template: torrent_downloaded_email_template,
+            options: options,
+            mqtt_publisher: mqtt_publisher,
 
             client: client,
             failed: HashSet::new(),
+            cancel_notified: HashSet::new(),
             data: data.clone(),
         };
 
@@ -73,25 +278,78 @@ impl Consumer {
 
     pub fn get_in_process(&self) -> HashSet<String> {
         let data = self.data.lock().unwrap();
-        data.in_process.clone()
+        data.in_process.keys().cloned().collect()
     }
 
-    pub fn consume(&self, hash: &str) {
-        debug!("Scheduling {:?} torrent for consuming.", hash);
+    /// Returns the paths where the data of the torrent has been copied to or None if the torrent hasn't been copied
+    /// since the consumer start.
+    pub fn get_consumed_paths(&self, hash: &str) -> Option<Vec<PathBuf>> {
+        let data = self.data.lock().unwrap();
+        data.consumed.get(hash).cloned()
+    }
+
+    /// Returns the number of torrents consumed since the previous call.
+    pub fn take_consumed_count(&self) -> usize {
+        let mut data = self.data.lock().unwrap();
+        mem::take(&mut data.consumed_count)
+    }
+
+    pub fn forget_consumed(&self, hash: &str) {
+        let mut data = self.data.lock().unwrap();
+        data.consumed.remove(hash);
+    }
+
+    #[cfg(test)]
+    pub fn add_consumed(&self, hash: &str, paths: Vec<PathBuf>) {
+        let mut data = self.data.lock().unwrap();
+        data.consumed.insert(s!(hash), paths);
+    }
+
+    /// Checks whether the consuming thread is still running (it may die only on panic).
+    pub fn is_alive(&self) -> bool {
+        self.thread_handle.as_ref().is_some_and(|thread_handle| !thread_handle.is_finished())
+    }
+
+    /// Pauses/resumes consuming of the scheduled torrents (the torrent being consumed at the moment is finished).
+    pub fn set_paused(&self, paused: bool) {
+        self.data.lock().unwrap().paused = paused;
+
+        if !paused {
+            if let Some(ref thread_handle) = self.thread_handle {
+                thread_handle.thread().unpark();
+            }
+        }
+    }
+
+    pub fn consume(&self, torrent: &Torrent) {
+        debug!("Scheduling {} torrent for consuming.", torrent.id());
 
         {
             let mut data = self.data.lock().unwrap();
-            data.in_process.insert(s!(hash));
+            data.in_process.insert(torrent.hash.clone(), torrent.name.clone());
         }
 
         if let Some(ref thread_handle) = self.thread_handle {
             thread_handle.thread().unpark();
         }
     }
-}
 
-impl Drop for Consumer {
-    fn drop(&mut self) {
+    /// Asks the consuming thread to stop without waiting for it to finish.
+    pub fn request_stop(&self) {
+        self.data.lock().unwrap().stop = true;
+
+        if let Some(ref thread_handle) = self.thread_handle {
+            thread_handle.thread().unpark();
+        }
+    }
+
+    /// Stops the consuming thread waiting for it to finish. If the thread hasn't stopped within
+    /// the timeout, the specified action is taken.
+    pub fn stop(mut self, timeout: Option<Duration>, on_timeout: ShutdownTimeoutAction) {
+        self.stop_thread(timeout, on_timeout);
+    }
+
+    fn stop_thread(&mut self, timeout: Option<Duration>, on_timeout: ShutdownTimeoutAction) {
         let mut thread_handle = None;
         mem::swap(&mut thread_handle, &mut self.thread_handle);
 
@@ -101,6 +359,25 @@ impl Drop for Consumer {
             self.data.lock().unwrap().stop = true;
             thread_handle.thread().unpark();
 
+            if let Some(timeout) = timeout {
+                let deadline = Instant::now() + timeout;
+                while !thread_handle.is_finished() && Instant::now() < deadline {
+                    thread::sleep(SHUTDOWN_CHECK_PERIOD);
+                }
+
+                if !thread_handle.is_finished() {
+                    match on_timeout {
+                        ShutdownTimeoutAction::Force => {
+                            warn!("Torrent consuming thread hasn't stopped in time. Abandoning the torrents being consumed.");
+                            return;
+                        },
+                        ShutdownTimeoutAction::Extend => {
+                            warn!("Torrent consuming thread hasn't stopped in time. Waiting for it to finish...");
+                        },
+                    }
+                }
+            }
+
             if let Err(error) = thread_handle.join() {
                 error!("Torrent consuming thread has panicked: {:?}.", error);
             } else {
@@ -110,25 +387,64 @@ impl Drop for Consumer {
     }
 }
 
+impl Drop for Consumer {
+    fn drop(&mut self) {
+        self.stop_thread(None, ShutdownTimeoutAction::Extend);
+    }
+}
+
 impl ConsumerThread {
     fn run(&mut self) {
-        if let (Some(copy_to), Some(_)) = (self.copy_to.as_ref(), self.move_to.as_ref()) {
-            if let Err(error) = check_copy_to_directory(copy_to) {
-                error!("Failed to check copy to directory: {}.", error);
-            }
-        }
+        // Copy to directories must be empty only when the files are moved from them
+        let copy_dirs = match self.options.move_to {
+            Some(_) => self.get_copy_dirs(),
+            None => Vec::new(),
+        };
+        let mut check_time = if copy_dirs.is_empty() { None } else { Some(Instant::now()) };
+        let mut scrub_time = self.options.scrub_interval.map(|interval| Instant::now() + interval);
 
         let mut retry_after = None;
 
         loop {
-            if let Some(retry_after) = retry_after {
-                thread::park_timeout(retry_after);
+            if let Some(time) = check_time {
+                if time <= Instant::now() {
+                    for copy_dir in &copy_dirs {
+                        if let Err(error) = check_copy_to_directory(copy_dir) {
+                            error!("Failed to check copy to directory: {}.", error);
+                        }
+                    }
+                    check_time = self.options.abandoned_files_check_period.map(|period| Instant::now() + period);
+                }
+            }
+
+            if let Some(time) = scrub_time {
+                if time <= Instant::now() {
+                    if let Err(error) = self.scrub() {
+                        error!("Failed to scrub the copy/move directories: {}.", error);
+                    }
+                    scrub_time = self.options.scrub_interval.map(|interval| Instant::now() + interval);
+                }
+            }
+
+            let mut timeout = retry_after;
+            for time in check_time.iter().chain(scrub_time.iter()) {
+                let check_timeout = time.saturating_duration_since(Instant::now());
+                timeout = Some(timeout.map_or(check_timeout, |timeout| cmp::min(timeout, check_timeout)));
+            }
+
+            if let Some(timeout) = timeout {
+                thread::park_timeout(timeout);
             } else {
                 thread::park();
             }
 
-            if self.data.lock().unwrap().stop {
-                break;
+            {
+                let data = self.data.lock().unwrap();
+                if data.stop {
+                    break;
+                } else if data.paused {
+                    continue;
+                }
             }
 
             retry_after = self.process();
@@ -142,21 +458,82 @@ impl ConsumerThread {
         }
     }
 
-    fn to_process(&self) -> Vec<String> {
+    fn to_process(&self) -> Vec<(String, String)> {
         let data = self.data.lock().unwrap();
-        data.in_process.difference(&self.failed).cloned().collect()
+        data.in_process.iter()
+            .filter(|(hash, _)| !self.failed.contains(*hash))
+            .map(|(hash, name)| (hash.clone(), name.clone()))
+            .collect()
+    }
+
+    // The default copy directory and the per-label ones
+    fn get_copy_dirs(&self) -> Vec<PathBuf> {
+        let mut copy_dirs: Vec<PathBuf> = Vec::new();
+
+        for path in self.options.copy_to.iter().chain(self.options.copy_to_labels.iter().map(|(_, path)| path)) {
+            if !copy_dirs.contains(path) {
+                copy_dirs.push(path.clone());
+            }
+        }
+
+        copy_dirs
+    }
+
+    fn scrub(&self) -> EmptyResult {
+        let torrents = self.client.get_torrents()?;
+        let torrent_names: HashSet<&str> = torrents.iter().map(|torrent| torrent.name.as_str()).collect();
+
+        let copy_dirs = self.get_copy_dirs();
+
+        for path in copy_dirs.iter().chain(self.options.move_to.iter()) {
+            debug!("Scrubbing '{}'...", path.display());
+
+            // Move to directory is the final destination of the data which outlives the torrents, so only the copy
+            // directories are checked for orphaned files.
+            if copy_dirs.contains(path) {
+                let orphaned_files: Vec<String> = read_directory(path)?.into_iter()
+                    .map(|file_name| file_name.to_string_lossy().into_owned())
+                    .filter(|file_name| !file_name.starts_with('.') && !torrent_names.contains(file_name.as_str()))
+                    .collect();
+
+                if !orphaned_files.is_empty() {
+                    warn!("'{}' has the following files not associated with any torrent: {}.", path.display(),
+                        orphaned_files.iter().map(|file_name| format!("'{}'", file_name)).join(", "));
+                }
+            }
+
+            let mut empty_dirs = Vec::new();
+            scrub_directory(path, self.options.scrub_remove_empty_dirs, &mut empty_dirs)?;
+
+            if !empty_dirs.is_empty() {
+                warn!("{} the following empty directories: {}.",
+                    if self.options.scrub_remove_empty_dirs { "Removed" } else { "Found" },
+                    empty_dirs.iter().map(|path| format!("'{}'", path.display())).join(", "));
+            }
+        }
+
+        Ok(())
     }
 
     fn process(&mut self) -> Option<Duration> {
-        for hash in &self.to_process() {
+        for (hash, name) in &self.to_process() {
+            if self.data.lock().unwrap().paused {
+                break;
+            }
+
             match self.process_torrent(hash)  {
                 Ok(_) => {
-                    assert!(self.data.lock().unwrap().in_process.remove(hash));
+                    self.cancel_notified.remove(hash);
+                    assert!(self.data.lock().unwrap().in_process.remove(hash).is_some());
                 },
                 Err(error) => match error {
                     ProcessError::Cancelled(error) => {
                         warn!("{}.", error);
-                        assert!(self.data.lock().unwrap().in_process.remove(hash));
+                        // The torrent may be scheduled for consuming again and again, so notify only once
+                        if self.cancel_notified.insert(hash.clone()) {
+                            self.notify_failure(hash, name, &error);
+                        }
+                        assert!(self.data.lock().unwrap().in_process.remove(hash).is_some());
                     },
                     ProcessError::Temporary(error) => {
                         error!("{}.", error);
@@ -164,6 +541,8 @@ impl ConsumerThread {
                     },
                     ProcessError::Persistent(error) => {
                         error!("{}.", error);
+                        // The failed torrents aren't processed anymore, so the notification is sent only once
+                        self.notify_failure(hash, name, &error);
                         assert!(self.failed.insert(hash.clone()));
                     },
                 },
@@ -173,98 +552,314 @@ impl ConsumerThread {
         None
     }
 
-    fn process_torrent(&self, hash: &str) -> ProcessResult {
-        let torrent = self.client.get_torrent(hash).map_err(|error| {
-            if let TransmissionClientError::Rpc(TransmissionRpcError::TorrentNotFoundError(_)) = error {
-                return ProcessError::Cancelled(format!(
-                    "Failed to consume {} torrent: it has been removed", hash));
+    fn notify_failure(&self, hash: &str, name: &str, error: &str) {
+        if let Some(ref mailer) = self.options.notifications_mailer {
+            let mut params = HashMap::new();
+            params.insert("hash", s!(hash));
+            params.insert("name", s!(name));
+            params.insert("error", s!(error));
+
+            if let Err(e) = self.options.torrent_failed_email_template.send(mailer, &params) {
+                error!("Failed to send 'torrent failed' notification for {} torrent: {}.", hash, e);
             }
+        }
+    }
 
-            ProcessError::Temporary(format!("Failed to get '{}' torrent info: {}", hash, error))
-        })?;
+    fn process_torrent(&self, hash: &str) -> ProcessResult {
+        let torrent = self.get_torrent(hash)?;
 
         if !torrent.done {
             return Err(ProcessError::Cancelled(format!(
-                "Cancelling consuming of {} torrent: it has started to download", torrent.name)));
+                "Cancelling consuming of {} torrent: it has started to download", torrent.id())));
         }
 
-        if let Err(error) = self.consume_torrent(&torrent) {
-            return Err(ProcessError::Persistent(error.to_string()));
+        let torrent = if self.options.verify_before_consume {
+            self.verify_torrent(&torrent)?
+        } else {
+            torrent
+        };
+
+        self.consume_torrent(&torrent)
+    }
+
+    fn get_torrent(&self, hash: &str) -> Result<Torrent, ProcessError> {
+        let mut attempt = 0;
+
+        loop {
+            match self.client.get_torrent(hash) {
+                Ok(torrent) => return Ok(torrent),
+
+                // Right after Transmission restart the torrent may be temporary missing until all torrents are
+                // loaded, so don't treat it as removed immediately.
+                Err(TransmissionClientError::Rpc(TransmissionRpcError::TorrentNotFoundError(_)))
+                    if attempt < self.options.not_found_retries => {
+                    attempt += 1;
+                    debug!("{} torrent is not found. Retrying in {:?}...", hash, self.options.not_found_retry_delay);
+
+                    thread::park_timeout(self.options.not_found_retry_delay);
+                    if self.data.lock().unwrap().stop {
+                        return Err(ProcessError::Temporary(format!(
+                            "Failed to get {} torrent info: the operation has been interrupted", hash)));
+                    }
+                },
+
+                Err(TransmissionClientError::Rpc(TransmissionRpcError::TorrentNotFoundError(_))) => {
+                    return Err(ProcessError::Cancelled(format!(
+                        "Failed to consume {} torrent: it has been removed", hash)));
+                },
+
+                Err(error) => return Err(ProcessError::Temporary(format!(
+                    "Failed to get '{}' torrent info: {}", hash, error))),
+            }
         }
+    }
 
-        Ok(())
+    /// Asks Transmission to verify the torrent's data and waits for the verification result.
+    fn verify_torrent(&self, torrent: &Torrent) -> Result<Torrent, ProcessError> {
+        info!("Verifying {} torrent...", torrent.id());
+
+        self.client.verify(&torrent.hash).map_err(|e| ProcessError::Temporary(format!(
+            "Failed to start verification of {} torrent: {}", torrent.id(), e)))?;
+
+        let deadline = Instant::now() + VERIFY_TIMEOUT;
+
+        loop {
+            thread::park_timeout(VERIFY_CHECK_PERIOD);
+
+            if self.data.lock().unwrap().stop {
+                return Err(ProcessError::Temporary(format!(
+                    "Verification of {} torrent has been interrupted", torrent.id())));
+            }
+
+            let verified_torrent = self.get_torrent(&torrent.hash)?;
+
+            match verified_torrent.status {
+                TorrentStatus::CheckWait | TorrentStatus::Checking => {
+                    if Instant::now() >= deadline {
+                        return Err(ProcessError::Persistent(format!(
+                            "Failed to verify {} torrent: verification has timed out", torrent.id())));
+                    }
+                },
+                _ => {
+                    if !verified_torrent.done {
+                        return Err(ProcessError::Cancelled(format!(
+                            "Cancelling consuming of {} torrent: it's incomplete after verification",
+                            torrent.id())));
+                    }
+
+                    info!("{} torrent has been successfully verified.", torrent.id());
+                    return Ok(verified_torrent);
+                },
+            }
+        }
     }
 
-    fn consume_torrent(&self, torrent: &Torrent) -> EmptyResult {
-        info!("Consuming '{}' torrent...", torrent.name);
+    fn consume_torrent(&self, torrent: &Torrent) -> ProcessResult {
+        info!("Consuming {} torrent...", torrent.id());
+
+        // Paths where the torrent's data is located after consuming
+        let mut dest_paths: Vec<PathBuf> = Vec::new();
 
-        if let Some(ref copy_to) = self.copy_to {
-            let torrent_files = copy_torrent(torrent, copy_to).map_err(|e| format!(
-                "Failed to copy '{}' torrent: {}", torrent.name, e))?;
+        let copy_to = self.options.copy_to.as_ref().map(|copy_to| get_copy_destination(
+            torrent, copy_to, &self.options.copy_to_labels));
 
-            if let Some(ref move_to) = self.move_to {
+        if let Some(copy_to) = copy_to {
+            let torrent_files = copy_torrent(torrent, copy_to, &self.options).map_err(|e| {
+                ProcessError::from_fs_error(e.map(|e| format!("Failed to copy {} torrent: {}", torrent.id(), e)))
+            })?;
+
+            if let Some(ref move_to) = self.options.move_to {
                 for file_path in &torrent_files {
-                    move_torrent_file(file_path, move_to).map_err(|e| format!(
-                        "Failed to move '{}' torrent: {}", torrent.name, e))?;
+                    let dest_path = move_torrent_file(file_path, move_to, self.options.move_conflict_policy)
+                        .map_err(|e| ProcessError::from_fs_error(e.map(|e| format!(
+                            "Failed to move {} torrent: {}", torrent.id(), e))))?;
+                    dest_paths.push(dest_path);
                 }
+            } else {
+                dest_paths.extend(torrent_files);
+            }
+        } else {
+            dest_paths.push(util::fs::remap_path(&torrent.download_dir, &self.options.path_remaps).join(&torrent.name));
+        }
+
+        dest_paths.sort();
+
+        self.client.set_processed(torrent).map_err(|e| ProcessError::Persistent(e.to_string()))?;
+        info!("{} torrent has been consumed.", torrent.id());
+
+        {
+            let mut data = self.data.lock().unwrap();
+            data.consumed_count += 1;
+
+            // Remember the actual destination to be able to check later whether the data still exists
+            if copy_to.is_some() && !dest_paths.is_empty() {
+                data.consumed.insert(torrent.hash.clone(), dest_paths.clone());
             }
         }
 
-        self.client.set_processed(&torrent.hash)?;
-        info!("'{}' torrent has been consumed.", torrent.name);
+        if let Some(ratio) = self.options.seed_ratio_limit {
+            if let Err(e) = self.client.set_seed_ratio_limit(&torrent.hash, ratio) {
+                error!("Failed to set seed ratio limit for {} torrent: {}.", torrent.id(), e);
+            }
+        }
 
-        if let Some(ref mailer) = self.notifications_mailer {
+        if let Some(ref mailer) = self.options.notifications_mailer {
             let mut params = HashMap::new();
             params.insert("name", torrent.name.clone());
+            params.insert("dest_paths", dest_paths.iter().map(|path| path.display()).join("\n"));
 
-            if let Err(e) = self.torrent_downloaded_email_template.send(mailer, &params) {
-                error!("Failed to send 'torrent downloaded' notification for '{}' torrent: {}.",
-                    torrent.name, e);
+            let files: Vec<&str> = torrent.files.iter().flatten()
+                .filter(|file| file.selected)
+                .map(|file| file.name.as_str())
+                .collect();
+            params.insert("files", email::format_list(&files, MAX_NOTIFICATION_FILES));
+
+            if let Err(e) = self.options.torrent_downloaded_email_template.send(mailer, &params) {
+                error!("Failed to send 'torrent downloaded' notification for {} torrent: {}.",
+                    torrent.id(), e);
             }
         }
 
+        if let Some(ref mqtt_publisher) = self.mqtt_publisher {
+            let data = json!({
+                "hash": torrent.hash,
+                "name": torrent.name,
+                "dest_paths": dest_paths,
+            });
+
+            mqtt_publisher.publish("torrent-downloaded", data);
+        }
+
         Ok(())
     }
 }
 
-fn copy_torrent<P: AsRef<Path>>(torrent: &Torrent, destination: P) -> GenericResult<HashSet<PathBuf>> {
+// The torrent's first label selects the destination: the torrents without a matching label are copied to the
+// default one.
+fn get_copy_destination<'a>(torrent: &Torrent, copy_to: &'a Path, copy_to_labels: &'a [(String, PathBuf)]) -> &'a Path {
+    torrent.labels.first().and_then(|label| {
+        copy_to_labels.iter()
+            .find(|(copy_label, _)| copy_label == label)
+            .map(|(_, path)| path.as_path())
+    }).unwrap_or(copy_to)
+}
+
+fn copy_torrent<P: AsRef<Path>>(
+    torrent: &Torrent, destination: P, options: &ConsumerOptions,
+) -> FsResult<HashSet<PathBuf>> {
     let destination = destination.as_ref();
 
-    let download_dir_path = Path::new(&torrent.download_dir);
-    if !download_dir_path.is_absolute() {
-        return Err!("Torrent's download directory is not an absolute path: {}",
-            torrent.download_dir)
+    if !Path::new(&torrent.download_dir).is_absolute() {
+        return Err(FsError::Other(format!("Torrent's download directory is not an absolute path: {}",
+            torrent.download_dir)))
     }
+    let download_dir_path = util::fs::remap_path(&torrent.download_dir, &options.path_remaps);
 
-    info!("Copying '{}' to '{}'...", torrent.name, destination.display());
+    info!("Copying {} to '{}'...", torrent.id(), destination.display());
 
     let mut torrent_files = HashSet::new();
+    let mut owned_dirs = HashSet::new();
+    let mut missing_files = Vec::new();
 
-    for file in torrent.files.as_ref().unwrap().iter().filter(|file| file.selected) {
-        let (file_root_path, file_path, file_name) = validate_torrent_file_name(&file.name)?;
+    for file in torrent.files.as_ref().unwrap().iter().filter(|file| options.copy_all_files || file.selected) {
+        let (file_root_path, file_path, file_name) = validate_torrent_file_name(&file.name).map_err(|e| {
+            if e.is_suspicious() {
+                warn!("{} ({}): Suspicious torrent file name: {}.", torrent.id(), torrent.hash, e);
+            }
+            FsError::Other(e.to_string())
+        })?;
 
-        if file_name.to_string_lossy().starts_with('.') {
-            info!("'{}': Ignoring '{}'.", torrent.name, file_path.display());
+        if file_name.to_string_lossy().starts_with('.') || has_extension(&file_path, &options.ignored_extensions) {
+            info!("{}: Ignoring '{}'.", torrent.id(), file_path.display());
             continue;
         }
 
         let src_path = download_dir_path.join(&file_path);
         let dst_path = destination.join(&file_path);
 
+        // Unselected files are usually not downloaded at all or downloaded only partially
+        if !file.selected && (!file.downloaded || !src_path.exists()) {
+            info!("{}: Skipping '{}': it hasn't been fully downloaded.", torrent.id(), file_path.display());
+            continue;
+        }
+
+        // Don't follow symlinks which lead outside of the download directory
+        if util::fs::escapes_directory(&src_path, &download_dir_path)? {
+            warn!("{}: Skipping '{}': it resolves to a location outside of the download directory.",
+                torrent.id(), file_path.display());
+            continue;
+        }
+
         debug!("Copying '{}'...", src_path.display());
 
         if let Some(file_dir_path) = file_path.parent() {
             util::fs::create_all_dirs_from_base(destination, file_dir_path)?;
+
+            if let Some(ref owner) = options.copy_owner {
+                for dir_path in file_dir_path.ancestors().filter(|path| !path.as_os_str().is_empty()) {
+                    if owned_dirs.insert(dir_path.to_path_buf()) {
+                        owner.apply(destination.join(dir_path))?;
+                    }
+                }
+            }
+        }
+
+        match util::fs::copy_downloaded_file(&src_path, &dst_path, options.copy_options) {
+            Err(FsError::NotFound(error)) if options.missing_file_policy == MissingFilePolicy::Skip => {
+                warn!("{}: Skipping '{}': {}.", torrent.id(), file_path.display(), error);
+                missing_files.push(file_path);
+                continue;
+            },
+            result => result?,
         }
 
-        util::fs::copy_downloaded_file(&src_path, &dst_path)?;
+        if let Some(ref owner) = options.copy_owner {
+            owner.apply(&dst_path)?;
+        }
         torrent_files.insert(destination.join(&file_root_path));
     }
 
+    if !missing_files.is_empty() {
+        warn!("{} has been copied without the following missing files:\n{}", torrent.id(),
+              missing_files.iter().map(|path| format!("* {}", path.display())).join("\n"));
+    }
+
     Ok(torrent_files)
 }
 
-fn validate_torrent_file_name(torrent_file_name: &str) -> GenericResult<(PathBuf, PathBuf, OsString)> {
+// Extensions are expected to be in lowercase
+fn has_extension(path: &Path, extensions: &[String]) -> bool {
+    match path.extension() {
+        Some(extension) => extensions.contains(&extension.to_string_lossy().to_lowercase()),
+        None => false,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum FileNameError {
+    Malformed(String),
+    Absolute(String),
+    Traversal(String),
+}
+
+impl FileNameError {
+    // Absolute and traversal paths can't be produced by a well-behaved torrent client
+    fn is_suspicious(&self) -> bool {
+        matches!(*self, FileNameError::Absolute(_) | FileNameError::Traversal(_))
+    }
+}
+
+impl fmt::Display for FileNameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FileNameError::Malformed(ref name) => write!(f, "Invalid torrent file name: '{}'", name),
+            FileNameError::Absolute(ref name) => write!(f, "Torrent file name is an absolute path: '{}'", name),
+            FileNameError::Traversal(ref name) => write!(f, "Torrent file name points outside of the torrent: '{}'", name),
+        }
+    }
+}
+
+fn validate_torrent_file_name(torrent_file_name: &str) -> Result<(PathBuf, PathBuf, OsString), FileNameError> {
     use std::path::Component::*;
 
     let mut file_root_path = None;
@@ -280,9 +875,9 @@ fn validate_torrent_file_name(torrent_file_name: &str) -> GenericResult<(PathBuf
                 file_name = Some(component);
                 file_path.push(component);
             },
-            Prefix(_) | RootDir | CurDir | ParentDir => {
-                return Err!("Invalid torrent file name: '{}'", torrent_file_name);
-            }
+            Prefix(_) | RootDir => return Err(FileNameError::Absolute(s!(torrent_file_name))),
+            ParentDir => return Err(FileNameError::Traversal(s!(torrent_file_name))),
+            CurDir => return Err(FileNameError::Malformed(s!(torrent_file_name))),
         }
     }
 
@@ -290,12 +885,14 @@ fn validate_torrent_file_name(torrent_file_name: &str) -> GenericResult<(PathBuf
         return Ok((file_root_path.to_path_buf(), file_path, file_name.to_os_string()))
     }
 
-    Err!("Invalid torrent file name: '{}'", torrent_file_name)
+    Err(FileNameError::Malformed(s!(torrent_file_name)))
 }
 
-fn move_torrent_file<S, D>(src: S, dst_dir: D) -> EmptyResult where S: AsRef<Path>, D: AsRef<Path> {
+fn move_torrent_file<S, D>(src: S, dst_dir: D, conflict_policy: MoveConflictPolicy) -> FsResult<PathBuf>
+    where S: AsRef<Path>, D: AsRef<Path>
+{
     let (src, dst_dir) = (src.as_ref(), dst_dir.as_ref());
-    let src_name = src.file_name().ok_or(format!("Invalid file name: {}", src.display()))?;
+    let src_name = src.file_name().ok_or_else(|| FsError::Other(format!("Invalid file name: {}", src.display())))?;
 
     for id in 0..10 {
         let mut dst_file_name = OsString::new();
@@ -306,35 +903,64 @@ fn move_torrent_file<S, D>(src: S, dst_dir: D) -> EmptyResult where S: AsRef<Pat
 
         let dst = dst_dir.join(dst_file_name);
 
-        match fs::metadata(&dst) {
-            Ok(_) => continue,
+        match fs::symlink_metadata(&dst) {
+            Ok(metadata) => match conflict_policy {
+                MoveConflictPolicy::Rename => continue,
+                MoveConflictPolicy::Overwrite => {
+                    replace_file(src, &dst, metadata.is_dir())?;
+                    return Ok(dst);
+                },
+                MoveConflictPolicy::Skip => {
+                    warn!("Not moving '{}' to '{}': the destination already exists.", src.display(), dst.display());
+                    return Ok(src.to_path_buf());
+                },
+            },
             Err(err) => match err.kind() {
                 io::ErrorKind::NotFound => {},
-                _ => return Err!("Failed to stat() '{}': {}", dst.display(), err)
+                _ => return Err(FsError::new(&err, format!("Failed to stat() '{}': {}", dst.display(), err)))
             }
         }
 
         info!("Moving '{}' to '{}'...", src.display(), dst.display());
-        fs::rename(src, &dst).map_err(|e| format!(
-            "Failed to rename '{}' to '{}': {}", src.display(), dst.display(), e))?;
+        fs::rename(src, &dst).map_err(|e| FsError::new(&e, format!(
+            "Failed to rename '{}' to '{}': {}", src.display(), dst.display(), e)))?;
+
+        return Ok(dst);
+    }
+
+    Err(FsError::Other(format!("Failed to move '{}' to '{}': the file is already exists",
+        src.display(), dst_dir.display())))
+}
+
+fn replace_file(src: &Path, dst: &Path, dst_is_dir: bool) -> EmptyFsResult {
+    info!("Replacing '{}' with '{}'...", dst.display(), src.display());
+
+    let rename = |src: &Path, dst: &Path| fs::rename(src, dst).map_err(|e| FsError::new(&e, format!(
+        "Failed to rename '{}' to '{}': {}", src.display(), dst.display(), e)));
 
-        return Ok(());
+    // rename() replaces files atomically, but fails on non-empty directories and on file type
+    // mismatch, so move the old directory aside first.
+    if !dst_is_dir && !src.is_dir() {
+        return rename(src, dst);
     }
 
-    Err!("Failed to move '{}' to '{}': the file is already exists",
-        src.display(), dst_dir.display())
+    let mut old_name = OsString::from(".");
+    old_name.push(dst.file_name().unwrap());
+    old_name.push(".tc-old");
+    let old_path = dst.with_file_name(old_name);
+
+    rename(dst, &old_path)?;
+    rename(src, dst)?;
+
+    fs::remove_dir_all(&old_path).map_err(|e| FsError::new(&e, format!(
+        "Failed to delete '{}': {}", old_path.display(), e)))
 }
 
 fn check_copy_to_directory<P: AsRef<Path>>(path: P) -> EmptyResult {
     let path = path.as_ref();
-    let map_dir_reading_error = |e| format!(
-        "Error while reading '{}' directory: {}", path.display(), e);
-
     let mut abandoned_files: Vec<String> = Vec::new();
-    let directory = fs::read_dir(path).map_err(map_dir_reading_error)?;
 
-    for entry in directory {
-        let file_name = entry.map_err(map_dir_reading_error)?.file_name();
+    for file_name in read_directory(path)? {
         let file_name_lossy = file_name.to_string_lossy();
 
         if !file_name_lossy.starts_with('.') {
@@ -349,3 +975,215 @@ fn check_copy_to_directory<P: AsRef<Path>>(path: P) -> EmptyResult {
 
     Ok(())
 }
+
+fn read_directory(path: &Path) -> GenericResult<Vec<OsString>> {
+    let map_dir_reading_error = |e| format!(
+        "Error while reading '{}' directory: {}", path.display(), e);
+
+    let mut file_names = Vec::new();
+    let directory = fs::read_dir(path).map_err(map_dir_reading_error)?;
+
+    for entry in directory {
+        file_names.push(entry.map_err(map_dir_reading_error)?.file_name());
+    }
+
+    Ok(file_names)
+}
+
+/// Recursively collects (and optionally removes) empty directories under the specified path.
+/// Returns true if the directory itself is empty.
+fn scrub_directory(path: &Path, remove_empty_dirs: bool, empty_dirs: &mut Vec<PathBuf>) -> GenericResult<bool> {
+    let mut empty = true;
+
+    for file_name in read_directory(path)? {
+        let file_path = path.join(file_name);
+        let metadata = fs::symlink_metadata(&file_path).map_err(|e| format!(
+            "Unable to get '{}' metadata: {}", file_path.display(), e))?;
+
+        if metadata.is_dir() && scrub_directory(&file_path, remove_empty_dirs, empty_dirs)? {
+            if remove_empty_dirs {
+                fs::remove_dir(&file_path).map_err(|e| format!(
+                    "Unable to remove '{}': {}", file_path.display(), e))?;
+                empty_dirs.push(file_path);
+                continue;
+            }
+
+            empty_dirs.push(file_path);
+        }
+
+        empty = false;
+    }
+
+    Ok(empty)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transmissionrpc::TorrentFile;
+    use crate::transmissionrpc::tests::mock_torrent;
+    use crate::util::fs::tests::create_temp_dir;
+
+    use super::*;
+
+    #[test]
+    fn test_has_extension() {
+        let extensions = vec![s!("nfo"), s!("url")];
+
+        assert!(has_extension(Path::new("Torrent/Info.NFO"), &extensions));
+        assert!(has_extension(Path::new("site.url"), &extensions));
+        assert!(!has_extension(Path::new("Torrent/movie.mkv"), &extensions));
+        assert!(!has_extension(Path::new("Torrent/nfo"), &extensions));
+        assert!(!has_extension(Path::new("Torrent/Info.nfo"), &[]));
+    }
+
+    #[test]
+    fn test_validate_torrent_file_name() {
+        assert_eq!(validate_torrent_file_name("Torrent/Some file.mkv").unwrap(), (
+            PathBuf::from("Torrent"), PathBuf::from("Torrent/Some file.mkv"), OsString::from("Some file.mkv")));
+
+        assert_eq!(validate_torrent_file_name("../etc/passwd").unwrap_err(),
+                   FileNameError::Traversal(s!("../etc/passwd")));
+        assert_eq!(validate_torrent_file_name("Torrent/../../etc/passwd").unwrap_err(),
+                   FileNameError::Traversal(s!("Torrent/../../etc/passwd")));
+        assert_eq!(validate_torrent_file_name("/abs/path").unwrap_err(),
+                   FileNameError::Absolute(s!("/abs/path")));
+        assert_eq!(validate_torrent_file_name("").unwrap_err(),
+                   FileNameError::Malformed(s!("")));
+        assert_eq!(validate_torrent_file_name("./file").unwrap_err(),
+                   FileNameError::Malformed(s!("./file")));
+
+        assert!(validate_torrent_file_name("../etc/passwd").unwrap_err().is_suspicious());
+        assert!(!validate_torrent_file_name("").unwrap_err().is_suspicious());
+    }
+
+    #[test]
+    fn test_scrub_directory() {
+        let temp_dir = create_temp_dir("scrub-directory");
+
+        fs::create_dir_all(temp_dir.join("torrent/empty/nested")).unwrap();
+        fs::create_dir_all(temp_dir.join("other")).unwrap();
+        fs::write(temp_dir.join("torrent/file"), "data").unwrap();
+
+        let mut empty_dirs = Vec::new();
+        assert!(!scrub_directory(&temp_dir, false, &mut empty_dirs).unwrap());
+        empty_dirs.sort();
+        assert_eq!(empty_dirs, vec![temp_dir.join("other"), temp_dir.join("torrent/empty/nested")]);
+
+        let mut empty_dirs = Vec::new();
+        assert!(!scrub_directory(&temp_dir, true, &mut empty_dirs).unwrap());
+        empty_dirs.sort();
+        assert_eq!(empty_dirs, vec![
+            temp_dir.join("other"), temp_dir.join("torrent/empty"), temp_dir.join("torrent/empty/nested")]);
+        assert_eq!(read_directory(&temp_dir).unwrap(), vec![OsString::from("torrent")]);
+
+        fs::remove_dir_all(&temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_copy_torrent_missing_files() {
+        let temp_dir = create_temp_dir("copy-torrent");
+
+        let (download_dir, copy_dir) = (temp_dir.join("downloads"), temp_dir.join("copy"));
+        fs::create_dir_all(download_dir.join("torrent")).unwrap();
+        fs::create_dir_all(&copy_dir).unwrap();
+        fs::write(download_dir.join("torrent/existing"), "data").unwrap();
+
+        let mut torrent = new_torrent(&download_dir);
+        torrent.files = Some(vec![
+            TorrentFile {name: s!("torrent/existing"), selected: true, downloaded: true},
+            TorrentFile {name: s!("torrent/missing"), selected: true, downloaded: true},
+        ]);
+
+        let mut options = ConsumerOptions::default();
+        assert!(matches!(copy_torrent(&torrent, &copy_dir, &options).unwrap_err(), FsError::NotFound(_)));
+
+        options.missing_file_policy = MissingFilePolicy::Skip;
+        assert_eq!(copy_torrent(&torrent, &copy_dir, &options).unwrap(),
+                   [copy_dir.join("torrent")].into_iter().collect());
+        assert_eq!(fs::read_to_string(copy_dir.join("torrent/existing")).unwrap(), "data");
+        assert!(!copy_dir.join("torrent/missing").exists());
+
+        fs::remove_dir_all(&temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_copy_all_files() {
+        let temp_dir = create_temp_dir("copy-all-files");
+
+        let (download_dir, copy_dir) = (temp_dir.join("downloads"), temp_dir.join("copy"));
+        fs::create_dir_all(download_dir.join("torrent")).unwrap();
+        fs::create_dir_all(&copy_dir).unwrap();
+        for name in ["selected", "downloaded", "partial"] {
+            fs::write(download_dir.join("torrent").join(name), name).unwrap();
+        }
+
+        let mut torrent = new_torrent(&download_dir);
+        torrent.files = Some(vec![
+            TorrentFile {name: s!("torrent/selected"), selected: true, downloaded: true},
+            TorrentFile {name: s!("torrent/downloaded"), selected: false, downloaded: true},
+            TorrentFile {name: s!("torrent/partial"), selected: false, downloaded: false},
+            TorrentFile {name: s!("torrent/missing"), selected: false, downloaded: false},
+        ]);
+
+        let options = ConsumerOptions {copy_all_files: true, ..Default::default()};
+        copy_torrent(&torrent, &copy_dir, &options).unwrap();
+
+        assert!(copy_dir.join("torrent/selected").exists());
+        assert!(copy_dir.join("torrent/downloaded").exists());
+        assert!(!copy_dir.join("torrent/partial").exists());
+        assert!(!copy_dir.join("torrent/missing").exists());
+
+        fs::remove_dir_all(&temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_copy_torrent_retry() {
+        let temp_dir = create_temp_dir("copy-torrent-retry");
+
+        let (download_dir, copy_dir) = (temp_dir.join("downloads"), temp_dir.join("copy"));
+        fs::create_dir_all(download_dir.join("torrent")).unwrap();
+        fs::create_dir_all(&copy_dir).unwrap();
+        fs::write(download_dir.join("torrent/first"), "first").unwrap();
+
+        let mut torrent = new_torrent(&download_dir);
+        torrent.files = Some(vec![
+            TorrentFile {name: s!("torrent/first"), selected: true, downloaded: true},
+            TorrentFile {name: s!("torrent/second"), selected: true, downloaded: true},
+        ]);
+
+        // The copying fails after the first file has been copied
+        let options = ConsumerOptions::default();
+        assert!(copy_torrent(&torrent, &copy_dir, &options).is_err());
+        assert_eq!(fs::read_to_string(copy_dir.join("torrent/first")).unwrap(), "first");
+
+        fs::write(download_dir.join("torrent/second"), "second").unwrap();
+        assert_eq!(copy_torrent(&torrent, &copy_dir, &options).unwrap(),
+                   [copy_dir.join("torrent")].into_iter().collect());
+        assert_eq!(fs::read_to_string(copy_dir.join("torrent/second")).unwrap(), "second");
+
+        fs::remove_dir_all(&temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_get_copy_destination() {
+        let copy_to = Path::new("/copy");
+        let copy_to_labels = vec![(s!("tv"), PathBuf::from("/tv")), (s!("movies"), PathBuf::from("/movies"))];
+        let mut torrent = new_torrent(Path::new("/downloads"));
+
+        assert_eq!(get_copy_destination(&torrent, copy_to, &copy_to_labels), Path::new("/copy"));
+
+        torrent.labels = vec![s!("movies"), s!("tv")];
+        assert_eq!(get_copy_destination(&torrent, copy_to, &copy_to_labels), Path::new("/movies"));
+
+        torrent.labels = vec![s!("other"), s!("tv")];
+        assert_eq!(get_copy_destination(&torrent, copy_to, &copy_to_labels), Path::new("/copy"));
+    }
+
+    fn new_torrent(download_dir: &Path) -> Torrent {
+        Torrent {
+            name: s!("torrent"),
+            download_dir: s!(download_dir.to_str().unwrap()),
+            ..mock_torrent("hash")
+        }
+    }
+}
@@ -1,126 +1,841 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::Arc;
+use std::thread;
 use std::time::Instant;
 
-use time::{OffsetDateTime, Duration};
+use itertools::Itertools;
+use serde_json::json;
+use time::Duration;
 
-use crate::common::{EmptyResult, GenericResult};
-use crate::consumer::Consumer;
-use crate::email::{Mailer, EmailTemplate};
-use crate::transmissionrpc::{self, TransmissionClient, Torrent, TorrentStatus};
+use crate::common::{EmptyResult, GenericError, GenericResult};
+use crate::consumer::{Consumer, ConsumerOptions, ShutdownTimeoutAction};
+use crate::mqtt::MqttPublisher;
+use crate::statsd::{Metric, StatsdClient};
+use crate::transmissionrpc::{self, BandwidthPriority, TransmissionClient, Torrent, TorrentStatus};
 use crate::util;
-use crate::util::time::{WeekPeriods, Timestamp};
+use crate::util::fs::{FreeSpace, FreeSpaceProvider};
+use crate::util::size::Size;
+use crate::util::time::{Clock, DateOverrides, SystemClock, WeekPeriods, Timestamp};
 
 pub struct Controller {
     action: Option<Action>,
     action_periods: WeekPeriods,
+    date_overrides: DateOverrides,
+    schedule_scope: ScheduleScope,
+    maintenance_periods: Option<WeekPeriods>,
+    in_maintenance: bool,
+    active_only_in_periods: bool,
+    managed_statuses: Option<Vec<TorrentStatus>>,
+    tracker_hosts: Vec<String>,
+    managed_label: Option<String>,
+    priority_rules: Vec<(String, BandwidthPriority)>,
+    // Hashes of the torrents to which the priority rules have been applied: the rules are applied only once to not
+    // override the priority changed manually by the user.
+    prioritized_torrents: HashSet<String>,
 
     download_dir: PathBuf,
+    path_remaps: Vec<(PathBuf, PathBuf)>,
+    deletable_dirs: Vec<PathBuf>,
+    removal_allowed: bool,
+    seed_location: Option<PathBuf>,
+    min_consume_size: Option<Size>,
+    max_consume_size: Option<Size>,
+    reconsume_if_missing: bool,
+    reconsume_check_time: Option<Timestamp>,
+    consume_delay: Option<util::time::Duration>,
+    // Time when the unprocessed torrents have been first seen downloaded
+    done_seen_times: HashMap<String, Timestamp>,
     free_space_threshold: Option<u8>,
+    free_space_headroom: u8,
+    free_space_check_retries: usize,
+    free_space_warn_threshold: Option<u8>,
+    free_space_warning_time: Option<Timestamp>,
+    max_removals_per_cycle: Option<usize>,
+    on_low_space: LowSpaceAction,
+    // Hashes of the torrents which have been paused due to low free space to resume them once it's back to normal
+    paused_for_space: HashSet<String>,
+    low_space_alerted: bool,
     upload_ratio_limit: Option<f64>,
     seed_time_limit: Option<util::time::Duration>,
+    max_age: Option<util::time::Duration>,
+    reannounce_interval: Option<util::time::Duration>,
+    // Time of the last reannounce by torrent hash
+    reannounce_times: HashMap<String, Timestamp>,
+    protect_private: bool,
+    min_other_seeders: Option<u64>,
+    remove_missing_data: bool,
+    // Number of the remaining control cycles during which decisions are only logged without making any changes
+    observe_cycles: usize,
+    // Number of consecutive successful control cycles required before removing or consuming any torrents
+    warmup_cycles: usize,
+    warmup_cycles_left: usize,
 
     client: Arc<TransmissionClient>,
     consumer: Consumer,
+    free_space_provider: Box<dyn FreeSpaceProvider>,
+    clock: Box<dyn Clock>,
+    mqtt_publisher: Option<Arc<MqttPublisher>>,
+    statsd_client: Option<StatsdClient>,
+    // Number of torrents removed since the last metrics report
+    removed_torrents: Cell<usize>,
+    // Usage of the download directory device last measured during the control cycle
+    device_usage: Cell<Option<u8>>,
+    // Devices for which low free space event has been published and free space hasn't recovered yet
+    low_space_devices: RefCell<HashSet<String>>,
 
     manual_time: Option<Instant>,
+    // Maps hashes of the torrents being relocated to their original download directories and relocation request times
+    relocating_torrents: HashMap<String, (String, Timestamp)>,
+    processed_markers_migrated: bool,
 }
 
-#[derive(Debug, PartialEq)]
+const DEVICE_USAGE_RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+const FREE_SPACE_WARNING_PERIOD: Timestamp = 6 * 60 * 60;
+const RECONSUME_CHECK_PERIOD: Timestamp = 60 * 60;
+const RELOCATION_TIMEOUT: Timestamp = 60 * 60;
+const CLEANUP_CANDIDATE_ACTION: &str = "cleanup-candidate";
+
+// Summary of the conditions evaluated for a torrent during the control cycle, logged to simplify
+// debugging of why the controller has or hasn't acted on the torrent
+struct TorrentDecision {
+    done: bool,
+    processed: bool,
+    consuming: bool,
+    missing_data: bool,
+    max_age_reached: bool,
+    ratio_reached: bool,
+    seed_time_elapsed: bool,
+    protected: bool,
+    schedule: &'static str,
+    action: &'static str,
+}
+
+impl fmt::Display for TorrentDecision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+            "done={} processed={} consuming={} missing_data={} max_age_reached={} ratio_reached={} \
+             seed_time_elapsed={} protected={} schedule={} action={}",
+            self.done, self.processed, self.consuming, self.missing_data, self.max_age_reached,
+            self.ratio_reached, self.seed_time_elapsed, self.protected, self.schedule, self.action)
+    }
+}
+
+// A device with the download directories which reside on it
+struct Device {
+    name: String,
+    usage: u8,
+    // Local path on the device to check its usage
+    path: PathBuf,
+    download_dirs: Vec<PathBuf>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 enum State {
     Active,
     Paused,
     Manual,
 }
 
+/// Specifies what to do when free space drops below the threshold.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LowSpaceAction {
+    /// Delete the downloaded torrents
+    Delete,
+    /// Pause the torrents which are being downloaded
+    Pause,
+    /// Only report the problem
+    Alert,
+}
+
+impl LowSpaceAction {
+    pub const ALL: [LowSpaceAction; 3] = [LowSpaceAction::Delete, LowSpaceAction::Pause, LowSpaceAction::Alert];
+
+    fn name(&self) -> &'static str {
+        match *self {
+            LowSpaceAction::Delete => "delete",
+            LowSpaceAction::Pause  => "pause",
+            LowSpaceAction::Alert  => "alert",
+        }
+    }
+}
+
+impl fmt::Display for LowSpaceAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for LowSpaceAction {
+    type Err = GenericError;
+
+    fn from_str(string: &str) -> Result<LowSpaceAction, GenericError> {
+        LowSpaceAction::ALL.iter()
+            .find(|action| action.name() == string)
+            .cloned()
+            .ok_or_else(|| format_to!("Invalid low space action: {:?}", string))
+    }
+}
+
+/// Specifies which torrents are paused/resumed according to the schedule.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ScheduleScope {
+    /// All torrents
+    All,
+    /// Only the torrents which are being downloaded
+    Download,
+    /// Only the downloaded torrents
+    Seed,
+}
+
+impl ScheduleScope {
+    pub const ALL: [ScheduleScope; 3] = [ScheduleScope::All, ScheduleScope::Download, ScheduleScope::Seed];
+
+    fn name(&self) -> &'static str {
+        match *self {
+            ScheduleScope::All      => "all",
+            ScheduleScope::Download => "download",
+            ScheduleScope::Seed     => "seed",
+        }
+    }
+
+    // Paused torrents have no downloading/seeding status, so the category is determined by
+    // torrent's completeness.
+    fn includes(&self, torrent: &Torrent) -> bool {
+        match *self {
+            ScheduleScope::All => true,
+            ScheduleScope::Download => !torrent.done,
+            ScheduleScope::Seed => torrent.done,
+        }
+    }
+}
+
+impl fmt::Display for ScheduleScope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for ScheduleScope {
+    type Err = GenericError;
+
+    fn from_str(string: &str) -> Result<ScheduleScope, GenericError> {
+        ScheduleScope::ALL.iter()
+            .find(|scope| scope.name() == string)
+            .cloned()
+            .ok_or_else(|| format_to!("Invalid schedule scope: {:?}", string))
+    }
+}
+
 #[derive(Copy, Clone)]
 pub enum Action {
     StartOrPause,
     PauseOrStart,
 }
 
+/// Controller settings. By default the torrents are only monitored without making any changes.
+pub struct ControllerOptions {
+    pub action: Option<Action>,
+    pub action_periods: WeekPeriods,
+    pub date_overrides: DateOverrides,
+    pub schedule_scope: ScheduleScope,
+    pub maintenance_periods: Option<WeekPeriods>,
+    pub active_only_in_periods: bool,
+    pub managed_statuses: Option<Vec<TorrentStatus>>,
+    pub tracker_hosts: Vec<String>,
+    pub managed_label: Option<String>,
+    pub priority_rules: Vec<(String, BandwidthPriority)>,
+
+    pub download_dir: PathBuf,
+    pub seed_location: Option<PathBuf>,
+    pub deletable_dirs: Vec<PathBuf>,
+    pub removal_allowed: bool,
+    pub min_consume_size: Option<Size>,
+    pub max_consume_size: Option<Size>,
+    pub consume_delay: Option<util::time::Duration>,
+    pub reconsume_if_missing: bool,
+
+    pub seed_time_limit: Option<util::time::Duration>,
+    pub max_age: Option<util::time::Duration>,
+    pub reannounce_interval: Option<util::time::Duration>,
+    pub upload_ratio_limit: Option<f64>,
+    pub protect_private: bool,
+    pub min_other_seeders: Option<u64>,
+    pub remove_missing_data: bool,
+    pub free_space_threshold: Option<u8>,
+    pub free_space_headroom: u8,
+    pub free_space_check_retries: usize,
+    pub free_space_warn_threshold: Option<u8>,
+    pub max_removals_per_cycle: Option<usize>,
+    pub on_low_space: LowSpaceAction,
+    pub observe_cycles: usize,
+    pub warmup_cycles: usize,
+
+    pub consumer: ConsumerOptions,
+}
+
+impl Default for ControllerOptions {
+    fn default() -> ControllerOptions {
+        ControllerOptions {
+            action: None,
+            action_periods: WeekPeriods::new(),
+            date_overrides: DateOverrides::new(),
+            schedule_scope: ScheduleScope::All,
+            maintenance_periods: None,
+            active_only_in_periods: false,
+            managed_statuses: None,
+            tracker_hosts: Vec::new(),
+            managed_label: None,
+            priority_rules: Vec::new(),
+
+            download_dir: PathBuf::new(),
+            seed_location: None,
+            deletable_dirs: Vec::new(),
+            removal_allowed: true,
+            min_consume_size: None,
+            max_consume_size: None,
+            consume_delay: None,
+            reconsume_if_missing: false,
+
+            seed_time_limit: None,
+            max_age: None,
+            reannounce_interval: None,
+            upload_ratio_limit: None,
+            protect_private: false,
+            min_other_seeders: None,
+            remove_missing_data: false,
+            free_space_threshold: None,
+            free_space_headroom: 0,
+            free_space_check_retries: 2,
+            free_space_warn_threshold: None,
+            max_removals_per_cycle: None,
+            on_low_space: LowSpaceAction::Delete,
+            observe_cycles: 0,
+            warmup_cycles: 0,
+
+            consumer: ConsumerOptions::default(),
+        }
+    }
+}
+
 impl Controller {
     pub fn new(
-        client: TransmissionClient, action: Option<Action>, action_periods: WeekPeriods,
-        download_dir: PathBuf, copy_to: Option<PathBuf>, move_to: Option<PathBuf>,
-        seed_time_limit: Option<util::time::Duration>, upload_ratio_limit: Option<f64>,
-        free_space_threshold: Option<u8>, notifications_mailer: Option<Mailer>,
-        torrent_downloaded_email_template: EmailTemplate,
+        client: TransmissionClient, options: ControllerOptions,
+        mqtt_publisher: Option<MqttPublisher>, statsd_client: Option<StatsdClient>,
     ) -> Controller {
+        let ControllerOptions {
+            action, action_periods, date_overrides, schedule_scope, maintenance_periods, active_only_in_periods,
+            managed_statuses, tracker_hosts, managed_label, priority_rules,
+            download_dir, seed_location, deletable_dirs, removal_allowed, min_consume_size, max_consume_size,
+            consume_delay, reconsume_if_missing,
+            seed_time_limit, max_age, reannounce_interval, upload_ratio_limit, protect_private, min_other_seeders,
+            remove_missing_data, free_space_threshold, free_space_headroom, free_space_check_retries,
+            free_space_warn_threshold, max_removals_per_cycle, on_low_space, observe_cycles, warmup_cycles,
+            consumer: consumer_options,
+        } = options;
+
         let client = Arc::new(client);
+        let mqtt_publisher = mqtt_publisher.map(Arc::new);
 
         Controller {
-            action, action_periods,
+            action, action_periods, date_overrides, schedule_scope, maintenance_periods, in_maintenance: false,
+            active_only_in_periods, managed_statuses, tracker_hosts, managed_label,
+            priority_rules, prioritized_torrents: HashSet::new(),
 
-            download_dir, free_space_threshold,
-            upload_ratio_limit, seed_time_limit,
+            download_dir, path_remaps: consumer_options.path_remaps.clone(), deletable_dirs, removal_allowed,
+            seed_location, min_consume_size, max_consume_size,
+            reconsume_if_missing, reconsume_check_time: None, consume_delay, done_seen_times: HashMap::new(),
+            free_space_threshold, free_space_headroom, free_space_check_retries,
+            free_space_warn_threshold, free_space_warning_time: None, max_removals_per_cycle,
+            on_low_space, paused_for_space: HashSet::new(), low_space_alerted: false,
+            upload_ratio_limit, seed_time_limit, max_age, reannounce_interval, reannounce_times: HashMap::new(),
+            protect_private, min_other_seeders, remove_missing_data, observe_cycles,
+            warmup_cycles, warmup_cycles_left: warmup_cycles,
 
             client: client.clone(),
-            consumer: Consumer::new(client, copy_to, move_to, notifications_mailer, torrent_downloaded_email_template),
+            consumer: Consumer::new(client, consumer_options, mqtt_publisher.clone()),
+            free_space_provider: Box::new(FreeSpace),
+            clock: Box::new(SystemClock),
+            mqtt_publisher,
+            statsd_client,
+            removed_torrents: Cell::new(0),
+            device_usage: Cell::new(None),
+            low_space_devices: RefCell::new(HashSet::new()),
 
             manual_time: None,
+            relocating_torrents: HashMap::new(),
+            processed_markers_migrated: false,
         }
     }
 
     pub fn control(&mut self) -> transmissionrpc::EmptyResult {
+        let in_maintenance = self.maintenance_periods.as_ref().is_some_and(|periods| {
+            util::time::is_in(periods, &self.clock.local_time())
+        });
+
+        if in_maintenance != self.in_maintenance {
+            if in_maintenance {
+                info!("Maintenance window has started: taking no actions.");
+            } else {
+                info!("Maintenance window is over.");
+            }
+
+            self.consumer.set_paused(in_maintenance);
+            self.in_maintenance = in_maintenance;
+        }
+
+        if in_maintenance {
+            return Ok(());
+        }
+
+        if self.observing() {
+            info!("Running in observe mode ({} cycles left): no changes will be made.", self.observe_cycles);
+        }
+
+        self.check_free_space_warning();
+        let result = self._control();
+
+        // Includes the calls made by the consumer thread since the previous cycle
+        let call_stats = self.client.take_call_stats();
+        debug!("RPC calls made during the cycle: {}.", if call_stats.is_empty() {
+            s!("none")
+        } else {
+            call_stats.iter().map(|(method, count)| format!("{}: {}", method, count)).join(", ")
+        });
+
+        if self.observing() {
+            self.observe_cycles -= 1;
+            if !self.observing() {
+                info!("Observe period is over. Switching to live operation.");
+            }
+        }
+
+        if self.warming_up() {
+            if result.is_ok() {
+                self.warmup_cycles_left -= 1;
+                if !self.warming_up() {
+                    info!("Warm-up is over. Enabling torrent removal and consuming.");
+                }
+            } else {
+                self.warmup_cycles_left = self.warmup_cycles;
+            }
+        }
+
+        result
+    }
+
+    fn _control(&mut self) -> transmissionrpc::EmptyResult {
+        // Done on the first successful cycle instead of startup, since Transmission may be unavailable at that moment
+        if !self.processed_markers_migrated {
+            self.client.migrate_processed_markers()?;
+            self.processed_markers_migrated = true;
+        }
+
+        self.device_usage.set(None);
+
         let state = self.calculate_state()?;
         debug!("Transmission daemon should be in {:?} state.", state);
 
+        let in_periods = !self.active_only_in_periods || self.is_in_action_periods(state);
+        if !in_periods {
+            debug!("Outside of the time periods: only pausing/resuming the torrents according to the schedule.");
+        }
+
         // Be careful here: we should get snapshot of current torrent status in exactly the
         // following order to not get into data race.
         let consuming_torrents = self.consumer.get_in_process();
-        let torrents = self.client.get_torrents()?;
+        let torrents: Vec<Torrent> = self.client.get_torrents()?.into_iter()
+            .filter(|torrent| self.is_managed(torrent))
+            .collect();
 
-        let mut removable_torrents = Vec::new();
+        let torrent_counts: Vec<(TorrentStatus, usize)> = TorrentStatus::ALL.iter().map(|&status| {
+            (status, torrents.iter().filter(|torrent| torrent.status == status).count())
+        }).collect();
 
-        for torrent in torrents {
-            debug!("Checking '{}' torrent...", torrent.name);
+        // The consume delay is counted from the moment since which the torrent is continuously done: it may
+        // become not done for a while when its file selection is changed.
+        let done_unprocessed_torrents: HashSet<String> = torrents.iter()
+            .filter(|torrent| torrent.done && !torrent.processed)
+            .map(|torrent| torrent.hash.clone())
+            .collect();
+        self.done_seen_times.retain(|hash, _| done_unprocessed_torrents.contains(hash));
 
-            if torrent.status == TorrentStatus::Paused && state == State::Active {
-                info!("Resuming '{}' torrent...", torrent.name);
-                self.client.start(&torrent.hash)?;
-            } else if torrent.status != TorrentStatus::Paused && state == State::Paused {
-                info!("Pausing '{}' torrent...", torrent.name);
-                self.client.stop(&torrent.hash)?;
-            }
+        let hashes: HashSet<&str> = torrents.iter().map(|torrent| torrent.hash.as_str()).collect();
+        self.prioritized_torrents.retain(|hash| hashes.contains(hash.as_str()));
+        self.relocating_torrents.retain(|hash, _| hashes.contains(hash.as_str()));
 
-            if !torrent.done || consuming_torrents.contains(&torrent.hash) {
-                continue;
+        let mut removable_torrents = Vec::new();
+        let mut downloading_torrents = Vec::new();
+        let mut space_paused_torrents = Vec::new();
+        // Calculated lazily, since it's needed only when there are paused torrents to download
+        let mut enough_free_space = None;
+
+        // Consumed data existence is checked only periodically to not stat the destination on each cycle
+        let check_missing_data = self.reconsume_if_missing && {
+            let now = self.clock.timestamp();
+            let check = self.reconsume_check_time.is_none_or(|time| now >= time);
+            if check {
+                self.reconsume_check_time = Some(now + RECONSUME_CHECK_PERIOD);
             }
+            check
+        };
 
-            if !torrent.processed {
-                info!("'{}' torrent has been downloaded.", torrent.name);
-                self.consumer.consume(&torrent.hash);
-                continue;
+        for torrent in torrents {
+            if torrent.done {
+                debug!("Checking {} torrent ({})...", torrent.id(), torrent.status);
+            } else {
+                debug!("Checking {} torrent ({}, ETA: {})...", torrent.id(), torrent.status, match torrent.eta() {
+                    Some(eta) => format!("{}s", eta.as_secs()),
+                    None => s!("unknown"),
+                });
             }
 
-            match (torrent.upload_ratio, self.upload_ratio_limit) {
-                (Some(ratio), Some(limit)) if ratio >= limit => {
-                    info!("'{}' torrent has seeded above upload ratio limit. Deleting it...", torrent.name);
-                    self.client.remove(&torrent.hash)?;
-                    continue;
+            let now = self.clock.timestamp();
+            let mut decision = TorrentDecision {
+                done: torrent.done,
+                processed: torrent.processed,
+                consuming: consuming_torrents.contains(&torrent.hash),
+                missing_data: torrent.missing_data,
+                max_age_reached: self.max_age.is_some_and(|max_age| now - torrent.added_time >= max_age),
+                ratio_reached: matches!((torrent.upload_ratio, self.upload_ratio_limit),
+                                        (Some(ratio), Some(limit)) if ratio >= limit),
+                seed_time_elapsed: match (self.seed_time_limit, torrent.done_time) {
+                    (Some(limit), Some(done_time)) => now - done_time >= limit,
+                    _ => false,
                 },
-                _ => {},
-            }
+                protected: self.protect_private && torrent.private,
+                schedule: "none",
+                action: "none",
+            };
 
-            if let Some(ref seed_time_limit) = self.seed_time_limit {
-                if OffsetDateTime::now_utc().unix_timestamp() - torrent.done_time.unwrap() >= *seed_time_limit {
-                    info!("'{}' torrent has seeded enough time to delete it. Deleting it...", torrent.name);
-                    self.client.remove(&torrent.hash)?;
-                    continue;
+            decision.action = 'control: {
+                if !in_periods {
+                    decision.schedule = self.schedule_torrent(&torrent, state, &mut enough_free_space)?;
+                    break 'control "out-of-periods";
+                }
+
+                if torrent.missing_data && self.remove_missing_data && !decision.consuming {
+                    info!("{} torrent's data has been deleted from the disk. Removing the torrent...", torrent.id());
+                    if self.remove_torrent(&torrent, false)? {
+                        self.publish("torrent-data-missing", json!({
+                            "hash": torrent.hash,
+                            "name": torrent.name,
+                        }));
+                    }
+                    break 'control "remove-missing-data";
+                }
+
+                // Don't touch the torrent while it's being consumed: its data is copied at this moment
+                if decision.max_age_reached && !decision.consuming {
+                    info!("{} torrent has reached the maximum age. Deleting it...", torrent.id());
+                    self.remove_torrent(&torrent, true)?;
+                    break 'control "remove-max-age";
+                }
+
+                decision.schedule = self.schedule_torrent(&torrent, state, &mut enough_free_space)?;
+
+                if !torrent.done {
+                    self.apply_priority_rules(&torrent)?;
+                }
+
+                if torrent.tracker_error && torrent.status != TorrentStatus::Paused {
+                    self.reannounce(&torrent)?;
+                }
+
+                if !torrent.done && torrent.status != TorrentStatus::Paused && (
+                    state != State::Paused || !self.schedule_scope.includes(&torrent)
+                ) {
+                    downloading_torrents.push(torrent.clone());
+                }
+
+                // The scheduled torrents are resumed by the schedule
+                if torrent.status == TorrentStatus::Paused && self.paused_for_space.contains(&torrent.hash) && (
+                    state == State::Manual || !self.schedule_scope.includes(&torrent)
+                ) {
+                    space_paused_torrents.push(torrent.clone());
+                }
+
+                if !torrent.done {
+                    break 'control "download";
+                } else if decision.consuming {
+                    break 'control "wait-consuming";
+                }
+
+                if !torrent.processed {
+                    // Let Transmission and external tools finish their post-download work with the files
+                    if let Some(delay) = self.consume_delay {
+                        let done_seen_time = *self.done_seen_times.entry(torrent.hash.clone()).or_insert(now);
+
+                        if now - done_seen_time < delay {
+                            debug!("Delaying consuming of {} torrent.", torrent.id());
+                            break 'control "delay-consume";
+                        }
+                    }
+
+                    if self.warming_up() {
+                        debug!("Not consuming {} torrent during warm-up.", torrent.id());
+                        break 'control "warm-up";
+                    }
+
+                    info!("{} torrent has been downloaded.", torrent.id());
+
+                    if !self.is_consumable_size(&torrent) {
+                        info!("{} torrent size ({} bytes) is out of consuming limits. \
+                               Marking it as processed without consuming...", torrent.id(), torrent.size);
+                        if !self.observing() {
+                            self.client.set_processed(&torrent)?;
+                        }
+                        break 'control "mark-processed";
+                    } else if self.observing() {
+                        info!("Skipping consuming of {} torrent in observe mode.", torrent.id());
+                    } else {
+                        self.consumer.consume(&torrent);
+                    }
+
+                    break 'control "consume";
+                }
+
+                if check_missing_data && self.is_consumable_size(&torrent) && self.is_consumed_data_missing(&torrent) {
+                    info!("{} torrent has been consumed, but its data is missing now. \
+                           Resetting its processed marker to consume it again...", torrent.id());
+                    if !self.observing() {
+                        self.client.reset_processed(&torrent)?;
+                        self.consumer.forget_consumed(&torrent.hash);
+                    }
+                    break 'control "reconsume";
+                }
+
+                if let Some(ref seed_location) = self.seed_location {
+                    if Path::new(&torrent.download_dir) != seed_location.as_path() {
+                        let now = self.clock.timestamp();
+
+                        // The move may fail without any notice, so request it again if it takes too long
+                        if let Some(&(_, request_time)) = self.relocating_torrents.get(&torrent.hash) {
+                            if now - request_time >= RELOCATION_TIMEOUT {
+                                warn!("{} torrent hasn't been moved to '{}' in time.",
+                                    torrent.id(), seed_location.display());
+                                self.relocating_torrents.remove(&torrent.hash);
+                            }
+                        }
+
+                        // Transmission moves the data asynchronously and changes the download directory
+                        // only when it's done, so don't request the move twice.
+                        if !self.relocating_torrents.contains_key(&torrent.hash) {
+                            info!("Moving {} torrent to '{}' to seed it from there...",
+                                torrent.id(), seed_location.display());
+                            if !self.observing() {
+                                self.client.set_location(&torrent, &seed_location.to_string_lossy(), true)?;
+                                self.relocating_torrents.insert(
+                                    torrent.hash.clone(), (torrent.download_dir.clone(), now));
+                            }
+                        }
+                        break 'control "relocate";
+                    }
+
+                    if let Some((old_location, _)) = self.relocating_torrents.remove(&torrent.hash) {
+                        self.client.remove_stale_processed_marker(&torrent.hash, &old_location)?;
+                    }
+                }
+
+                if decision.ratio_reached {
+                    info!("{} torrent has seeded above upload ratio limit. Deleting it...", torrent.id());
+                    self.remove_torrent(&torrent, true)?;
+                    break 'control "remove-ratio";
+                }
+
+                if self.seed_time_limit.is_some() {
+                    if decision.protected {
+                        debug!("Not checking seed time of {} torrent: it's private.", torrent.id());
+                    } else if decision.seed_time_elapsed {
+                        info!("{} torrent has seeded enough time to delete it. Deleting it...", torrent.id());
+                        self.remove_torrent(&torrent, true)?;
+                        break 'control "remove-seed-time";
+                    }
                 }
+
+                CLEANUP_CANDIDATE_ACTION
+            };
+
+            trace!("{} torrent decision: {}.", torrent.id(), decision);
+
+            if decision.action == CLEANUP_CANDIDATE_ACTION {
+                removable_torrents.push(torrent);
             }
+        }
 
-            removable_torrents.push(torrent);
+        if !in_periods {
+            self.send_metrics(&torrent_counts);
+            return Ok(());
         }
 
-        if let Err(e) = self.cleanup_fs(&removable_torrents) {
-            error!("Failed to cleanup the download directory: {}.", e)
+        match self.on_low_space {
+            LowSpaceAction::Delete => {
+                if let Err(e) = self.cleanup_fs(&removable_torrents) {
+                    error!("Failed to cleanup the download directory: {}.", e)
+                }
+            },
+
+            LowSpaceAction::Pause => {
+                // Forget the torrents which have been resumed or removed by someone else
+                self.paused_for_space.retain(|hash| space_paused_torrents.iter().any(|torrent| &torrent.hash == hash));
+
+                if !downloading_torrents.is_empty() || !space_paused_torrents.is_empty() {
+                    if !enough_free_space.unwrap_or_else(|| self.check_free_space(0)) {
+                        for torrent in &downloading_torrents {
+                            info!("Pausing {} torrent due to low free space...", torrent.id());
+                            if !self.observing() {
+                                self.client.stop(&torrent.hash)?;
+                                self.paused_for_space.insert(torrent.hash.clone());
+                            }
+                        }
+                    } else {
+                        for torrent in &space_paused_torrents {
+                            info!("Resuming {} torrent: free space is back to normal...", torrent.id());
+                            if !self.observing() {
+                                self.client.start(&torrent.hash)?;
+                                self.paused_for_space.remove(&torrent.hash);
+                            }
+                        }
+                    }
+                }
+            },
+
+            LowSpaceAction::Alert => {
+                let enough_free_space = enough_free_space.unwrap_or_else(|| self.check_free_space(0));
+
+                // Report only once until the free space is back to normal
+                if !enough_free_space && !self.low_space_alerted {
+                    error!("The free space on the disk is below the threshold. It must be freed manually.");
+                }
+                self.low_space_alerted = !enough_free_space;
+            },
         }
 
+        self.send_metrics(&torrent_counts);
+
         Ok(())
     }
 
+    // Pauses/resumes the torrent according to the schedule
+    fn schedule_torrent(
+        &self, torrent: &Torrent, state: State, enough_free_space: &mut Option<bool>,
+    ) -> transmissionrpc::Result<&'static str> {
+        if !self.schedule_scope.includes(torrent) {
+            debug!("Not scheduling {} torrent: it's out of the schedule scope.", torrent.id());
+            return Ok("out-of-scope");
+        }
+
+        Ok(if torrent.status == TorrentStatus::Paused && state == State::Active {
+            if !torrent.done && !*enough_free_space.get_or_insert_with(|| self.check_free_space(0)) {
+                info!("Not resuming {} torrent: downloads are held due to low free space.", torrent.id());
+                "hold"
+            } else {
+                info!("Resuming {} torrent...", torrent.id());
+                if !self.observing() {
+                    self.client.start(&torrent.hash)?;
+                }
+                "resume"
+            }
+        } else if torrent.status != TorrentStatus::Paused && state == State::Paused {
+            info!("Pausing {} torrent...", torrent.id());
+            if !self.observing() {
+                self.client.stop(&torrent.hash)?;
+            }
+            "pause"
+        } else {
+            "none"
+        })
+    }
+
+    // Date overrides take precedence over the time periods the same way as they do for the schedule
+    fn is_in_action_periods(&self, state: State) -> bool {
+        match (self.action, state) {
+            (Some(Action::StartOrPause), State::Active) | (Some(Action::PauseOrStart), State::Paused) => true,
+            (Some(_), State::Active | State::Paused) => false,
+            _ => util::time::is_in(&self.action_periods, &self.clock.local_time()),
+        }
+    }
+
+    fn send_metrics(&self, torrent_counts: &[(TorrentStatus, usize)]) {
+        let statsd_client = match self.statsd_client {
+            Some(ref client) => client,
+            None => return,
+        };
+
+        let status_metrics: Vec<(String, usize)> = torrent_counts.iter().map(|(status, count)| {
+            (format!("torrents.{}", status.to_string().to_lowercase()), *count)
+        }).collect();
+
+        let mut metrics: Vec<Metric> = status_metrics.iter()
+            .map(|(name, count)| Metric::Gauge(name, *count as u64))
+            .collect();
+
+        // Free space is measured only if it hasn't been checked during the cycle
+        match self.device_usage.take().map(Ok).unwrap_or_else(|| self.get_device_usage().map(|(_, usage)| usage)) {
+            Ok(usage) => metrics.push(Metric::Gauge("free_space", 100 - usage as u64)),
+            Err(e) => debug!("Free space is unknown: {}. Not reporting it.", e),
+        }
+
+        metrics.push(Metric::Counter("consumed", self.consumer.take_consumed_count() as u64));
+        metrics.push(Metric::Counter("removed", self.removed_torrents.replace(0) as u64));
+
+        statsd_client.send(&metrics);
+    }
+
+    pub fn is_consumer_alive(&self) -> bool {
+        self.consumer.is_alive()
+    }
+
+    pub fn request_shutdown(&self) {
+        self.consumer.request_stop();
+    }
+
+    pub fn shutdown(self, timeout: Option<std::time::Duration>, on_timeout: ShutdownTimeoutAction) {
+        self.consumer.stop(timeout, on_timeout);
+    }
+
+    fn observing(&self) -> bool {
+        self.observe_cycles != 0
+    }
+
+    // Transmission may return incomplete torrent list while it's starting, so don't act on it until
+    // it responds successfully for a few cycles in a row.
+    fn warming_up(&self) -> bool {
+        self.warmup_cycles_left != 0
+    }
+
+    fn is_managed(&self, torrent: &Torrent) -> bool {
+        if let Some(ref statuses) = self.managed_statuses {
+            if !statuses.contains(&torrent.status) {
+                debug!("Skipping {} torrent: it's in unmanaged {} status.", torrent.id(), torrent.status);
+                return false;
+            }
+        }
+
+        if !self.tracker_hosts.is_empty() && !torrent.tracker_hosts.iter().any(|torrent_host| {
+            self.tracker_hosts.iter().any(|host| {
+                torrent_host == host || torrent_host.ends_with(&format!(".{}", host))
+            })
+        }) {
+            debug!("Skipping {} torrent: it doesn't have trackers on managed hosts.", torrent.id());
+            return false;
+        }
+
+        if let Some(ref label) = self.managed_label {
+            if !torrent.labels.contains(label) {
+                debug!("Skipping {} torrent: it doesn't have {:?} label.", torrent.id(), label);
+                return false;
+            }
+        }
+
+        true
+    }
+
     fn calculate_state(&mut self) -> transmissionrpc::Result<State> {
         if self.action.is_none() {
             return Ok(State::Manual);
@@ -128,93 +843,1268 @@ impl Controller {
 
         if self.client.is_manual_mode()? {
             if let Some(manual_time) = self.manual_time {
-                if manual_time.elapsed() < Duration::days(1) {
+                if self.clock.instant().saturating_duration_since(manual_time) < Duration::days(1) {
                     return Ok(State::Manual);
                 }
 
                 error!("Reset outdated manual mode.");
-                self.client.set_manual_mode(false)?;
+                if !self.observing() {
+                    self.client.set_manual_mode(false)?;
+                }
             } else {
-                self.manual_time = Some(Instant::now());
+                self.manual_time = Some(self.clock.instant());
                 return Ok(State::Manual);
             }
         }
 
         self.manual_time = None;
 
-        Ok(match self.action.unwrap() {
-            Action::StartOrPause => {
-                if util::time::is_now_in(&self.action_periods) {
-                    State::Active
-                } else {
-                    State::Paused
-                }
-            }
-            Action::PauseOrStart => {
-                if util::time::is_now_in(&self.action_periods) {
-                    State::Paused
-                } else {
-                    State::Active
-                }
-            }
-        })
+        let now = self.clock.local_time();
+        if let Some(active) = util::time::get_date_override(&self.date_overrides, &now) {
+            debug!("Using the date override for today.");
+            return Ok(if active { State::Active } else { State::Paused });
+        }
+
+        Ok(get_scheduled_state(self.action.unwrap(), util::time::is_in(&self.action_periods, &now)))
     }
 
     fn cleanup_fs(&self, torrents: &[Torrent]) -> EmptyResult {
-        if torrents.is_empty() || self.check_free_space()? {
+        if torrents.is_empty() || self.free_space_threshold.is_none() {
             return Ok(());
         }
 
-        let mut torrents: Vec<_> = torrents.iter()
-            .filter(|&torrent| Path::new(&torrent.download_dir) == self.download_dir.as_path())
-            .collect();
+        let devices = match self.get_devices(torrents) {
+            Ok(devices) => devices,
+            Err(e) => {
+                // The download directory may be on a network mount which is temporary unavailable
+                warn!("Free space is unknown: {}. Skipping the cleanup.", e);
+                return Ok(());
+            },
+        };
 
-        torrents.sort_by(|a, b| {
-            let a = a.done_time.unwrap_or(Timestamp::MIN);
-            let b = b.done_time.unwrap_or(Timestamp::MAX);
-            a.cmp(&b)
-        });
+        let mut removed = 0;
+
+        // Each device is cleaned up independently: removal of torrents from one device doesn't
+        // free space on another one.
+        for device in devices {
+            if self.is_enough_free_space(&device.name, device.usage, 0) {
+                continue;
+            }
+
+            if self.warming_up() {
+                info!("Postponing the cleanup until the warm-up is over.");
+                return Ok(());
+            }
+
+            let torrents = get_cleanup_candidates(torrents, &device.download_dirs, self.protect_private);
+
+            for (id, torrent) in torrents.iter().enumerate() {
+                if self.max_removals_per_cycle.is_some_and(|max_removals| removed >= max_removals) {
+                    info!("Stopping the cleanup: the maximum number of torrent removals per cycle is reached.");
+                    return Ok(());
+                }
+
+                info!("Removing {} torrent to get a free space on {}...", torrent.id(), device.name);
+                if !self.remove_torrent(torrent, true)? {
+                    continue;
+                }
+                removed += 1;
 
-        for (id, torrent) in torrents.iter().enumerate() {
-            info!("Removing '{}' torrent to get a free space on the disk...", torrent.name);
-            self.client.remove(&torrent.hash)?;
+                // Free space doesn't change in observe mode, so we can't predict the subsequent removals
+                if self.observing() {
+                    break;
+                }
 
-            if id == torrents.len() - 1 || self.check_free_space()? {
-                break;
+                // Free some extra space to not remove one more torrent on each subsequent check
+                if id == torrents.len() - 1 || self.check_path_free_space(&device.path, self.free_space_headroom) {
+                    break;
+                }
             }
         }
 
         Ok(())
     }
 
-    fn check_free_space(&self) -> GenericResult<bool> {
+    // Groups download directories of the torrents by devices they reside on
+    fn get_devices(&self, torrents: &[Torrent]) -> GenericResult<Vec<Device>> {
+        let download_dirs: BTreeSet<&str> = torrents.iter()
+            .map(|torrent| torrent.download_dir.as_str())
+            .filter(|&download_dir| self.is_cleanable_dir(Path::new(download_dir)))
+            .collect();
+        let mut devices: Vec<Device> = Vec::new();
+
+        for download_dir in download_dirs {
+            let path = util::fs::remap_path(download_dir, &self.path_remaps);
+            let (name, usage) = self.get_path_usage(&path)?;
+
+            match devices.iter_mut().find(|device| device.name == name) {
+                Some(device) => device.download_dirs.push(PathBuf::from(download_dir)),
+                None => devices.push(Device {
+                    name, usage, path,
+                    download_dirs: vec![PathBuf::from(download_dir)],
+                }),
+            }
+        }
+
+        Ok(devices)
+    }
+
+    // Torrents outside of the download directory are cleaned up only if they reside in an explicitly
+    // specified deletable directory
+    fn is_cleanable_dir(&self, download_dir: &Path) -> bool {
+        download_dir == self.download_dir || self.deletable_dirs.iter().any(|dir| download_dir.starts_with(dir))
+    }
+
+    /// Describes which torrents would be removed by the cleanup in the current situation without
+    /// removing anything.
+    pub fn explain_cleanup(&self) -> GenericResult<Vec<String>> {
         let free_space_threshold = match self.free_space_threshold {
-            Some(value) => value,
-            None => return Ok(true),
+            Some(threshold) => threshold,
+            None => return Err!("Free space threshold is not configured"),
         };
 
-        let (device, usage) = util::fs::get_device_usage(&self.download_dir)?;
+        let torrents: Vec<Torrent> = self.client.get_torrents()?.into_iter()
+            .filter(|torrent| self.is_managed(torrent) && torrent.done && torrent.processed)
+            .collect();
 
-        let free_space = 100 - usage;
-        let needs_cleanup = free_space <= free_space_threshold;
+        let mut devices = self.get_devices(&torrents)?;
+        if devices.is_empty() {
+            let (name, usage) = self.get_device_usage()?;
+            devices.push(Device {
+                name, usage,
+                path: util::fs::remap_path(&self.download_dir, &self.path_remaps),
+                download_dirs: Vec::new(),
+            });
+        }
 
-        if needs_cleanup {
-            info!("We don't have enough free space on {}: {}% vs allowed > {}%.",
-                device, free_space, free_space_threshold)
+        let mut lines = Vec::new();
+
+        for device in devices {
+            let free_space = 100 - device.usage;
+
+            let mut candidates = get_cleanup_candidates(&torrents, &device.download_dirs, self.protect_private);
+            if let Some(max_removals) = self.max_removals_per_cycle {
+                candidates.truncate(max_removals);
+            }
+
+            let cleanup = if is_low_free_space(free_space, free_space_threshold) {
+                "will be triggered"
+            } else {
+                "won't be triggered now"
+            };
+
+            lines.push(format!("Free space on {}: {}% (threshold: {}%, headroom: {}%) - cleanup {}.",
+                device.name, free_space, free_space_threshold, self.free_space_headroom, cleanup));
+
+            if candidates.is_empty() {
+                lines.push(s!("There are no torrents to remove."));
+            } else {
+                lines.push(s!("Torrents will be removed in the following order until free space is enough:"));
+                lines.extend(candidates.iter().enumerate().map(|(index, torrent)| {
+                    format!("{}. {} ({} bytes, done at {})", index + 1, torrent.id(), torrent.size,
+                            torrent.done_time.map(format_timestamp).unwrap_or_else(|| s!("unknown time")))
+                }));
+            }
         }
 
-        Ok(!needs_cleanup)
+        Ok(lines)
     }
-}
 
-#[allow(clippy::to_string_trait_impl)]
-impl ToString for Action {
-    fn to_string(&self) -> String {
-        use self::Action::*;
+    // The first matching rule wins. "*" label matches any torrent.
+    fn apply_priority_rules(&mut self, torrent: &Torrent) -> transmissionrpc::EmptyResult {
+        if self.prioritized_torrents.contains(&torrent.hash) {
+            return Ok(());
+        }
 
-        s!(match *self {
-            StartOrPause => "start-or-pause",
-            PauseOrStart => "pause-or-start",
-        })
+        let priority = match self.priority_rules.iter().find(|(label, _)| {
+            label == "*" || torrent.labels.contains(label)
+        }) {
+            Some(&(_, priority)) => priority,
+            None => return Ok(()),
+        };
+
+        if torrent.bandwidth_priority != priority {
+            info!("Setting {} bandwidth priority for {} torrent...", priority, torrent.id());
+            if !self.observing() {
+                self.client.set_bandwidth_priority(&torrent.hash, priority)?;
+            }
+        }
+
+        if !self.observing() {
+            self.prioritized_torrents.insert(torrent.hash.clone());
+        }
+
+        Ok(())
+    }
+
+    // Trackers don't like too frequent announces, so reannounce is rate limited
+    fn reannounce(&mut self, torrent: &Torrent) -> transmissionrpc::EmptyResult {
+        let interval = match self.reannounce_interval {
+            Some(interval) => interval,
+            None => return Ok(()),
+        };
+
+        let now = self.clock.timestamp();
+        self.reannounce_times.retain(|_, time| now - *time < interval);
+
+        if self.reannounce_times.contains_key(&torrent.hash) {
+            debug!("Not reannouncing {} torrent: it has been reannounced recently.", torrent.id());
+            return Ok(());
+        }
+
+        info!("{} torrent has a tracker error. Reannouncing it...", torrent.id());
+        if !self.observing() {
+            self.client.reannounce(&torrent.hash)?;
+        }
+        self.reannounce_times.insert(torrent.hash.clone(), now);
+
+        Ok(())
+    }
+
+    fn remove_torrent(&self, torrent: &Torrent, delete_data: bool) -> transmissionrpc::Result<bool> {
+        if !self.removal_allowed {
+            debug!("Not removing {} torrent: torrent removal is disabled.", torrent.id());
+            return Ok(false);
+        }
+
+        if self.protect_private && torrent.private {
+            debug!("Not removing {} torrent: it's private.", torrent.id());
+            return Ok(false);
+        }
+
+        if !self.deletable_dirs.is_empty() {
+            let download_dir = Path::new(&torrent.download_dir);
+
+            if !self.deletable_dirs.iter().any(|dir| download_dir.starts_with(dir)) {
+                warn!("Refusing to remove {} torrent: its download directory ('{}') is not in the list of \
+                       deletable directories.", torrent.id(), torrent.download_dir);
+                return Ok(false);
+            }
+        }
+
+        // Keep the content alive if there are too few other seeders
+        if let Some(min_other_seeders) = self.min_other_seeders {
+            if delete_data && torrent.done {
+                // Tracker's seeder count includes us
+                match torrent.seeders.map(|seeders| seeders.saturating_sub(1)) {
+                    Some(other_seeders) if other_seeders < min_other_seeders => {
+                        info!("Not removing {} torrent: it has only {} other seeders.", torrent.id(), other_seeders);
+                        return Ok(false);
+                    },
+                    Some(_) => {},
+                    None => debug!("Seeder count of {} torrent is unknown.", torrent.id()),
+                }
+            }
+        }
+
+        if self.warming_up() {
+            info!("Not removing {} torrent during warm-up.", torrent.id());
+            return Ok(false);
+        }
+
+        if self.observing() {
+            info!("Not removing {} torrent in observe mode.", torrent.id());
+            return Ok(true);
+        }
+
+        self.client.remove(torrent, delete_data)?;
+        self.removed_torrents.set(self.removed_torrents.get() + 1);
+
+        self.publish("torrent-removed", json!({
+            "hash": torrent.hash,
+            "name": torrent.name,
+        }));
+
+        Ok(true)
+    }
+
+    fn check_free_space(&self, headroom: u8) -> bool {
+        let download_dir = util::fs::remap_path(&self.download_dir, &self.path_remaps);
+        self.check_path_free_space(&download_dir, headroom)
+    }
+
+    fn check_path_free_space(&self, path: &Path, headroom: u8) -> bool {
+        if self.free_space_threshold.is_none() {
+            return true;
+        }
+
+        let (device, usage) = match self.get_path_usage(path) {
+            Ok(usage) => usage,
+            Err(e) => {
+                // The download directory may be on a network mount which is temporary unavailable
+                warn!("Free space is unknown: {}. Skipping the cleanup.", e);
+                return true;
+            },
+        };
+
+        self.is_enough_free_space(&device, usage, headroom)
+    }
+
+    fn is_consumable_size(&self, torrent: &Torrent) -> bool {
+        self.min_consume_size.is_none_or(|size| torrent.size >= size) &&
+        self.max_consume_size.is_none_or(|size| torrent.size <= size)
+    }
+
+    // Only the torrents which have been copied by this process are checked: we can't reliably tell where the data
+    // of the others is (or whether it has been copied at all).
+    fn is_consumed_data_missing(&self, torrent: &Torrent) -> bool {
+        let paths = match self.consumer.get_consumed_paths(&torrent.hash) {
+            Some(paths) => paths,
+            None => return false,
+        };
+
+        paths.iter().all(|path| {
+            // Don't treat all consumed torrents as missing when the destination directory itself is unavailable
+            if !path.parent().is_some_and(Path::is_dir) {
+                return false;
+            }
+
+            match fs::symlink_metadata(path) {
+                Ok(_) => false,
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => true,
+                Err(e) => {
+                    warn!("Unable to check existence of '{}': {}.", path.display(), e);
+                    false
+                },
+            }
+        })
+    }
+
+    fn is_enough_free_space(&self, device: &str, usage: u8, headroom: u8) -> bool {
+        let free_space_threshold = match self.free_space_threshold {
+            Some(value) => value.saturating_add(headroom),
+            None => return true,
+        };
+
+        let free_space = 100 - usage;
+        let needs_cleanup = is_low_free_space(free_space, free_space_threshold);
+
+        if needs_cleanup {
+            info!("We don't have enough free space on {}: {}% vs required {}%.",
+                device, free_space, free_space_threshold);
+        }
+
+        // Publish the event only when the device gets low on free space. Headroom checks happen only
+        // during the cleanup, so they don't change the state.
+        if headroom == 0 {
+            let mut low_space_devices = self.low_space_devices.borrow_mut();
+
+            if !needs_cleanup {
+                low_space_devices.remove(device);
+            } else if low_space_devices.insert(s!(device)) {
+                self.publish("low-free-space", json!({
+                    "device": device,
+                    "free_space": free_space,
+                    "threshold": free_space_threshold,
+                }));
+            }
+        }
+
+        !needs_cleanup
+    }
+
+    // Warns in advance (but not too often) that free space is running low
+    fn check_free_space_warning(&mut self) -> bool {
+        let threshold = match self.free_space_warn_threshold {
+            Some(threshold) => threshold,
+            None => return false,
+        };
+
+        let (device, usage) = match self.get_device_usage() {
+            Ok(usage) => usage,
+            Err(e) => {
+                debug!("Free space is unknown: {}. Skipping the warning check.", e);
+                return false;
+            },
+        };
+
+        let free_space = 100 - usage;
+        if !is_low_free_space(free_space, threshold) {
+            self.free_space_warning_time = None;
+            return false;
+        }
+
+        let now = self.clock.timestamp();
+        if let Some(time) = self.free_space_warning_time {
+            if now < time + FREE_SPACE_WARNING_PERIOD {
+                return false;
+            }
+        }
+
+        error!("Free space on {} is running low: {}% vs warning threshold {}%.", device, free_space, threshold);
+        self.free_space_warning_time = Some(now);
+
+        true
+    }
+
+    fn get_device_usage(&self) -> GenericResult<(String, u8)> {
+        let download_dir = util::fs::remap_path(&self.download_dir, &self.path_remaps);
+        self.get_path_usage(&download_dir)
+    }
+
+    fn get_path_usage(&self, path: &Path) -> GenericResult<(String, u8)> {
+        let mut attempt = 0;
+
+        loop {
+            match self.free_space_provider.get_device_usage(path) {
+                Ok(usage) => {
+                    if path == util::fs::remap_path(&self.download_dir, &self.path_remaps) {
+                        self.device_usage.set(Some(usage.1));
+                    }
+                    return Ok(usage);
+                },
+                Err(e) if attempt < self.free_space_check_retries => {
+                    attempt += 1;
+                    debug!("Failed to get device usage for '{}': {}. Retrying...", path.display(), e);
+                    thread::sleep(DEVICE_USAGE_RETRY_DELAY);
+                },
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn publish(&self, event: &str, data: serde_json::Value) {
+        if let Some(ref mqtt_publisher) = self.mqtt_publisher {
+            mqtt_publisher.publish(event, data);
+        }
+    }
+}
+
+const DAY_NAMES: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+/// Selects the torrents which may be removed to free space on the disk in the order of their
+/// removal: the oldest downloaded ones go first.
+fn get_cleanup_candidates<'a>(
+    torrents: &'a [Torrent], download_dirs: &[PathBuf], protect_private: bool,
+) -> Vec<&'a Torrent> {
+    let mut torrents: Vec<_> = torrents.iter()
+        .filter(|&torrent| download_dirs.iter().any(|dir| dir == Path::new(&torrent.download_dir)))
+        .filter(|&torrent| !(protect_private && torrent.private))
+        .collect();
+
+    torrents.sort_by(|a, b| {
+        let a = a.done_time.unwrap_or(Timestamp::MIN);
+        let b = b.done_time.unwrap_or(Timestamp::MAX);
+        a.cmp(&b)
+    });
+
+    torrents
+}
+
+// All free space thresholds are satisfied when free space is exactly at the threshold
+fn is_low_free_space(free_space: u8, threshold: u8) -> bool {
+    free_space < threshold
+}
+
+fn format_timestamp(timestamp: Timestamp) -> String {
+    let time = legacy_time::at(legacy_time::Timespec::new(timestamp, 0));
+    format!("{}-{:02}-{:02} {:02}:{:02}",
+        time.tm_year + 1900, time.tm_mon + 1, time.tm_mday, time.tm_hour, time.tm_min)
+}
+
+fn get_scheduled_state(action: Action, in_period: bool) -> State {
+    match (action, in_period) {
+        (Action::StartOrPause, true) | (Action::PauseOrStart, false) => State::Active,
+        (Action::StartOrPause, false) | (Action::PauseOrStart, true) => State::Paused,
+    }
+}
+
+/// Renders the weekly schedule as it's interpreted by the controller: one line per day of week.
+#[allow(clippy::ptr_arg)]
+pub fn format_schedule(action: Action, periods: &WeekPeriods) -> Vec<String> {
+    // Monday-Sunday order
+    (1..8).map(|day| day % 7).map(|day| {
+        let intervals = util::time::split_day(&periods[day]).iter().map(|&(period, in_period)| {
+            format!("{}-{} {:?}", period.start, period.end, get_scheduled_state(action, in_period))
+        }).join(", ");
+
+        format!("{}: {}", DAY_NAMES[day], intervals)
+    }).collect()
+}
+
+/// Describes the state which the schedule dictates at the current moment (ignoring manual mode).
+#[allow(clippy::ptr_arg)]
+pub fn format_current_state(action: Action, periods: &WeekPeriods, date_overrides: &DateOverrides) -> String {
+    let now = legacy_time::now();
+
+    if let Some(active) = util::time::get_date_override(date_overrides, &now) {
+        return format!("Today ({}-{:02}-{:02}) is overridden, so torrents should be in {:?} state.",
+            now.tm_year + 1900, now.tm_mon + 1, now.tm_mday, if active { State::Active } else { State::Paused });
+    }
+
+    let in_period = util::time::is_in(periods, &now);
+
+    format!("Now is {} {:02}:{:02} (local time): {} the periods, so torrents should be in {:?} state.",
+        DAY_NAMES[now.tm_wday as usize], now.tm_hour, now.tm_min,
+        if in_period { "inside" } else { "outside" }, get_scheduled_state(action, in_period))
+}
+
+#[allow(clippy::to_string_trait_impl)]
+impl ToString for Action {
+    fn to_string(&self) -> String {
+        use self::Action::*;
+
+        s!(match *self {
+            StartOrPause => "start-or-pause",
+            PauseOrStart => "pause-or-start",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+    use std::collections::VecDeque;
+
+    use reqwest::StatusCode;
+    use serde_json::json;
+
+    use crate::transmissionrpc::tests::{mock_client, mock_torrent, success};
+    use crate::util::fs::tests::create_temp_dir;
+
+    use super::*;
+
+    struct FreeSpaceMock {
+        usage: RefCell<VecDeque<u8>>,
+    }
+
+    impl FreeSpaceProvider for FreeSpaceMock {
+        fn get_device_usage(&self, _path: &Path) -> GenericResult<(String, u8)> {
+            let usage = self.usage.borrow_mut().pop_front().expect("Got an unexpected device usage request");
+            Ok((s!("/dev/sda1"), usage))
+        }
+    }
+
+    // Serves the expected device usage requests in order
+    struct DevicesMock {
+        requests: RefCell<VecDeque<(&'static str, &'static str, u8)>>,
+    }
+
+    impl FreeSpaceProvider for DevicesMock {
+        fn get_device_usage(&self, path: &Path) -> GenericResult<(String, u8)> {
+            let (expected_path, device, usage) = self.requests.borrow_mut().pop_front()
+                .expect("Got an unexpected device usage request");
+            assert_eq!(path, Path::new(expected_path));
+            Ok((s!(device), usage))
+        }
+    }
+
+    struct ClockMock {
+        start: Instant,
+        elapsed: Rc<Cell<u64>>,
+    }
+
+    impl Clock for ClockMock {
+        fn timestamp(&self) -> Timestamp {
+            START_TIMESTAMP + self.elapsed.get() as Timestamp
+        }
+
+        fn instant(&self) -> Instant {
+            self.start + std::time::Duration::from_secs(self.elapsed.get())
+        }
+
+        fn local_time(&self) -> legacy_time::Tm {
+            // Monday, 12:00
+            legacy_time::Tm { tm_year: 124, tm_mon: 0, tm_mday: 1, tm_wday: 1, tm_hour: 12, .. legacy_time::empty_tm() }
+        }
+    }
+
+    const START_TIMESTAMP: Timestamp = 1_700_000_000;
+
+    fn mock_clock(controller: &mut Controller) -> Rc<Cell<u64>> {
+        let elapsed = Rc::new(Cell::new(0));
+        controller.clock = Box::new(ClockMock {
+            start: Instant::now(),
+            elapsed: elapsed.clone(),
+        });
+        elapsed
+    }
+
+    fn get_methods(requests: &[(Option<String>, serde_json::Value)]) -> Vec<String> {
+        requests.iter().map(|(_, request)| s!(request["method"].as_str().unwrap())).collect()
+    }
+
+    fn new_controller(client: TransmissionClient, free_space_threshold: Option<u8>, usage: Vec<u8>) -> Controller {
+        let mut controller = Controller::new(client, ControllerOptions {
+            action_periods: vec![Vec::new(); 7],
+            download_dir: PathBuf::from("/downloads"),
+            free_space_threshold,
+            free_space_check_retries: 0,
+            consumer: ConsumerOptions {
+                not_found_retries: 0,
+                not_found_retry_delay: std::time::Duration::ZERO,
+                ..Default::default()
+            },
+            ..Default::default()
+        }, None, None);
+
+        controller.free_space_provider = Box::new(FreeSpaceMock {
+            usage: RefCell::new(usage.into()),
+        });
+
+        controller
+    }
+
+    fn new_torrent(hash: &str, done_time: Timestamp) -> Torrent {
+        Torrent {done_time: Some(done_time), processed: true, ..mock_torrent(hash)}
+    }
+
+    fn get_removed_torrents(requests: &[(Option<String>, serde_json::Value)]) -> Vec<String> {
+        requests.iter().map(|(_, request)| {
+            assert_eq!(request["method"], "torrent-remove");
+            s!(request["arguments"]["ids"][0].as_str().unwrap())
+        }).collect()
+    }
+
+    fn new_transmission_torrent(hash: &str, left_until_done: u64) -> serde_json::Value {
+        json!({
+            "hashString": hash,
+            "name": hash,
+            "downloadDir": "/downloads",
+            "status": 0,
+            "addedDate": 1000,
+            "wanted": [1],
+            "leftUntilDone": left_until_done,
+            "sizeWhenDone": 4096,
+            "doneDate": 0,
+            "downloadLimit": 42,
+            "uploadRatio": 0,
+            "isPrivate": false,
+            "error": 0,
+            "errorString": "",
+            "trackers": [],
+        })
+    }
+
+    #[test]
+    fn test_resume_on_low_free_space() {
+        let (client, requests) = mock_client(vec![
+            success(json!({"alt-speed-enabled": false})),
+            success(json!({"torrents": [
+                new_transmission_torrent("downloading", 1024),
+                new_transmission_torrent("seeding", 0),
+            ]})),
+            success(json!({})),
+        ]);
+
+        let mut controller = new_controller(client, Some(10), vec![95, 95]);
+        controller.action = Some(Action::StartOrPause);
+        controller.action_periods = util::time::parse_periods(&[s!("1-7/0:00-24:00")]).unwrap();
+        controller.removal_allowed = false;
+        controller.control().unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[2].1, json!({"method": "torrent-start", "arguments": {"ids": ["seeding"]}}));
+    }
+
+    #[test]
+    fn test_seed_time_limit() {
+        let mut torrent = new_transmission_torrent("some-hash", 0);
+        torrent["doneDate"] = json!(START_TIMESTAMP - 60);
+
+        let (client, requests) = mock_client(vec![
+            success(json!({"torrents": [torrent.clone()]})),
+            success(json!({"torrents": [torrent]})),
+            success(json!({})),
+        ]);
+
+        let mut controller = new_controller(client, None, vec![]);
+        controller.seed_time_limit = Some(60 * 60);
+        let elapsed = mock_clock(&mut controller);
+
+        controller.control().unwrap();
+        assert_eq!(get_methods(&requests.lock().unwrap()), vec!["torrent-get"]);
+
+        elapsed.set(60 * 60 - 60);
+        controller.control().unwrap();
+        assert_eq!(get_methods(&requests.lock().unwrap()), vec!["torrent-get", "torrent-get", "torrent-remove"]);
+    }
+
+    #[test]
+    fn test_warmup_cycles() {
+        let mut torrent = new_transmission_torrent("some-hash", 0);
+        torrent["doneDate"] = json!(START_TIMESTAMP - 60 * 60);
+        let torrents = || success(json!({"torrents": [torrent.clone()]}));
+
+        let (client, requests) = mock_client(vec![
+            torrents(),
+            (StatusCode::OK, None, json!({"result": "some error"})),
+            torrents(), torrents(),
+            torrents(), success(json!({})),
+        ]);
+
+        let mut controller = new_controller(client, None, vec![]);
+        controller.seed_time_limit = Some(60);
+        controller.warmup_cycles = 2;
+        controller.warmup_cycles_left = 2;
+        mock_clock(&mut controller);
+
+        // A failed cycle restarts the warm-up
+        controller.control().unwrap();
+        controller.control().unwrap_err();
+        controller.control().unwrap();
+        controller.control().unwrap();
+        assert_eq!(get_methods(&requests.lock().unwrap()), vec!["torrent-get"; 4]);
+
+        controller.control().unwrap();
+        assert_eq!(get_methods(&requests.lock().unwrap())[4..], ["torrent-get", "torrent-remove"]);
+    }
+
+    #[test]
+    fn test_schedule_scope() {
+        let mut seeding = new_transmission_torrent("seeding", 0);
+        seeding["status"] = json!(TorrentStatus::Seeding as u8);
+
+        let mut downloading = new_transmission_torrent("downloading", 1024);
+        downloading["status"] = json!(TorrentStatus::Downloading as u8);
+
+        let (client, requests) = mock_client(vec![
+            success(json!({"alt-speed-enabled": false})),
+            success(json!({"torrents": [seeding, downloading]})),
+            success(json!({})),
+        ]);
+
+        let mut controller = new_controller(client, None, vec![]);
+        controller.action = Some(Action::PauseOrStart);
+        controller.action_periods = util::time::parse_periods(&[s!("1-7/0:00-24:00")]).unwrap();
+        controller.schedule_scope = ScheduleScope::Download;
+        mock_clock(&mut controller);
+        controller.control().unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(get_methods(&requests), vec!["session-get", "torrent-get", "torrent-stop"]);
+        assert_eq!(requests[2].1["arguments"], json!({"ids": ["downloading"]}));
+    }
+
+    #[test]
+    fn test_maintenance_period() {
+        let (client, requests) = mock_client(vec![success(json!({"torrents": []}))]);
+
+        let mut controller = new_controller(client, None, vec![]);
+        controller.maintenance_periods = Some(util::time::parse_periods(&[s!("1-1/11:00-13:00")]).unwrap());
+        mock_clock(&mut controller);
+
+        controller.control().unwrap();
+        assert!(controller.in_maintenance);
+        assert!(requests.lock().unwrap().is_empty());
+
+        controller.maintenance_periods = Some(util::time::parse_periods(&[s!("1-1/13:00-14:00")]).unwrap());
+        controller.control().unwrap();
+        assert!(!controller.in_maintenance);
+        assert_eq!(get_methods(&requests.lock().unwrap()), vec!["torrent-get"]);
+    }
+
+    #[test]
+    fn test_active_only_in_periods() {
+        let mut downloading = new_transmission_torrent("downloading", 1024);
+        downloading["status"] = json!(TorrentStatus::Downloading as u8);
+
+        let mut paused = downloading.clone();
+        paused["status"] = json!(TorrentStatus::Paused as u8);
+
+        let mut missing_data = new_transmission_torrent("missing-data", 0);
+        missing_data["error"] = json!(3);
+        missing_data["errorString"] = json!("No data found! Ensure your drives are connected.");
+
+        let manual_mode = || success(json!({"alt-speed-enabled": false}));
+        let (client, requests) = mock_client(vec![
+            manual_mode(), success(json!({"torrents": [downloading, missing_data.clone()]})), success(json!({})),
+            manual_mode(), success(json!({"torrents": [paused, missing_data]})), success(json!({})), success(json!({})),
+        ]);
+
+        let mut controller = new_controller(client, None, vec![]);
+        controller.action = Some(Action::StartOrPause);
+        controller.action_periods = util::time::parse_periods(&[s!("1-1/13:00-14:00")]).unwrap();
+        controller.active_only_in_periods = true;
+        controller.remove_missing_data = true;
+        mock_clock(&mut controller);
+
+        // Outside of the time periods the torrents are only paused according to the schedule
+        controller.control().unwrap();
+        assert_eq!(get_methods(&requests.lock().unwrap()), vec!["session-get", "torrent-get", "torrent-stop"]);
+
+        let (date, active) = util::time::parse_date_override("2024-01-01:active").unwrap();
+        controller.date_overrides.insert(date, active);
+
+        controller.control().unwrap();
+        assert_eq!(get_methods(&requests.lock().unwrap()), vec![
+            "session-get", "torrent-get", "torrent-stop",
+            "session-get", "torrent-get", "torrent-start", "torrent-remove"]);
+    }
+
+    #[test]
+    fn test_manual_mode_reset() {
+        let manual_mode = || success(json!({"alt-speed-enabled": true}));
+        let no_torrents = || success(json!({"torrents": []}));
+
+        let (client, requests) = mock_client(vec![
+            manual_mode(), no_torrents(),
+            manual_mode(), no_torrents(),
+            manual_mode(), success(json!({})), no_torrents(),
+        ]);
+
+        let mut controller = new_controller(client, None, vec![]);
+        controller.action = Some(Action::StartOrPause);
+        controller.action_periods = util::time::parse_periods(&[s!("1-7/0:00-24:00")]).unwrap();
+        let elapsed = mock_clock(&mut controller);
+
+        controller.control().unwrap();
+        assert!(controller.manual_time.is_some());
+
+        elapsed.set(24 * 60 * 60 - 1);
+        controller.control().unwrap();
+
+        elapsed.set(24 * 60 * 60);
+        controller.control().unwrap();
+        assert!(controller.manual_time.is_none());
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(get_methods(&requests), vec![
+            "session-get", "torrent-get",
+            "session-get", "torrent-get",
+            "session-get", "session-set", "torrent-get",
+        ]);
+        assert_eq!(requests[5].1["arguments"], json!({"alt-speed-enabled": false}));
+    }
+
+    #[test]
+    fn test_free_space_warning() {
+        let (client, _) = mock_client(Vec::new());
+        let mut controller = new_controller(client, Some(5), vec![90, 90, 90, 80, 90]);
+        controller.free_space_warn_threshold = Some(15);
+        let elapsed = mock_clock(&mut controller);
+
+        assert!(controller.check_free_space_warning());
+        assert!(!controller.check_free_space_warning());
+
+        elapsed.set(FREE_SPACE_WARNING_PERIOD as u64);
+        assert!(controller.check_free_space_warning());
+
+        // Free space has been restored, so the next drop is reported immediately
+        assert!(!controller.check_free_space_warning());
+        assert!(controller.check_free_space_warning());
+    }
+
+    #[test]
+    fn test_free_space_threshold_boundary() {
+        let (client, _) = mock_client(Vec::new());
+        let mut controller = new_controller(client, Some(10), vec![90, 91, 85, 86]);
+        controller.free_space_warn_threshold = Some(15);
+
+        assert!(controller.check_free_space(0));
+        assert!(!controller.check_free_space(0));
+
+        assert!(!controller.check_free_space_warning());
+        assert!(controller.check_free_space_warning());
+    }
+
+    #[test]
+    fn test_reannounce() {
+        let mut torrent = new_transmission_torrent("some-hash", 0);
+        torrent["status"] = json!(TorrentStatus::Seeding as u8);
+        torrent["error"] = json!(2);
+        torrent["errorString"] = json!("Tracker is unavailable");
+
+        let (client, requests) = mock_client(vec![
+            success(json!({"torrents": [torrent.clone()]})), success(json!({})),
+            success(json!({"torrents": [torrent.clone()]})),
+            success(json!({"torrents": [torrent.clone()]})), success(json!({})),
+        ]);
+
+        let mut controller = new_controller(client, None, vec![]);
+        controller.reannounce_interval = Some(60 * 60);
+        let elapsed = mock_clock(&mut controller);
+
+        controller.control().unwrap();
+
+        elapsed.set(60 * 60 - 1);
+        controller.control().unwrap();
+
+        elapsed.set(60 * 60);
+        controller.control().unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(get_methods(&requests), vec![
+            "torrent-get", "torrent-reannounce",
+            "torrent-get",
+            "torrent-get", "torrent-reannounce",
+        ]);
+    }
+
+    #[test]
+    fn test_statsd_metrics() {
+        let mut seeding = new_transmission_torrent("seeding", 0);
+        seeding["status"] = json!(TorrentStatus::Seeding as u8);
+
+        let (client, _) = mock_client(vec![success(json!({"torrents": [seeding]}))]);
+
+        let server = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        server.set_read_timeout(Some(std::time::Duration::from_secs(5))).unwrap();
+
+        // Free space is checked during the cleanup and isn't measured again for the metrics
+        let mut controller = new_controller(client, Some(10), vec![90]);
+        controller.statsd_client = Some(StatsdClient::new(&server.local_addr().unwrap().to_string(), "tc").unwrap());
+        controller.control().unwrap();
+
+        let mut buffer = [0; 1024];
+        let size = server.recv(&mut buffer).unwrap();
+        let metrics = std::str::from_utf8(&buffer[..size]).unwrap();
+
+        assert_eq!(metrics.lines().collect::<Vec<_>>(), vec![
+            "tc.torrents.paused:0|g", "tc.torrents.checkwait:0|g", "tc.torrents.checking:0|g",
+            "tc.torrents.downloadwait:0|g", "tc.torrents.downloading:0|g", "tc.torrents.seedwait:0|g",
+            "tc.torrents.seeding:1|g", "tc.free_space:10|g", "tc.consumed:0|c", "tc.removed:0|c",
+        ]);
+    }
+
+    #[test]
+    fn test_consume_delay() {
+        let mut torrent = new_transmission_torrent("some-hash", 0);
+        torrent["downloadLimit"] = json!(0);
+        torrent["status"] = json!(TorrentStatus::Seeding as u8);
+        torrent["sizeWhenDone"] = json!(0);
+
+        let (client, requests) = mock_client(vec![
+            success(json!({"torrents": [torrent.clone()]})),
+            success(json!({"torrents": [torrent.clone()]})),
+            success(json!({"torrents": [torrent.clone()]})), success(json!({})),
+        ]);
+
+        let mut controller = new_controller(client, None, vec![]);
+        controller.consume_delay = Some(60);
+        controller.min_consume_size = Some(1);
+        let elapsed = mock_clock(&mut controller);
+
+        controller.control().unwrap();
+
+        elapsed.set(59);
+        controller.control().unwrap();
+
+        // The torrent is out of consuming limits, so it's marked as processed without consuming
+        elapsed.set(60);
+        controller.control().unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(get_methods(&requests), vec!["torrent-get", "torrent-get", "torrent-get", "torrent-set"]);
+    }
+
+    #[test]
+    fn test_consume_delay_reset() {
+        let mut torrent = new_transmission_torrent("some-hash", 0);
+        torrent["downloadLimit"] = json!(0);
+        torrent["status"] = json!(TorrentStatus::Seeding as u8);
+        torrent["sizeWhenDone"] = json!(0);
+
+        let mut downloading_torrent = torrent.clone();
+        downloading_torrent["leftUntilDone"] = json!(1024);
+        downloading_torrent["status"] = json!(TorrentStatus::Downloading as u8);
+
+        let (client, requests) = mock_client(vec![
+            success(json!({"torrents": [torrent.clone()]})),
+            success(json!({"torrents": [downloading_torrent]})),
+            success(json!({"torrents": [torrent.clone()]})),
+            success(json!({"torrents": [torrent.clone()]})),
+            success(json!({"torrents": [torrent.clone()]})), success(json!({})),
+        ]);
+
+        let mut controller = new_controller(client, None, vec![]);
+        controller.consume_delay = Some(60);
+        controller.min_consume_size = Some(1);
+        let elapsed = mock_clock(&mut controller);
+
+        controller.control().unwrap();
+
+        // The torrent's file selection has been changed, so the delay starts over
+        elapsed.set(30);
+        controller.control().unwrap();
+
+        elapsed.set(40);
+        controller.control().unwrap();
+
+        elapsed.set(99);
+        controller.control().unwrap();
+
+        elapsed.set(100);
+        controller.control().unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(get_methods(&requests), vec![
+            "torrent-get", "torrent-get", "torrent-get", "torrent-get", "torrent-get", "torrent-set"]);
+    }
+
+    #[test]
+    fn test_priority_rules() {
+        let mut high = new_transmission_torrent("high", 1024);
+        high["labels"] = json!(["movies", "important"]);
+
+        let mut normal = new_transmission_torrent("normal", 1024);
+        normal["bandwidthPriority"] = json!(-1);
+
+        let mut low = new_transmission_torrent("low", 1024);
+        low["bandwidthPriority"] = json!(-1);
+        low["labels"] = json!(["movies"]);
+
+        let torrents = json!({"torrents": [high, normal, low, new_transmission_torrent("done", 0)]});
+        let (client, requests) = mock_client(vec![
+            success(torrents.clone()), success(json!({})), success(json!({})),
+            success(torrents),
+        ]);
+
+        let mut controller = new_controller(client, None, vec![]);
+        controller.priority_rules = vec![
+            (s!("important"), BandwidthPriority::High),
+            (s!("movies"), BandwidthPriority::Low),
+            (s!("*"), BandwidthPriority::Normal),
+        ];
+        controller.control().unwrap();
+
+        // The priority changed manually by the user is preserved
+        controller.control().unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.iter().skip(1).take(2).map(|(_, request)| {
+            request["arguments"].clone()
+        }).collect::<Vec<_>>(), vec![
+            json!({"ids": ["high"], "bandwidthPriority": 1}),
+            json!({"ids": ["normal"], "bandwidthPriority": 0}),
+        ]);
+        assert_eq!(requests.len(), 4);
+    }
+
+    #[test]
+    fn test_pause_on_low_space() {
+        let mut downloading = new_transmission_torrent("downloading", 1024);
+        downloading["status"] = json!(TorrentStatus::Downloading as u8);
+
+        let mut paused = downloading.clone();
+        paused["status"] = json!(TorrentStatus::Paused as u8);
+
+        let mut manually_paused = new_transmission_torrent("manually-paused", 1024);
+        manually_paused["status"] = json!(TorrentStatus::Paused as u8);
+
+        let (client, requests) = mock_client(vec![
+            success(json!({"torrents": [
+                downloading, manually_paused.clone(), new_transmission_torrent("seeding", 0),
+            ]})),
+            success(json!({})),
+            success(json!({"torrents": [paused.clone(), manually_paused.clone()]})),
+            success(json!({"torrents": [paused, manually_paused]})),
+            success(json!({})),
+        ]);
+
+        let mut controller = new_controller(client, Some(10), vec![95, 95, 50]);
+        controller.on_low_space = LowSpaceAction::Pause;
+        controller.control().unwrap();
+        controller.control().unwrap();
+
+        // Only the torrents paused due to low free space are resumed when it's back to normal
+        controller.control().unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(get_methods(&requests), vec![
+            "torrent-get", "torrent-stop", "torrent-get", "torrent-get", "torrent-start"]);
+        assert_eq!(requests[1].1["arguments"], json!({"ids": ["downloading"]}));
+        assert_eq!(requests[4].1["arguments"], json!({"ids": ["downloading"]}));
+    }
+
+    #[test]
+    fn test_reconsume_if_missing() {
+        let mut torrent = new_transmission_torrent("some-hash", 0);
+        torrent["status"] = json!(TorrentStatus::Seeding as u8);
+
+        let (client, requests) = mock_client(vec![
+            success(json!({"torrents": [torrent.clone()]})),
+            success(json!({"torrents": [torrent.clone()]})),
+            success(json!({"torrents": [torrent.clone()]})), success(json!({})),
+            success(json!({"torrents": [torrent.clone()]})),
+        ]);
+
+        let mut controller = new_controller(client, None, vec![]);
+        controller.reconsume_if_missing = true;
+        controller.observe_cycles = 1;
+        let elapsed = mock_clock(&mut controller);
+
+        let missing_path = create_temp_dir("reconsume").join("missing");
+        controller.consumer.add_consumed("some-hash", vec![missing_path]);
+
+        // Nothing is changed in observe mode
+        controller.control().unwrap();
+
+        // The data is checked only once per hour
+        elapsed.set(RECONSUME_CHECK_PERIOD as u64 - 1);
+        controller.control().unwrap();
+
+        elapsed.set(RECONSUME_CHECK_PERIOD as u64);
+        controller.control().unwrap();
+        assert!(controller.consumer.get_consumed_paths("some-hash").is_none());
+
+        // The torrents which haven't been copied by the controller are never reset
+        elapsed.set(2 * RECONSUME_CHECK_PERIOD as u64);
+        controller.control().unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(get_methods(&requests), vec![
+            "torrent-get", "torrent-get", "torrent-get", "torrent-set", "torrent-get"]);
+        assert_eq!(requests[3].1["arguments"], json!({"ids": ["some-hash"], "downloadLimit": 0}));
+    }
+
+    #[test]
+    fn test_relocation_timeout() {
+        let mut torrent = new_transmission_torrent("some-hash", 0);
+        torrent["status"] = json!(TorrentStatus::Seeding as u8);
+
+        let mut relocated = torrent.clone();
+        relocated["downloadDir"] = json!("/seeding");
+
+        let (client, requests) = mock_client(vec![
+            success(json!({"torrents": [torrent.clone()]})), success(json!({})),
+            success(json!({"torrents": [torrent.clone()]})),
+            success(json!({"torrents": [torrent.clone()]})), success(json!({})),
+            success(json!({"torrents": [relocated]})),
+        ]);
+
+        let mut controller = new_controller(client, None, vec![]);
+        controller.seed_location = Some(PathBuf::from("/seeding"));
+        let elapsed = mock_clock(&mut controller);
+
+        controller.control().unwrap();
+
+        // The move isn't requested twice while it's in progress
+        elapsed.set(RELOCATION_TIMEOUT as u64 - 1);
+        controller.control().unwrap();
+
+        // But is requested again if it takes too long
+        elapsed.set(RELOCATION_TIMEOUT as u64);
+        controller.control().unwrap();
+
+        controller.control().unwrap();
+        assert!(controller.relocating_torrents.is_empty());
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(get_methods(&requests), vec![
+            "torrent-get", "torrent-set-location", "torrent-get", "torrent-get", "torrent-set-location",
+            "torrent-get"]);
+    }
+
+    #[test]
+    fn test_low_space_action_names() {
+        for action in LowSpaceAction::ALL {
+            assert_eq!(action.to_string().parse::<LowSpaceAction>().unwrap(), action);
+        }
+        assert!("remove".parse::<LowSpaceAction>().is_err());
+    }
+
+    #[test]
+    fn test_cleanup_candidates() {
+        let mut private = new_torrent("private", 1);
+        private.private = true;
+
+        let mut other_dir = new_torrent("other-dir", 1);
+        other_dir.download_dir = s!("/seeds");
+
+        let torrents = vec![new_torrent("new", 3), private, other_dir, new_torrent("old", 2)];
+
+        let get_hashes = |protect_private| -> Vec<String> {
+            get_cleanup_candidates(&torrents, &[PathBuf::from("/downloads")], protect_private).iter()
+                .map(|torrent| torrent.hash.clone()).collect()
+        };
+
+        assert_eq!(get_hashes(false), vec!["private", "old", "new"]);
+        assert_eq!(get_hashes(true), vec!["old", "new"]);
+    }
+
+    #[test]
+    fn test_protect_private() {
+        let (client, requests) = mock_client(vec![success(json!({}))]);
+        let mut controller = new_controller(client, None, vec![]);
+        controller.protect_private = true;
+
+        let mut torrent = new_torrent("some-hash", 0);
+        torrent.private = true;
+        assert!(!controller.remove_torrent(&torrent, false).unwrap());
+
+        torrent.private = false;
+        assert!(controller.remove_torrent(&torrent, false).unwrap());
+
+        assert_eq!(get_removed_torrents(&requests.lock().unwrap()), vec!["some-hash"]);
+    }
+
+    #[test]
+    fn test_min_other_seeders() {
+        let (client, requests) = mock_client(vec![success(json!({}))]);
+        let mut controller = new_controller(client, None, vec![]);
+        controller.min_other_seeders = Some(2);
+
+        let mut torrent = new_torrent("few-seeders", 0);
+        torrent.seeders = Some(2);
+        assert!(!controller.remove_torrent(&torrent, true).unwrap());
+
+        torrent.seeders = Some(3);
+        assert!(controller.remove_torrent(&torrent, true).unwrap());
+
+        assert_eq!(get_removed_torrents(&requests.lock().unwrap()), vec!["few-seeders"]);
+    }
+
+    #[test]
+    fn test_cleanup_fs() {
+        let torrents = vec![new_torrent("third", 3), new_torrent("first", 1), new_torrent("second", 2)];
+
+        let (client, requests) = mock_client(vec![success(json!({})), success(json!({}))]);
+        let controller = new_controller(client, Some(10), vec![95, 95, 85]);
+        controller.cleanup_fs(&torrents).unwrap();
+        assert_eq!(get_removed_torrents(&requests.lock().unwrap()), vec!["first", "second"]);
+
+        let (client, requests) = mock_client(vec![]);
+        let controller = new_controller(client, Some(10), vec![85]);
+        controller.cleanup_fs(&torrents).unwrap();
+        assert!(requests.lock().unwrap().is_empty());
+
+        // Cleanup stops as soon as free space reaches the threshold
+        let (client, requests) = mock_client(vec![success(json!({}))]);
+        let controller = new_controller(client, Some(10), vec![91, 90]);
+        controller.cleanup_fs(&torrents).unwrap();
+        assert_eq!(get_removed_torrents(&requests.lock().unwrap()), vec!["first"]);
+    }
+
+    #[test]
+    fn test_cleanup_fs_per_device() {
+        let mut seeding = new_torrent("seeding", 0);
+        seeding.download_dir = s!("/seeds");
+        let torrents = vec![new_torrent("new", 2), seeding, new_torrent("old", 1)];
+
+        let (client, requests) = mock_client(vec![success(json!({}))]);
+        let mut controller = new_controller(client, Some(10), vec![]);
+        controller.deletable_dirs = vec![PathBuf::from("/downloads"), PathBuf::from("/seeds")];
+        controller.free_space_provider = Box::new(DevicesMock {
+            requests: RefCell::new(VecDeque::from([
+                ("/downloads", "/dev/sda1", 95), ("/seeds", "/dev/sdb1", 50),
+                ("/downloads", "/dev/sda1", 85),
+            ])),
+        });
+
+        // The oldest torrent resides on the device with enough free space, so it must be kept
+        controller.cleanup_fs(&torrents).unwrap();
+        assert_eq!(get_removed_torrents(&requests.lock().unwrap()), vec!["old"]);
+    }
+
+    #[test]
+    fn test_cleanup_fs_outside_download_dir() {
+        let mut seeding = new_torrent("seeding", 0);
+        seeding.download_dir = s!("/seeds");
+        let torrents = vec![seeding, new_torrent("old", 1)];
+
+        let (client, requests) = mock_client(vec![success(json!({}))]);
+        let mut controller = new_controller(client, Some(10), vec![]);
+        controller.free_space_provider = Box::new(DevicesMock {
+            requests: RefCell::new(VecDeque::from([
+                ("/downloads", "/dev/sda1", 95), ("/downloads", "/dev/sda1", 95),
+            ])),
+        });
+
+        // Torrents outside of the download directory aren't deletable unless explicitly allowed
+        controller.cleanup_fs(&torrents).unwrap();
+        assert_eq!(get_removed_torrents(&requests.lock().unwrap()), vec!["old"]);
     }
 }
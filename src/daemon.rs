@@ -0,0 +1,473 @@
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::cli_args::Arguments;
+use crate::common::{GenericError, GenericResult};
+use crate::config::{self, Config, ConfigReadingError};
+use crate::consumer::{ConsumerOptions, MissingFilePolicy, MoveConflictPolicy, ShutdownTimeoutAction};
+use crate::controller::{Controller, ControllerOptions};
+use crate::statsd::StatsdClient;
+use crate::transmissionrpc::{self, AddressFamily, Torrent, TransmissionClient};
+use crate::util;
+use crate::util::fs::{CopyOptions, FsError};
+use crate::util::net::Network;
+
+/// Daemon failure classes which are mapped to distinct exit codes to allow supervisors to act
+/// accordingly (for example, not to restart the daemon on configuration errors):
+/// * 1 - unexpected error
+/// * 2 - invalid command line arguments or configuration
+/// * 3 - permission error on accessing the configuration or the specified directories
+#[derive(Debug)]
+pub enum DaemonError {
+    Generic(GenericError),
+    Config(GenericError),
+    Permission(GenericError),
+}
+
+impl DaemonError {
+    pub fn from_config_error(err: GenericError, context: &str) -> DaemonError {
+        let permission_error = matches!(err.downcast_ref::<FsError>(), Some(FsError::Permission(_)));
+
+        let err = format_to!("{}: {}", context, err);
+        if permission_error {
+            DaemonError::Permission(err)
+        } else {
+            DaemonError::Config(err)
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            DaemonError::Generic(_) => 1,
+            DaemonError::Config(_) => 2,
+            DaemonError::Permission(_) => 3,
+        }
+    }
+}
+
+impl From<GenericError> for DaemonError {
+    fn from(err: GenericError) -> DaemonError {
+        DaemonError::Generic(err)
+    }
+}
+
+impl fmt::Display for DaemonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DaemonError::Generic(ref err) | DaemonError::Config(ref err) | DaemonError::Permission(ref err) =>
+                write!(f, "{}", err),
+        }
+    }
+}
+
+fn get_rpc_url(config: &Config) -> String {
+    let mut url = format!("http://{host}:{port}{path}",
+        host=config.rpc_bind_address, port=config.rpc_port, path=config.rpc_url);
+
+    if !url.ends_with('/') {
+        url.push('/');
+    }
+
+    url.push_str("rpc");
+
+    url
+}
+
+// Destructive operations are allowed only if all addresses of the RPC host are in the trusted networks
+fn is_rpc_host_trusted(config: &Config, trusted_networks: &[Network]) -> bool {
+    let host = config.rpc_bind_address.as_str();
+
+    let addresses: Vec<_> = match u16::try_from(config.rpc_port).map_err(|e| e.to_string())
+        .and_then(|port| (host, port).to_socket_addrs().map_err(|e| e.to_string())) {
+        Ok(addresses) => addresses.collect(),
+        Err(e) => {
+            warn!("Unable to resolve '{}' RPC host: {}.", host, e);
+            return false;
+        },
+    };
+
+    for address in &addresses {
+        if !trusted_networks.iter().any(|network| network.contains(address.ip())) {
+            warn!("'{}' RPC host resolves to {} which is not in the trusted networks.", host, address.ip());
+            return false;
+        }
+    }
+
+    !addresses.is_empty()
+}
+
+// Address family filtering is applied on host name resolution only, so IP literals have to be checked beforehand
+fn check_rpc_address_family(host: &str, family: AddressFamily) -> GenericResult<()> {
+    match host.parse::<IpAddr>() {
+        Ok(address) if !family.matches(&address) => Err!(
+            "'{}' RPC host doesn't match --rpc-address-family {}", host, family),
+        _ => Ok(()),
+    }
+}
+
+pub fn load_config(path: &Path, path_remaps: &[(PathBuf, PathBuf)]) -> Result<Config, DaemonError> {
+    let config = config::read_config(path, path_remaps).map_err(|e| match e {
+        ConfigReadingError::Validation(_) => DaemonError::Config(format_to!(
+            "Validation of '{}' configuration file failed: {}", path.display(), e)),
+        ConfigReadingError::Io(ref err) if err.kind() == io::ErrorKind::PermissionDenied => DaemonError::Permission(
+            format_to!("Error while reading '{}' configuration file: {}", path.display(), e)),
+        _ => DaemonError::Config(format_to!(
+            "Error while reading '{}' configuration file: {}", path.display(), e)),
+    })?;
+
+    debug!("Loaded config: {:?}", config);
+    Ok(config)
+}
+
+/// The controllers with all their dependencies constructed from the command line arguments and
+/// Transmission configurations. Signal handling and the main loop are left to the caller.
+pub struct Daemon {
+    // Controllers of the managed Transmission daemons by their RPC URL
+    controllers: Vec<(String, Controller)>,
+    start_time: Instant,
+    startup_error_grace: Duration,
+    shutdown_timeout: Option<Duration>,
+    on_shutdown_timeout: ShutdownTimeoutAction,
+}
+
+pub fn create_client(args: &Arguments, config: &Config) -> Result<TransmissionClient, DaemonError> {
+    let rpc_url = get_rpc_url(config);
+    debug!("Use RPC URL: {}.", rpc_url);
+
+    let mut client = TransmissionClient::new(&rpc_url);
+    if config.rpc_authentication_required {
+        client.set_authentication(&config.rpc_username, config.rpc_plain_password.as_ref().unwrap());
+    }
+    if let Some(timeout) = args.rpc_connect_timeout {
+        client.set_connect_timeout(Duration::from_secs(timeout as u64));
+    }
+    if let Some(timeout) = args.rpc_timeout {
+        client.set_timeout(Duration::from_secs(timeout as u64));
+    }
+    if let Some(size) = args.rpc_max_response_size {
+        client.set_max_response_size(size);
+    }
+    client.set_processed_marker(args.processed_marker);
+    client.set_path_remaps(&args.path_remaps);
+    check_rpc_address_family(&config.rpc_bind_address, args.rpc_address_family).map_err(DaemonError::Config)?;
+    client.set_address_family(args.rpc_address_family);
+    client.set_log_hashes(args.log_hashes);
+    if let Some(ref proxy) = args.rpc_socks_proxy {
+        client.set_socks_proxy(proxy).map_err(DaemonError::Config)?;
+    }
+    if !args.rpc_query_params.is_empty() {
+        client.set_query_params(&args.rpc_query_params).map_err(DaemonError::Config)?;
+    }
+
+    Ok(client)
+}
+
+/// Returns all torrents marked as processed.
+pub fn get_processed(client: &TransmissionClient) -> GenericResult<Vec<Torrent>> {
+    Ok(client.get_torrents()?.into_iter().filter(|torrent| torrent.processed).collect())
+}
+
+/// Clears the processed marker on all torrents, so they will be consumed again.
+pub fn reset_processed(client: &TransmissionClient) -> GenericResult<usize> {
+    let mut count = 0;
+
+    for torrent in client.get_torrents()? {
+        if !torrent.processed {
+            continue;
+        }
+
+        info!("Resetting processed marker of {} torrent...", torrent.id());
+        client.reset_processed(&torrent)?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+fn create_controller(
+    args: &Arguments, config: &Config, statsd_client: Option<StatsdClient>,
+) -> Result<Controller, DaemonError> {
+    let removal_allowed = args.trusted_networks.is_empty() || is_rpc_host_trusted(config, &args.trusted_networks);
+    if !removal_allowed {
+        warn!("Torrent removal is disabled: RPC host is not in the trusted networks.");
+    }
+
+    let client = create_client(args, config)?;
+
+    let copy_to = config.copy_to.clone().or_else(|| args.copy_to.clone());
+    let move_to = config.move_to.clone().or_else(|| args.move_to.clone());
+    check_consumer_options(args, copy_to.as_deref(), move_to.as_deref()).map_err(DaemonError::Config)?;
+
+    let mut consumer_options = ConsumerOptions {
+        path_remaps: args.path_remaps.clone(),
+        copy_to,
+        copy_to_labels: args.copy_to_labels.clone(),
+        copy_owner: args.copy_owner.clone(),
+        copy_options: args.copy_options,
+        copy_all_files: args.copy_all_files,
+        ignored_extensions: args.ignored_extensions.clone(),
+        missing_file_policy: args.missing_file_policy,
+        move_to,
+        move_conflict_policy: args.move_conflict_policy,
+        abandoned_files_check_period: args.abandoned_files_check_period.map(to_std_duration),
+        scrub_interval: args.scrub_interval.map(to_std_duration),
+        scrub_remove_empty_dirs: args.scrub_remove_empty_dirs,
+        seed_ratio_limit: args.seed_ratio_limit,
+        verify_before_consume: args.verify_before_consume,
+        not_found_retries: args.not_found_retries,
+        not_found_retry_delay: to_std_duration(args.not_found_retry_delay),
+        notifications_mailer: args.notifications_mailer.clone(),
+        torrent_failed_email_template: args.torrent_failed_email_template.clone(),
+        ..Default::default()
+    };
+
+    // Template file has precedence over the inline template from the config
+    if let Some(template) = args.torrent_downloaded_email_template.clone()
+        .or_else(|| config.get_torrent_downloaded_email_template()) {
+        consumer_options.torrent_downloaded_email_template = template;
+    }
+
+    let options = ControllerOptions {
+        action: args.action,
+        action_periods: args.action_periods.clone(),
+        date_overrides: args.date_overrides.clone(),
+        schedule_scope: args.schedule_scope,
+        maintenance_periods: args.maintenance_periods.clone(),
+        active_only_in_periods: args.active_only_in_periods,
+        managed_statuses: args.managed_statuses.clone(),
+        tracker_hosts: args.tracker_hosts.clone(),
+        managed_label: args.managed_label.clone(),
+        priority_rules: args.priority_rules.clone(),
+
+        download_dir: PathBuf::from(&config.download_dir),
+        seed_location: args.seed_location.clone(),
+        deletable_dirs: args.deletable_dirs.clone(),
+        removal_allowed,
+        min_consume_size: args.min_consume_size,
+        max_consume_size: args.max_consume_size,
+        consume_delay: args.consume_delay,
+        reconsume_if_missing: args.reconsume_if_missing,
+
+        seed_time_limit: args.seed_time_limit,
+        max_age: args.max_age,
+        reannounce_interval: args.reannounce_interval,
+        upload_ratio_limit: args.upload_ratio_limit,
+        protect_private: args.protect_private,
+        min_other_seeders: args.min_other_seeders,
+        remove_missing_data: args.remove_missing_data,
+        free_space_threshold: args.free_space_threshold,
+        free_space_headroom: args.free_space_headroom,
+        free_space_check_retries: args.free_space_check_retries,
+        free_space_warn_threshold: args.free_space_warn_threshold,
+        max_removals_per_cycle: args.max_removals_per_cycle,
+        on_low_space: args.on_low_space,
+        observe_cycles: args.observe_cycles,
+        warmup_cycles: args.warmup_cycles,
+
+        consumer: consumer_options,
+    };
+
+    Ok(Controller::new(client, options, args.mqtt_publisher.clone(), statsd_client))
+}
+
+// Checks the options which depend on the copy and move directories which may be overridden by the
+// daemon's configuration
+fn check_consumer_options(args: &Arguments, copy_to: Option<&Path>, move_to: Option<&Path>) -> GenericResult<()> {
+    if move_to.is_some() {
+        if copy_to.is_none() {
+            return Err!("--move-to ('tc-move-to') must be specified only with --copy-to ('tc-copy-to')");
+        }
+        if !args.copy_to_labels.is_empty() {
+            return Err!("--copy-to-label can't be used with --move-to ('tc-move-to')");
+        }
+    }
+
+    if copy_to.is_none() {
+        let default_copy_options = CopyOptions::default();
+
+        for (specified, option) in [
+            (!args.copy_to_labels.is_empty(), "--copy-to-label"),
+            (args.copy_owner.is_some(), "--copy-uid and --copy-gid"),
+            (args.copy_options.buffer_size != default_copy_options.buffer_size, "--copy-buffer-size"),
+            (args.copy_options.bandwidth.is_some(), "--copy-bandwidth"),
+            (args.copy_all_files, "--copy-all-files"),
+            (args.missing_file_policy != MissingFilePolicy::Fail, "--on-missing-file"),
+            (args.reconsume_if_missing, "--reconsume-if-missing"),
+            (args.scrub_interval.is_some(), "--scrub-interval"),
+        ] {
+            if specified {
+                return Err!("{} must be specified only with --copy-to ('tc-copy-to')", option);
+            }
+        }
+    }
+
+    if move_to.is_none() {
+        for (specified, option) in [
+            (args.move_conflict_policy != MoveConflictPolicy::Rename, "--move-conflict"),
+            (args.abandoned_files_check_period.is_some(), "--abandoned-files-check-period"),
+        ] {
+            if specified {
+                return Err!("{} must be specified only with --move-to ('tc-move-to')", option);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn to_std_duration(duration: util::time::Duration) -> Duration {
+    Duration::from_secs(duration as u64)
+}
+
+impl Daemon {
+    /// Creates a controller for each of the specified Transmission daemons.
+    pub fn new(mut args: Arguments, configs: &[Config]) -> Result<Daemon, DaemonError> {
+        // Statsd is allowed only for a single daemon, so the client is passed to the first controller
+        let mut statsd_client = args.statsd_client.take();
+        let mut controllers = Vec::new();
+
+        for config in configs {
+            let name = get_rpc_url(config);
+            let controller = create_controller(&args, config, statsd_client.take())?;
+            controllers.push((name, controller));
+        }
+
+        Ok(Daemon {
+            controllers: controllers,
+            start_time: Instant::now(),
+            startup_error_grace: Duration::from_secs(args.startup_error_grace as u64),
+            shutdown_timeout: args.shutdown_timeout.map(|timeout| Duration::from_secs(timeout as u64)),
+            on_shutdown_timeout: args.on_shutdown_timeout,
+        })
+    }
+
+    /// Runs a single control cycle for all the daemons returning the first error to the caller.
+    pub fn control(&mut self) -> transmissionrpc::EmptyResult {
+        let mut result = Ok(());
+
+        for (_, controller) in &mut self.controllers {
+            let cycle_result = controller.control();
+            if result.is_ok() {
+                result = cycle_result;
+            }
+        }
+
+        result
+    }
+
+    /// Runs a single control cycle for all the daemons logging their errors. Fails only if the
+    /// daemon can't continue operating.
+    pub fn run_once(&mut self) -> Result<(), DaemonError> {
+        let multiple = self.controllers.len() > 1;
+
+        for (name, controller) in &mut self.controllers {
+            if let Err(e) = controller.control() {
+                let message = if multiple {
+                    format!("{}: {}", name, e)
+                } else {
+                    e.to_string()
+                };
+
+                // Transmission RPC may not respond for some time after startup. Increase the severity
+                // of error messages to not send emails after each reboot.
+                if self.start_time.elapsed() < self.startup_error_grace {
+                    warn!("{}.", message)
+                } else {
+                    error!("{}.", message)
+                }
+            }
+
+            // Without the consumer the daemon would silently stop consuming the torrents, so exit and
+            // let the supervisor restart us.
+            if !controller.is_consumer_alive() {
+                error!("Torrent consuming thread has died. Exiting...");
+                return Err(DaemonError::Generic(format_to!("Torrent consuming thread has died")));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn explain_cleanup(&self) -> GenericResult<Vec<String>> {
+        let mut lines = Vec::new();
+
+        for (name, controller) in &self.controllers {
+            if self.controllers.len() > 1 {
+                lines.push(format!("{}:", name));
+            }
+            lines.extend(controller.explain_cleanup()?);
+        }
+
+        Ok(lines)
+    }
+
+    /// Stops the controllers waiting for their torrent consuming threads to finish (limited by the
+    /// shutdown timeout if it's specified).
+    pub fn shutdown(self) {
+        info!("Shutting down the controller...");
+
+        // The threads are stopped in parallel, so the shutdown timeout limits the total wait time
+        for (_, controller) in &self.controllers {
+            controller.request_shutdown();
+        }
+
+        let deadline = self.shutdown_timeout.map(|timeout| Instant::now() + timeout);
+
+        for (_, controller) in self.controllers {
+            let timeout = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            controller.shutdown(timeout, self.on_shutdown_timeout);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::cli_args::Arguments;
+    use crate::consumer::MissingFilePolicy;
+    use crate::transmissionrpc::AddressFamily;
+    use super::{check_consumer_options, check_rpc_address_family};
+
+    #[test]
+    fn test_per_daemon_consumer_options() {
+        let copy_to = Some(Path::new("/copy"));
+        let move_to = Some(Path::new("/move"));
+
+        let mut args = Arguments::default();
+        assert!(check_consumer_options(&args, None, None).is_ok());
+
+        // Copy options may be used by a daemon which has only 'tc-copy-to' in its configuration
+        args.copy_all_files = true;
+        args.missing_file_policy = MissingFilePolicy::Skip;
+        args.scrub_interval = Some(60);
+        assert!(check_consumer_options(&args, copy_to, None).is_ok());
+        assert_eq!(
+            check_consumer_options(&args, None, None).unwrap_err().to_string(),
+            "--copy-all-files must be specified only with --copy-to ('tc-copy-to')");
+
+        args.abandoned_files_check_period = Some(60);
+        assert!(check_consumer_options(&args, copy_to, move_to).is_ok());
+        assert!(check_consumer_options(&args, copy_to, None).is_err());
+        assert!(check_consumer_options(&Arguments::default(), None, move_to).is_err());
+
+        args.copy_to_labels = vec![(s!("label"), "/label".into())];
+        assert!(check_consumer_options(&args, copy_to, move_to).is_err());
+    }
+
+    #[test]
+    fn test_rpc_address_family() {
+        assert!(check_rpc_address_family("localhost", AddressFamily::Ipv6).is_ok());
+        assert!(check_rpc_address_family("127.0.0.1", AddressFamily::Any).is_ok());
+        assert!(check_rpc_address_family("127.0.0.1", AddressFamily::Ipv4).is_ok());
+        assert!(check_rpc_address_family("::1", AddressFamily::Ipv6).is_ok());
+
+        assert_eq!(
+            check_rpc_address_family("127.0.0.1", AddressFamily::Ipv6).unwrap_err().to_string(),
+            "'127.0.0.1' RPC host doesn't match --rpc-address-family ipv6");
+        assert!(check_rpc_address_family("::1", AddressFamily::Ipv4).is_err());
+    }
+}
@@ -2,35 +2,87 @@ use std::collections::HashMap;
 use std::fs::File;
 use std::io::{Read, BufReader, BufRead};
 use std::path::Path;
+#[cfg(feature = "email")] use std::thread;
+use std::time::Duration;
 
-use log::debug;
+#[cfg(feature = "email")] use log::debug;
 
-use lettre::{Message, Transport, SmtpTransport};
-use lettre::message::Mailbox;
+#[cfg(feature = "email")] use lettre::{Message, Transport, SmtpTransport};
+#[cfg(feature = "email")] use lettre::message::Mailbox;
 
 use crate::common::{EmptyResult, GenericResult};
 
-#[derive(Debug)]
+pub const DEFAULT_RETRIES: usize = 2;
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[cfg(feature = "email")] const RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+// Limits the time of sending a single email with all retries, since it blocks the calling thread
+#[cfg(feature = "email")] const MAX_SEND_TIME: Duration = Duration::from_secs(2 * 60);
+
+/// Sends emails via the local SMTP server. Without `email` feature the mailer can't be created.
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "email"), allow(dead_code))]
 pub struct Mailer {
+    #[cfg(feature = "email")]
     from: Mailbox,
+    #[cfg(feature = "email")]
     to: Mailbox,
+    subject_prefix: Option<String>,
+    retries: usize,
+    timeout: Duration,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct EmailTemplate {
     subject: String,
     body: String,
 }
 
 impl Mailer {
+    #[cfg(not(feature = "email"))]
+    pub fn new(_from: &str, _to: &str) -> GenericResult<Mailer> {
+        Err!("Email support is disabled in this build")
+    }
+
+    #[cfg(feature = "email")]
     pub fn new(from: &str, to: &str) -> GenericResult<Mailer> {
         Ok(Mailer {
             from: from.parse().map_err(|_| format!("Invalid email: {:?}", from))?,
             to: to.parse().map_err(|_| format!("Invalid email: {:?}", to))?,
+            subject_prefix: None,
+            retries: DEFAULT_RETRIES,
+            timeout: DEFAULT_TIMEOUT,
         })
     }
 
+    pub fn set_subject_prefix(&mut self, prefix: &str) {
+        self.subject_prefix = Some(s!(prefix));
+    }
+
+    pub fn set_retries(&mut self, retries: usize) {
+        self.retries = retries;
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
     pub fn send(&self, subject: &str, body: &str) -> EmptyResult {
+        let subject = match self.subject_prefix {
+            Some(ref prefix) => format!("{} {}", prefix, subject),
+            None => s!(subject),
+        };
+
+        self.send_message(&subject, body)
+    }
+
+    #[cfg(not(feature = "email"))]
+    fn send_message(&self, _subject: &str, _body: &str) -> EmptyResult {
+        Err!("Email support is disabled in this build")
+    }
+
+    #[cfg(feature = "email")]
+    fn send_message(&self, subject: &str, body: &str) -> EmptyResult {
         let message = Message::builder()
             .from(self.from.clone())
             .to(self.to.clone())
@@ -38,8 +90,27 @@ impl Mailer {
             .body(body.to_owned())
             .map_err(|e| format!("Failed to construct a email: {}", e))?;
 
+        let transport = SmtpTransport::builder_dangerous("localhost").timeout(Some(self.timeout)).build();
+        let mut retry_delays = get_retry_delays(self.retries, self.timeout).into_iter();
+
         debug!("Sending {:?} email to {}...", subject, self.to.email);
-        SmtpTransport::unencrypted_localhost().send(&message)?;
+
+        loop {
+            match transport.send(&message) {
+                Ok(_) => break,
+                Err(e) => {
+                    let delay = match retry_delays.next() {
+                        Some(delay) if !e.is_permanent() => delay,
+                        _ => return Err(e.into()),
+                    };
+
+                    // Mustn't be logged with higher level: the mailer is used by the logger itself
+                    debug!("Failed to send the email: {}. Retrying in {}s...", e, delay.as_secs());
+                    thread::sleep(delay);
+                },
+            }
+        }
+
         debug!("The email has been sent.");
 
         Ok(())
@@ -89,6 +160,20 @@ impl EmailTemplate {
     }
 }
 
+/// Formats the items as a bulleted list for a template variable truncating it to the specified
+/// number of items.
+pub fn format_list<T: AsRef<str>>(items: &[T], max_items: usize) -> String {
+    let mut lines: Vec<String> = items.iter().take(max_items)
+        .map(|item| s!("* ") + item.as_ref())
+        .collect();
+
+    if items.len() > max_items {
+        lines.push(format!("(+{} more)", items.len() - max_items));
+    }
+
+    lines.join("\n")
+}
+
 fn render_template(template: &str, params: &HashMap<&str, String>) -> GenericResult<String> {
     let mut result = s!(template);
 
@@ -100,3 +185,48 @@ fn render_template(template: &str, params: &HashMap<&str, String>) -> GenericRes
 
     Ok(result)
 }
+
+// Returns delays between the sending attempts which fit into the maximum send time
+#[cfg(feature = "email")]
+fn get_retry_delays(retries: usize, timeout: Duration) -> Vec<Duration> {
+    let mut delays = Vec::new();
+    let mut delay = RETRY_BASE_DELAY;
+    let mut total_time = timeout;
+
+    for _ in 0..retries {
+        total_time += delay + timeout;
+        if total_time > MAX_SEND_TIME {
+            break;
+        }
+
+        delays.push(delay);
+        delay *= 2;
+    }
+
+    delays
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "email")]
+    #[test]
+    fn test_get_retry_delays() {
+        let secs = Duration::from_secs;
+
+        assert_eq!(get_retry_delays(0, secs(30)), Vec::<Duration>::new());
+        assert_eq!(get_retry_delays(2, secs(30)), vec![secs(1), secs(2)]);
+        assert_eq!(get_retry_delays(5, secs(30)), vec![secs(1), secs(2)]);
+        assert_eq!(get_retry_delays(10, secs(10)), vec![secs(1), secs(2), secs(4), secs(8), secs(16)]);
+        assert_eq!(get_retry_delays(2, secs(90)), Vec::<Duration>::new());
+    }
+
+    #[test]
+    fn test_format_list() {
+        assert_eq!(format_list::<&str>(&[], 2), "");
+        assert_eq!(format_list(&["a"], 2), "* a");
+        assert_eq!(format_list(&["a", "b"], 2), "* a\n* b");
+        assert_eq!(format_list(&["a", "b", "c", "d"], 2), "* a\n* b\n(+2 more)");
+    }
+}
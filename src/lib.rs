@@ -0,0 +1,20 @@
+#[cfg(feature = "email")] extern crate email as libemail;
+#[macro_use] extern crate enum_primitive;
+#[macro_use] extern crate log;
+
+#[macro_use] mod common;
+pub mod cli_args;
+pub mod config;
+pub mod consumer;
+pub mod controller;
+pub mod daemon;
+pub mod email;
+pub mod logging;
+pub mod mqtt;
+pub mod statsd;
+pub mod transmissionrpc;
+pub mod util;
+
+pub use crate::common::{EmptyResult, GenericError, GenericResult};
+pub use crate::cli_args::Arguments;
+pub use crate::daemon::{Daemon, DaemonError};
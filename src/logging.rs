@@ -1,33 +1,40 @@
 use std::cmp;
 use std::fmt;
+use std::fs;
 use std::io;
 use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex, Weak};
 use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use itertools::Itertools;
 use log::{self, Log, Record, Level, Metadata, SetLoggerError};
-use time::Duration;
 
+use crate::common::{EmptyResult, GenericResult};
 use crate::email::Mailer;
 use crate::util::helpers::SelfArc;
 
 
-pub fn init(level: Level, target: Option<&'static str>, mailer: Option<Mailer>) -> Result<LoggerGuard, SetLoggerError> {
-    let mut logger = Logger::new(level, target);
+pub fn init(
+    level: Level, target: Option<&'static str>, extra_targets: Vec<(String, Level)>,
+    mailer: Option<Mailer>, email_state_path: Option<PathBuf>,
+) -> Result<LoggerGuard, SetLoggerError> {
+    let max_level = extra_targets.iter().map(|&(_, level)| level).fold(level, cmp::max);
+    let mut logger = Logger::new(level, target, extra_targets);
 
     let stderr_handler = StderrHandler::new(level >= Level::Debug);
     logger.add_handler(stderr_handler.clone());
 
     if let Some(mailer) = mailer {
-        logger.add_handler(EmailHandler::new("Transmission controller errors", mailer, stderr_handler));
+        logger.add_handler(EmailHandler::new(
+            "Transmission controller errors", mailer, email_state_path, stderr_handler));
     }
 
     let logger = Arc::new(logger);
 
     log::set_boxed_logger(Box::new(LoggerWrapper { logger: logger.clone() }))?;
-    log::set_max_level(level.to_level_filter());
+    log::set_max_level(max_level.to_level_filter());
 
     Ok(LoggerGuard { logger: Arc::downgrade(&logger) })
 }
@@ -68,14 +75,16 @@ impl Log for LoggerWrapper {
 struct Logger {
     target: Option<&'static str>,
     level: Level,
+    extra_targets: Vec<(String, Level)>,
     handlers: Vec<Arc<dyn LoggingHandler>>,
 }
 
 impl Logger {
-    fn new(level: Level, target: Option<&'static str>) -> Logger {
+    fn new(level: Level, target: Option<&'static str>, extra_targets: Vec<(String, Level)>) -> Logger {
         Logger {
             target: target,
             level: level,
+            extra_targets: extra_targets,
             handlers: Vec::new(),
         }
     }
@@ -87,10 +96,17 @@ impl Logger {
 
 impl Log for Logger {
     fn enabled(&self, metadata: &Metadata) -> bool {
+        let target = metadata.target();
+
+        // The most specific of the explicitly configured targets takes precedence
+        if let Some(&(_, level)) = self.extra_targets.iter()
+            .filter(|&(prefix, _)| is_target_matches(target, prefix))
+            .max_by_key(|&(prefix, _)| prefix.len()) {
+            return metadata.level() <= level;
+        }
+
         metadata.level() <= self.level && (
-            self.target.is_none() ||
-            metadata.target() == self.target.unwrap() ||
-            metadata.target().starts_with(&(s!(self.target.unwrap()) + "::"))
+            self.target.is_none() || is_target_matches(target, self.target.unwrap())
         )
     }
 
@@ -112,6 +128,10 @@ impl Log for Logger {
     }
 }
 
+fn is_target_matches(target: &str, prefix: &str) -> bool {
+    target == prefix || target.starts_with(prefix) && target[prefix.len()..].starts_with("::")
+}
+
 
 struct StderrHandler {
     debug: bool,
@@ -166,18 +186,35 @@ impl LoggingHandler for StderrHandler {
 struct EmailHandler {
     subject: String,
     mailer: Mailer,
+    state_path: Option<PathBuf>,
     fallback_handler: Arc<dyn LoggingHandler>,
     log: Mutex<EmailLog>,
     arc: SelfArc<EmailHandler>,
 }
 
 impl EmailHandler {
-    fn new(subject: &str, mailer: Mailer, fallback_handler: Arc<dyn LoggingHandler>) -> Arc<EmailHandler> {
+    fn new(
+        subject: &str, mailer: Mailer, state_path: Option<PathBuf>, fallback_handler: Arc<dyn LoggingHandler>,
+    ) -> Arc<EmailHandler> {
+        let mut log = EmailLog::new();
+
+        if let Some(ref state_path) = state_path {
+            match load_time_since_last_flush(state_path) {
+                Ok(Some(elapsed)) => {
+                    log.min_flush_time = Some(Instant::now() + MIN_EMAIL_SENDING_PERIOD.saturating_sub(elapsed));
+                },
+                Ok(None) => {},
+                Err(error) => fallback_handler.log(module_path!(), Some(file!()), Some(line!()), Level::Warn,
+                    &format_args!("Failed to load email log state from '{}': {}.", state_path.display(), error)),
+            }
+        }
+
         let handler = Arc::new(EmailHandler {
             mailer: mailer,
             subject: s!(subject),
+            state_path: state_path,
             fallback_handler: fallback_handler,
-            log: Mutex::new(EmailLog::new()),
+            log: Mutex::new(log),
             arc: SelfArc::new(),
         });
         handler.arc.init(&handler);
@@ -189,9 +226,37 @@ impl EmailHandler {
             self.fallback_handler.log(module_path!(), Some(file!()), Some(line!()), Level::Error,
                 &format_args!("Failed to send an error via email: {}.", error));
         }
+
+        if let Some(ref state_path) = self.state_path {
+            if let Err(error) = save_last_flush_time(state_path) {
+                self.fallback_handler.log(module_path!(), Some(file!()), Some(line!()), Level::Error,
+                    &format_args!("Failed to save email log state to '{}': {}.", state_path.display(), error));
+            }
+        }
     }
 }
 
+// The state is stored as a UNIX timestamp of the last sent email to honor the email sending period across restarts
+fn load_time_since_last_flush(path: &Path) -> GenericResult<Option<Duration>> {
+    let state = match fs::read_to_string(path) {
+        Ok(state) => state,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    let timestamp: u64 = state.trim().parse().map_err(|_| format!("Invalid state: {:?}", state.trim()))?;
+    let elapsed = SystemTime::now().duration_since(UNIX_EPOCH + Duration::from_secs(timestamp))
+        .unwrap_or_default();
+
+    Ok(Some(elapsed))
+}
+
+fn save_last_flush_time(path: &Path) -> EmptyResult {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    fs::write(path, format!("{}\n", timestamp))?;
+    Ok(())
+}
+
 impl LoggingHandler for EmailHandler {
     fn log(&self, _target: &str, _file: Option<&str>, _line: Option<u32>, level: Level, args: &fmt::Arguments) {
         if level > Level::Error {
@@ -248,10 +313,13 @@ fn email_log_flush_thread(weak: Weak<EmailHandler>) {
 }
 
 
+const FIRST_EMAIL_DELAY_TIME: Duration = Duration::from_secs(60);
+const MIN_EMAIL_SENDING_PERIOD: Duration = Duration::from_secs(60 * 60);
+
 struct EmailLog {
     errors: Vec<String>,
     flush_time: Option<Instant>,
-    last_flush_time: Option<Instant>,
+    min_flush_time: Option<Instant>,
     flush_thread: Option<thread::JoinHandle<()>>,
 }
 
@@ -260,19 +328,16 @@ impl EmailLog {
         EmailLog {
             errors: Vec::new(),
             flush_time: None,
-            last_flush_time: None,
+            min_flush_time: None,
             flush_thread: None,
         }
     }
 
     fn on_error(&mut self, error: String) {
         if self.errors.is_empty() {
-            let first_email_delay_time = Duration::minutes(1);
-            let min_email_sending_period = Duration::hours(1);
-
-            let mut flush_time = Instant::now() + first_email_delay_time;
-            if let Some(last_flush_time) = self.last_flush_time {
-                flush_time = cmp::max(flush_time, last_flush_time + min_email_sending_period);
+            let mut flush_time = Instant::now() + FIRST_EMAIL_DELAY_TIME;
+            if let Some(min_flush_time) = self.min_flush_time {
+                flush_time = cmp::max(flush_time, min_flush_time);
             }
 
             self.flush_time = Some(flush_time);
@@ -291,7 +356,7 @@ impl EmailLog {
 
         self.errors.clear();
         self.flush_time = None;
-        self.last_flush_time = Some(Instant::now());
+        self.min_flush_time = Some(Instant::now() + MIN_EMAIL_SENDING_PERIOD);
 
         Some(message)
     }
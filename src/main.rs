@@ -1,70 +1,30 @@
-extern crate argparse;
 #[macro_use] extern crate chan;
 extern crate chan_signal; // Attention: this crate calls pthread_sigmask() in crate's init() which masks all signals
-extern crate email as libemail;
-#[macro_use] extern crate enum_primitive;
-extern crate itertools;
-extern crate lettre;
-extern crate lettre_email;
-extern crate libc;
 #[macro_use] extern crate log;
-extern crate mime;
-extern crate num;
-extern crate regex;
-extern crate reqwest;
-extern crate time;
-
-#[macro_use] mod common;
-mod cli_args;
-mod config;
-mod consumer;
-mod controller;
-mod email;
-mod logging;
-mod transmissionrpc;
-mod util;
-
-use std::io::Write;
-use std::path::{Path, PathBuf};
+
+use std::io::{self, Write};
+use std::mem;
+use std::path::PathBuf;
 use std::process;
-use std::time::Instant;
 
 use chan_signal::Signal;
 
-use crate::common::GenericResult;
-use crate::config::{Config, ConfigReadingError};
-use crate::email::Mailer;
-
-fn get_rpc_url(config: &Config) -> String {
-    let mut url = format!("http://{host}:{port}{path}",
-        host=config.rpc_bind_address, port=config.rpc_port, path=config.rpc_url);
-
-    if !url.ends_with('/') {
-        url.push('/');
-    }
-
-    url.push_str("rpc");
-
-    url
-}
-
-fn load_config(path: &Path) -> GenericResult<Config> {
-    let config = config::read_config(path).map_err(|e| match e {
-        ConfigReadingError::Validation(_) => {
-            format!("Validation of '{}' configuration file failed: {}", path.display(), e)
-        },
-        _ => format!("Error while reading '{}' configuration file: {}", path.display(), e),
-    })?;
-
-    debug!("Loaded config: {:?}", config);
-    Ok(config)
-}
+use transmission_controller::{cli_args, controller, logging, Daemon, DaemonError, GenericResult};
+use transmission_controller::daemon::{self, load_config};
+use transmission_controller::email::Mailer;
 
-fn setup_logging(debug_level: usize, error_mailer: Option<Mailer>) -> GenericResult<logging::LoggerGuard> {
+fn setup_logging(
+    debug_level: usize, quiet_level: usize, log_targets: Vec<(String, log::Level)>, error_mailer: Option<Mailer>,
+    email_state_path: Option<PathBuf>,
+) -> GenericResult<logging::LoggerGuard> {
     let mut log_target = Some(module_path!());
 
     let log_level = match debug_level {
-        0 => log::Level::Info,
+        0 => match quiet_level {
+            0 => log::Level::Info,
+            1 => log::Level::Warn,
+            _ => log::Level::Error,
+        },
         1 => log::Level::Debug,
         2 => log::Level::Trace,
         _ => {
@@ -73,47 +33,114 @@ fn setup_logging(debug_level: usize, error_mailer: Option<Mailer>) -> GenericRes
         }
     };
 
-    Ok(logging::init(log_level, log_target, error_mailer)?)
+    Ok(logging::init(log_level, log_target, log_targets, error_mailer, email_state_path)?)
+}
+
+fn confirm(question: &str) -> GenericResult<bool> {
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
 }
 
-fn daemon() -> GenericResult<i32> {
+fn test_email(mailers: &[(&str, Option<&Mailer>)]) -> i32 {
+    let mut exit_code = 0;
+
+    for &(name, mailer) in mailers {
+        let Some(mailer) = mailer else {
+            continue;
+        };
+
+        match mailer.send("Test email", "This is a test email from Transmission controller.") {
+            Ok(()) => println!("Test {} email has been sent.", name),
+            Err(e) => {
+                eprintln!("Failed to send test {} email: {}.", name, e);
+                exit_code = 1;
+            },
+        }
+    }
+
+    exit_code
+}
+
+fn daemon() -> Result<i32, DaemonError> {
     let signal_channel = chan_signal::notify(
         &[Signal::INT, Signal::TERM, Signal::QUIT]);
 
-    let args = cli_args::parse().map_err(|e| format!(
-        "Command line arguments parsing error: {}", e))?;
+    let mut args = cli_args::parse().map_err(|e| DaemonError::from_config_error(
+        e, "Command line arguments parsing error"))?;
+
+    if args.list_periods_now {
+        println!("{}", controller::format_current_state(
+            args.action.unwrap(), &args.action_periods, &args.date_overrides));
+        return Ok(0);
+    }
+
+    if args.test_email {
+        return Ok(test_email(&[
+            ("error", args.error_mailer.as_ref()),
+            ("notification", args.notifications_mailer.as_ref()),
+        ]));
+    }
 
-    let _logging = setup_logging(args.debug_level, args.error_mailer)?;
+    let _logging = setup_logging(
+        args.debug_level, args.quiet_level, mem::take(&mut args.log_targets), args.error_mailer.take(),
+        args.email_state_file.take())?;
     info!("Starting the daemon...");
 
-    let config = load_config(&args.config)?;
-    let rpc_url = get_rpc_url(&config);
-    debug!("Use RPC URL: {}.", rpc_url);
+    if let Some(action) = args.action {
+        info!("Using the following schedule:");
+        for line in controller::format_schedule(action, &args.action_periods) {
+            info!("* {}", line);
+        }
+    }
+
+    let configs = args.configs.iter()
+        .map(|path| load_config(path, &args.path_remaps))
+        .collect::<Result<Vec<_>, _>>()?;
 
-    let mut client = transmissionrpc::TransmissionClient::new(&rpc_url);
-    if config.rpc_authentication_required {
-        client.set_authentication(&config.rpc_username, config.rpc_plain_password.as_ref().unwrap());
+    if args.list_processed {
+        for config in &configs {
+            let client = daemon::create_client(&args, config)?;
+            for torrent in daemon::get_processed(&client)? {
+                println!("{} {}", torrent.hash, torrent.name);
+            }
+        }
+        return Ok(0);
     }
 
-    let mut controller = controller::Controller::new(
-        client, args.action, args.action_periods,
-        PathBuf::from(&config.download_dir), args.copy_to, args.move_to,
-        args.seed_time_limit, args.upload_ratio_limit, args.free_space_threshold,
-        args.notifications_mailer, args.torrent_downloaded_email_template);
+    if args.reset_processed {
+        if !args.yes && !confirm("All processed torrents will be consumed again. Continue?")? {
+            return Ok(0);
+        }
+
+        let mut count = 0;
+        for config in &configs {
+            let client = daemon::create_client(&args, config)?;
+            count += daemon::reset_processed(&client)?;
+        }
+        info!("Processed marker has been reset on {} torrents.", count);
+        return Ok(0);
+    }
+
+    let explain_cleanup = args.explain_cleanup;
+    let mut daemon = Daemon::new(args, &configs)?;
+
+    if explain_cleanup {
+        for line in daemon.explain_cleanup()? {
+            println!("{}", line);
+        }
+        daemon.shutdown();
+        return Ok(0);
+    }
 
     let tick = chan::tick_ms(5000);
-    let start_time = Instant::now();
 
     loop {
-        if let Err(e) = controller.control() {
-            // Transmission RPC may not respond for some time after startup. Increase the severity
-            // of error messages to not send emails after each reboot.
-            if start_time.elapsed().as_secs() < 60 {
-                warn!("{}.", e)
-            } else {
-                error!("{}.", e)
-            }
-        }
+        daemon.run_once()?;
 
         chan_select! {
             signal_channel.recv() => {
@@ -124,6 +151,7 @@ fn daemon() -> GenericResult<i32> {
         }
     }
 
+    daemon.shutdown();
     Ok(0)
 }
 
@@ -131,8 +159,8 @@ fn main() {
     let exit_code = match daemon() {
         Ok(code) => code,
         Err(err) => {
-            let _ = writeln!(&mut std::io::stderr(), "Error: {}.", err);
-            1
+            let _ = writeln!(&mut io::stderr(), "Error: {}.", err);
+            err.exit_code()
         }
     };
 
@@ -0,0 +1,190 @@
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::json;
+
+use crate::common::{EmptyResult, GenericResult};
+use crate::util::net;
+
+const DEFAULT_PORT: u16 = 1883;
+const TIMEOUT: Duration = Duration::from_secs(10);
+const KEEP_ALIVE: u16 = 60;
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const DISCONNECT: u8 = 0xE0;
+
+/// Publishes controller events to MQTT broker as small JSON messages.
+///
+/// The events are rare, so a new connection is established for each message which allows to not
+/// maintain a persistent connection with keep alive pings. The messages are published with QoS 0
+/// from a background thread to not block the controller on a slow or unavailable broker.
+#[derive(Debug, Clone)]
+pub struct MqttPublisher {
+    topic: String,
+    sender: Sender<(String, String)>,
+}
+
+#[derive(Debug)]
+struct MqttConnection {
+    broker: String,
+    topic: String,
+    client_id: String,
+}
+
+impl MqttPublisher {
+    pub fn new(broker: &str, topic: &str) -> GenericResult<MqttPublisher> {
+        if broker.is_empty() {
+            return Err!("Invalid MQTT broker: it mustn't be empty");
+        }
+
+        if topic.is_empty() || topic.len() > u16::MAX as usize || topic.contains(['+', '#']) {
+            return Err!("Invalid MQTT topic: {:?}", topic);
+        }
+
+        let connection = MqttConnection {
+            broker: net::with_default_port(broker, DEFAULT_PORT),
+            topic: s!(topic),
+            client_id: format!("transmission-controller-{}", process::id()),
+        };
+
+        let (sender, receiver) = mpsc::channel::<(String, String)>();
+        thread::spawn(move || {
+            for (event, payload) in receiver {
+                if let Err(e) = connection.publish(&payload) {
+                    warn!("Failed to publish {} event to {} MQTT broker: {}.", event, connection.broker, e);
+                }
+            }
+        });
+
+        Ok(MqttPublisher {
+            topic: s!(topic),
+            sender: sender,
+        })
+    }
+
+    /// Schedules the event for publishing. The messages which haven't been published before exit are lost.
+    pub fn publish(&self, event: &str, data: serde_json::Value) {
+        let payload = json!({"event": event, "data": data}).to_string();
+        debug!("Publishing {} to {:?} MQTT topic...", payload, self.topic);
+
+        // The publishing thread never exits while there are senders
+        let _ = self.sender.send((s!(event), payload));
+    }
+}
+
+impl MqttConnection {
+    fn publish(&self, payload: &str) -> EmptyResult {
+        let mut stream = self.connect()?;
+
+        let mut body = Vec::new();
+        push_string(&mut body, &self.topic);
+        body.extend_from_slice(payload.as_bytes());
+        stream.write_all(&encode_packet(PUBLISH, &body))?;
+
+        stream.write_all(&encode_packet(DISCONNECT, &[]))?;
+        debug!("The message has been published.");
+
+        Ok(())
+    }
+
+    fn connect(&self) -> GenericResult<TcpStream> {
+        let mut last_error = None;
+        let mut stream = None;
+
+        for address in self.broker.to_socket_addrs().map_err(|e| format!(
+            "Unable to resolve {:?} MQTT broker address: {}", self.broker, e))? {
+            match TcpStream::connect_timeout(&address, TIMEOUT) {
+                Ok(connection) => {
+                    stream = Some(connection);
+                    break;
+                },
+                Err(err) => last_error = Some(err),
+            }
+        }
+
+        let mut stream = match (stream, last_error) {
+            (Some(stream), _) => stream,
+            (None, Some(err)) => return Err!("Unable to connect to {} MQTT broker: {}", self.broker, err),
+            (None, None) => return Err!("Unable to resolve {:?} MQTT broker address", self.broker),
+        };
+
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+
+        let mut body = Vec::new();
+        push_string(&mut body, "MQTT");
+        body.push(4); // Protocol level (MQTT 3.1.1)
+        body.push(0x02); // Clean session
+        body.extend_from_slice(&KEEP_ALIVE.to_be_bytes());
+        push_string(&mut body, &self.client_id);
+        stream.write_all(&encode_packet(CONNECT, &body))?;
+
+        let mut response = [0; 4];
+        stream.read_exact(&mut response)?;
+
+        if response[..2] != [CONNACK, 2] {
+            return Err!("Got an invalid response from {} MQTT broker", self.broker);
+        } else if response[3] != 0 {
+            return Err!("{} MQTT broker has rejected the connection with {} return code",
+                self.broker, response[3]);
+        }
+
+        Ok(stream)
+    }
+}
+
+fn encode_packet(packet_type: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![packet_type];
+    let mut length = body.len();
+
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length != 0 {
+            byte |= 0x80;
+        }
+
+        packet.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+
+    packet.extend_from_slice(body);
+    packet
+}
+
+fn push_string(buffer: &mut Vec<u8>, string: &str) {
+    buffer.extend_from_slice(&(string.len() as u16).to_be_bytes());
+    buffer.extend_from_slice(string.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_packet() {
+        assert_eq!(encode_packet(DISCONNECT, &[]), vec![0xE0, 0x00]);
+        assert_eq!(encode_packet(PUBLISH, &[1, 2, 3]), vec![0x30, 0x03, 1, 2, 3]);
+
+        let body = vec![0; 321];
+        assert_eq!(encode_packet(PUBLISH, &body)[..3], [0x30, 0xC1, 0x02]);
+
+        let body = vec![0; 16384];
+        assert_eq!(encode_packet(PUBLISH, &body)[..4], [0x30, 0x80, 0x80, 0x01]);
+    }
+
+    #[test]
+    fn test_push_string() {
+        let mut buffer = Vec::new();
+        push_string(&mut buffer, "MQTT");
+        assert_eq!(buffer, vec![0, 4, b'M', b'Q', b'T', b'T']);
+    }
+}
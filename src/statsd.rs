@@ -0,0 +1,82 @@
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+use crate::common::GenericResult;
+use crate::util::net;
+
+const DEFAULT_PORT: u16 = 8125;
+
+pub enum Metric<'a> {
+    Gauge(&'a str, u64),
+    Counter(&'a str, u64),
+}
+
+/// Sends controller metrics to statsd daemon.
+///
+/// Metrics are sent over UDP in a fire-and-forget manner: a missing or overloaded statsd daemon
+/// mustn't affect the controller, so send errors are only logged.
+#[derive(Debug)]
+pub struct StatsdClient {
+    socket: UdpSocket,
+    address: SocketAddr,
+    prefix: String,
+}
+
+impl StatsdClient {
+    pub fn new(address: &str, prefix: &str) -> GenericResult<StatsdClient> {
+        let address_string = net::with_default_port(address, DEFAULT_PORT);
+
+        let address = address_string.to_socket_addrs()
+            .map_err(|e| format!("Unable to resolve {:?} statsd address: {}", address_string, e))?
+            .next().ok_or_else(|| format!("Unable to resolve {:?} statsd address", address_string))?;
+
+        let socket = UdpSocket::bind(if address.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })?;
+        socket.set_nonblocking(true)?;
+
+        Ok(StatsdClient {
+            socket: socket,
+            address: address,
+            prefix: s!(prefix.trim_end_matches('.')),
+        })
+    }
+
+    pub fn send(&self, metrics: &[Metric]) {
+        if metrics.is_empty() {
+            return;
+        }
+
+        let payload = format_metrics(&self.prefix, metrics);
+        debug!("Sending metrics to {} statsd:\n{}", self.address, payload);
+
+        if let Err(e) = self.socket.send_to(payload.as_bytes(), self.address) {
+            debug!("Failed to send metrics to {} statsd: {}.", self.address, e);
+        }
+    }
+}
+
+fn format_metrics(prefix: &str, metrics: &[Metric]) -> String {
+    metrics.iter().map(|metric| {
+        let (name, value, metric_type) = match *metric {
+            Metric::Gauge(name, value) => (name, value, "g"),
+            Metric::Counter(name, value) => (name, value, "c"),
+        };
+
+        if prefix.is_empty() {
+            format!("{}:{}|{}", name, value, metric_type)
+        } else {
+            format!("{}.{}:{}|{}", prefix, name, value, metric_type)
+        }
+    }).collect::<Vec<_>>().join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_metrics() {
+        let metrics = [Metric::Gauge("free_space", 42), Metric::Counter("removed", 1)];
+
+        assert_eq!(format_metrics("tc", &metrics), "tc.free_space:42|g\ntc.removed:1|c");
+        assert_eq!(format_metrics("", &metrics), "free_space:42|g\nremoved:1|c");
+    }
+}
@@ -1,40 +1,204 @@
 #![allow(deprecated)] // We still use deprecated RustcDecodable here
 
+use std::collections::BTreeMap;
 use std::convert::From;
 use std::error::Error;
 use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::mem;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::str::FromStr;
-use std::sync::RwLock;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 
 use enum_primitive_serde_shim::impl_serde_for_enum_primitive;
 use itertools::Itertools;
 use mime::{self, Mime};
-use reqwest::{Method, StatusCode, header};
-use reqwest::blocking::{Client, Response};
+use reqwest::{Method, Proxy, StatusCode, Url, header};
+use reqwest::blocking::{Client, Request};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
 use serde::{ser, de, Serialize, Deserialize};
 
+use crate::common::{GenericError, GenericResult};
+use crate::util;
+use crate::util::size::Size;
 use crate::util::time::Timestamp;
 
 pub struct TransmissionClient {
     client: Client,
+    transport: Box<dyn RpcTransport>,
+    connect_timeout: Duration,
+    timeout: Duration,
+    proxy: Option<Proxy>,
+    address_family: AddressFamily,
     url: String,
     user: Option<String>,
     password: Option<String>,
     session_id: RwLock<Option<String>>,
+    max_response_size: u64,
+    processed_marker: ProcessedMarker,
+    // Remote to local download directory prefixes to access the processed marker files by
+    path_remaps: Vec<(PathBuf, PathBuf)>,
+    log_hashes: bool,
+    // Whether the missing alt-speed-enabled session field has been already reported
+    alt_speed_warned: AtomicBool,
+    // Number of RPC calls by method since the last stats request
+    call_stats: Mutex<BTreeMap<String, usize>>,
 }
 
-#[derive(Debug)]
+/// Specifies how processed torrents are marked.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ProcessedMarker {
+    /// A special value of torrent's download limit (it isn't enabled, so doesn't affect the torrent)
+    DownloadLimit,
+    /// A hidden file in torrent's download directory
+    File,
+    /// A torrent label (requires Transmission 3.00+)
+    Label,
+}
+
+impl ProcessedMarker {
+    pub const ALL: [ProcessedMarker; 3] = [
+        ProcessedMarker::DownloadLimit, ProcessedMarker::File, ProcessedMarker::Label];
+
+    fn name(&self) -> &'static str {
+        match *self {
+            ProcessedMarker::DownloadLimit => "download-limit",
+            ProcessedMarker::File          => "file",
+            ProcessedMarker::Label         => "label",
+        }
+    }
+}
+
+impl fmt::Display for ProcessedMarker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for ProcessedMarker {
+    type Err = GenericError;
+
+    fn from_str(string: &str) -> std::result::Result<ProcessedMarker, GenericError> {
+        ProcessedMarker::ALL.iter()
+            .find(|marker| marker.name() == string)
+            .cloned()
+            .ok_or_else(|| format_to!("Invalid processed marker: {:?}", string))
+    }
+}
+
+/// Specifies IP protocol version which is used to connect to Transmission RPC.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AddressFamily {
+    /// Any of the resolved addresses
+    Any,
+    /// IPv4 addresses only
+    Ipv4,
+    /// IPv6 addresses only
+    Ipv6,
+}
+
+impl AddressFamily {
+    pub const ALL: [AddressFamily; 3] = [AddressFamily::Any, AddressFamily::Ipv4, AddressFamily::Ipv6];
+
+    fn name(&self) -> &'static str {
+        match *self {
+            AddressFamily::Any  => "any",
+            AddressFamily::Ipv4 => "ipv4",
+            AddressFamily::Ipv6 => "ipv6",
+        }
+    }
+
+    pub fn matches(&self, address: &IpAddr) -> bool {
+        match *self {
+            AddressFamily::Any  => true,
+            AddressFamily::Ipv4 => address.is_ipv4(),
+            AddressFamily::Ipv6 => address.is_ipv6(),
+        }
+    }
+}
+
+impl fmt::Display for AddressFamily {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for AddressFamily {
+    type Err = GenericError;
+
+    fn from_str(string: &str) -> std::result::Result<AddressFamily, GenericError> {
+        AddressFamily::ALL.iter()
+            .find(|family| family.name() == string)
+            .cloned()
+            .ok_or_else(|| format_to!("Invalid address family: {:?}", string))
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Torrent {
     pub hash: String,
     pub name: String,
     pub status: TorrentStatus,
     pub files: Option<Vec<TorrentFile>>,
     pub download_dir: String,
+    pub tracker_hosts: Vec<String>,
+    pub labels: Vec<String>,
+    pub size: Size,
+    pub added_time: Timestamp,
     pub done: bool,
     pub done_time: Option<Timestamp>,
+    pub left_until_done: u64,
+    // Current download rate in bytes per second
+    pub download_rate: u64,
     pub upload_ratio: Option<f64>,
+    pub private: bool,
+    pub missing_data: bool,
+    pub tracker_error: bool,
+    pub bandwidth_priority: BandwidthPriority,
+    // Maximum seeder count reported by torrent's trackers
+    pub seeders: Option<u64>,
     pub processed: bool,
+    // Whether to add a short hash prefix to the torrent name in log messages (inherited from the client)
+    pub log_hash: bool,
+}
+
+impl Torrent {
+    /// Returns torrent identity for log messages.
+    pub fn id(&self) -> TorrentId<'_> {
+        TorrentId { name: &self.name, hash: &self.hash, log_hash: self.log_hash }
+    }
+
+    /// Returns estimated time until the torrent is downloaded or None if it's unknown (the torrent
+    /// isn't being downloaded at the moment).
+    pub fn eta(&self) -> Option<Duration> {
+        if self.left_until_done == 0 {
+            Some(Duration::ZERO)
+        } else if self.download_rate == 0 {
+            None
+        } else {
+            Some(Duration::from_secs(self.left_until_done.div_ceil(self.download_rate)))
+        }
+    }
+}
+
+pub struct TorrentId<'a> {
+    name: &'a str,
+    hash: &'a str,
+    log_hash: bool,
+}
+
+impl fmt::Display for TorrentId<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.log_hash {
+            write!(f, "'{}' ({})", self.name, self.hash.get(..8).unwrap_or(self.hash))
+        } else {
+            write!(f, "'{}'", self.name)
+        }
+    }
 }
 
 enum_from_primitive! {
@@ -52,10 +216,109 @@ enum_from_primitive! {
 
 impl_serde_for_enum_primitive!(TorrentStatus);
 
-#[derive(Debug)]
+impl TorrentStatus {
+    pub const ALL: [TorrentStatus; 7] = [
+        TorrentStatus::Paused, TorrentStatus::CheckWait, TorrentStatus::Checking, TorrentStatus::DownloadWait,
+        TorrentStatus::Downloading, TorrentStatus::SeedWait, TorrentStatus::Seeding,
+    ];
+
+    fn name(&self) -> &'static str {
+        use self::TorrentStatus::*;
+
+        match *self {
+            Paused       => "Paused",
+            CheckWait    => "CheckWait",
+            Checking     => "Checking",
+            DownloadWait => "DownloadWait",
+            Downloading  => "Downloading",
+            SeedWait     => "SeedWait",
+            Seeding      => "Seeding",
+        }
+    }
+}
+
+impl fmt::Display for TorrentStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for TorrentStatus {
+    type Err = GenericError;
+
+    // Accepts the names case-insensitively, so both "DownloadWait" and "download-wait" are valid
+    fn from_str(string: &str) -> std::result::Result<TorrentStatus, GenericError> {
+        let normalized: String = string.chars()
+            .filter(|&c| c != '-' && c != '_')
+            .flat_map(char::to_lowercase)
+            .collect();
+
+        TorrentStatus::ALL.iter()
+            .find(|status| status.name().to_lowercase() == normalized)
+            .cloned()
+            .ok_or_else(|| format_to!("Invalid torrent status: {:?}", string))
+    }
+}
+
+/// Torrent's bandwidth priority which Transmission takes into account when distributing the bandwidth.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BandwidthPriority {
+    Low,
+    Normal,
+    High,
+}
+
+impl BandwidthPriority {
+    pub const ALL: [BandwidthPriority; 3] = [BandwidthPriority::Low, BandwidthPriority::Normal, BandwidthPriority::High];
+
+    fn name(&self) -> &'static str {
+        match *self {
+            BandwidthPriority::Low    => "low",
+            BandwidthPriority::Normal => "normal",
+            BandwidthPriority::High   => "high",
+        }
+    }
+
+    fn value(&self) -> i8 {
+        match *self {
+            BandwidthPriority::Low    => -1,
+            BandwidthPriority::Normal => 0,
+            BandwidthPriority::High   => 1,
+        }
+    }
+
+    fn from_value(value: i8) -> BandwidthPriority {
+        match value {
+            ..=-1 => BandwidthPriority::Low,
+            0 => BandwidthPriority::Normal,
+            1.. => BandwidthPriority::High,
+        }
+    }
+}
+
+impl fmt::Display for BandwidthPriority {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for BandwidthPriority {
+    type Err = GenericError;
+
+    fn from_str(string: &str) -> std::result::Result<BandwidthPriority, GenericError> {
+        BandwidthPriority::ALL.iter()
+            .find(|priority| priority.name() == string)
+            .cloned()
+            .ok_or_else(|| format_to!("Invalid bandwidth priority: {:?}", string))
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct TorrentFile {
     pub name: String,
     pub selected: bool,
+    // Unselected files may be partially downloaded: the pieces on the boundaries of the selected files
+    pub downloaded: bool,
 }
 
 #[derive(Serialize)]
@@ -71,17 +334,44 @@ pub type EmptyResult = Result<()>;
 
 // Use this value of downloadLimit as marker for processed torrents
 const TORRENT_PROCESSED_MARKER: u64 = 42;
+const TORRENT_PROCESSED_LABEL: &str = "tc-processed";
+const TORRENT_PROCESSED_FILE_PREFIX: &str = ".tc-processed-";
+
+// Use the torrent's own seed ratio limit instead of the global one
+const SEED_RATIO_MODE_SINGLE: u8 = 1;
+
+// Tracker has returned a warning or an error
+const TORRENT_TRACKER_WARNING: u8 = 1;
+const TORRENT_TRACKER_ERROR: u8 = 2;
+
+// Transmission reports this error when the torrent's data has been deleted from the disk
+const TORRENT_LOCAL_ERROR: u8 = 3;
+const TORRENT_MISSING_DATA_ERROR: &str = "No data found";
 
 const SESSION_ID_HEADER_NAME: &str = "X-Transmission-Session-Id";
 
+pub const DEFAULT_MAX_RESPONSE_SIZE: u64 = 100 * 1024 * 1024;
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
 impl TransmissionClient{
     pub fn new(url: &str) -> TransmissionClient {
         TransmissionClient {
-            client: Client::builder().timeout(Duration::from_secs(10)).build().unwrap(),
+            client: build_client(DEFAULT_TIMEOUT, DEFAULT_TIMEOUT, None, AddressFamily::Any).unwrap(),
+            transport: Box::new(HttpTransport),
+            connect_timeout: DEFAULT_TIMEOUT,
+            timeout: DEFAULT_TIMEOUT,
+            proxy: None,
+            address_family: AddressFamily::Any,
             url: s!(url),
             user: None,
             password: None,
             session_id: RwLock::new(None),
+            max_response_size: DEFAULT_MAX_RESPONSE_SIZE,
+            processed_marker: ProcessedMarker::DownloadLimit,
+            path_remaps: Vec::new(),
+            log_hashes: false,
+            alt_speed_warned: AtomicBool::new(false),
+            call_stats: Mutex::new(BTreeMap::new()),
         }
     }
 
@@ -90,16 +380,77 @@ impl TransmissionClient{
         self.password = Some(s!(password));
     }
 
+    pub fn set_connect_timeout(&mut self, timeout: Duration) {
+        self.connect_timeout = timeout;
+        self.client = build_client(self.connect_timeout, self.timeout, self.proxy.clone(), self.address_family).unwrap();
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+        self.client = build_client(self.connect_timeout, self.timeout, self.proxy.clone(), self.address_family).unwrap();
+    }
+
+    pub fn set_address_family(&mut self, family: AddressFamily) {
+        self.address_family = family;
+        self.client = build_client(self.connect_timeout, self.timeout, self.proxy.clone(), self.address_family).unwrap();
+    }
+
+    pub fn set_socks_proxy(&mut self, url: &str) -> GenericResult<()> {
+        if !url.starts_with("socks5://") && !url.starts_with("socks5h://") {
+            return Err!("Invalid SOCKS proxy URL: {:?} (socks5:// or socks5h:// scheme is expected)", url);
+        }
+
+        let proxy = Proxy::all(url).map_err(|e| format!("Invalid SOCKS proxy URL {:?}: {}", url, e))?;
+        self.client = build_client(self.connect_timeout, self.timeout, Some(proxy.clone()), self.address_family)?;
+        self.proxy = Some(proxy);
+
+        Ok(())
+    }
+
+    /// Appends the specified query string parameters to RPC URL (e.g. auth tokens required by a
+    /// reverse proxy).
+    pub fn set_query_params(&mut self, params: &[(String, String)]) -> GenericResult<()> {
+        let mut url = Url::parse(&self.url).map_err(|e| format!("Invalid RPC URL {:?}: {}", self.url, e))?;
+        url.query_pairs_mut().extend_pairs(params.iter().map(|(name, value)| (name, value)));
+        self.url = url.into();
+        Ok(())
+    }
+
+    pub fn set_max_response_size(&mut self, size: u64) {
+        self.max_response_size = size;
+    }
+
+    pub fn set_processed_marker(&mut self, marker: ProcessedMarker) {
+        self.processed_marker = marker;
+    }
+
+    pub fn set_path_remaps(&mut self, path_remaps: &[(PathBuf, PathBuf)]) {
+        self.path_remaps = path_remaps.to_vec();
+    }
+
+    /// Enables adding a short hash prefix to names of the torrents returned by the client in log messages.
+    pub fn set_log_hashes(&mut self, enabled: bool) {
+        self.log_hashes = enabled;
+    }
+
     pub fn is_manual_mode(&self) -> Result<bool> {
         #[derive(Deserialize)]
         struct Response {
             #[serde(rename = "alt-speed-enabled")]
-            alt_speed_enabled: bool,
+            alt_speed_enabled: Option<bool>,
         }
 
         let response: Response = self.call("session-get", &EmptyRequest{})?;
 
-        Ok(response.alt_speed_enabled)
+        // The field may be missing in some Transmission versions - don't fail the whole control
+        // cycle because of it and assume automatic mode.
+        Ok(response.alt_speed_enabled.unwrap_or_else(|| {
+            if !self.alt_speed_warned.swap(true, Ordering::Relaxed) {
+                warn!("Transmission session info doesn't contain alt-speed-enabled field. \
+                       Assuming automatic mode.");
+            }
+            false
+        }))
     }
 
     pub fn set_manual_mode(&self, enabled: bool) -> EmptyResult {
@@ -117,19 +468,41 @@ impl TransmissionClient{
     }
 
     pub fn get_torrents(&self) -> Result<Vec<Torrent>> {
-        self._get_torrents(None, false)
+        Ok(self._get_torrents(None, false)?.into_iter().map(|(torrent, _)| torrent).collect())
     }
 
     pub fn get_torrent(&self, hash: &str) -> Result<Torrent> {
         let mut torrents = self._get_torrents(Some(vec![s!(hash)]), true)?;
         match torrents.len() {
             0 => Err(Rpc(TorrentNotFoundError(s!(hash)))),
-            1 => Ok(torrents.pop().unwrap()),
+            1 => Ok(torrents.pop().unwrap().0),
             _ => Err(Protocol(s!("Got a few torrents when requested only one"))),
         }
     }
 
-    fn _get_torrents(&self, hashes: Option<Vec<String>>, with_files: bool) -> Result<Vec<Torrent>> {
+    /// Migrates the torrents which have been marked as processed with the legacy marker to the configured one.
+    pub fn migrate_processed_markers(&self) -> EmptyResult {
+        if self.processed_marker == ProcessedMarker::DownloadLimit {
+            return Ok(());
+        }
+
+        for (torrent, legacy_processed) in self._get_torrents(None, false)? {
+            if !legacy_processed {
+                continue;
+            }
+
+            info!("Migrating {} torrent to {} processed marker...", torrent.id(), self.processed_marker);
+            if let Err(err) = self.set_processed(&torrent) {
+                error!("Failed to migrate {} torrent to {} processed marker: {}.",
+                    torrent.id(), self.processed_marker, err);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Returns the torrents along with the flag whether they are marked as processed only with the legacy marker
+    fn _get_torrents(&self, hashes: Option<Vec<String>>, with_files: bool) -> Result<Vec<(Torrent, bool)>> {
         #[derive(Serialize)]
         struct Request {
             #[serde(skip_serializing_if = "Option::is_none")]
@@ -155,20 +528,52 @@ impl TransmissionClient{
             wanted: Vec<u8>,
             #[serde(rename = "leftUntilDone")]
             left_until_done: u64,
+            #[serde(rename = "rateDownload", default)]
+            rate_download: u64,
+            #[serde(rename = "sizeWhenDone")]
+            size_when_done: Size,
             #[serde(rename = "doneDate")]
             done_date: Timestamp,
             #[serde(rename = "downloadLimit")]
             download_limit: u64,
+            #[serde(rename = "bandwidthPriority", default)]
+            bandwidth_priority: i8,
             files: Option<Vec<File>>,
             #[serde(rename = "fileStats")]
             file_stats: Option<Vec<FileStats>>,
             #[serde(rename = "uploadRatio")]
             upload_ratio: f64,
+            #[serde(rename = "isPrivate")]
+            is_private: bool,
+            error: u8,
+            #[serde(rename = "errorString")]
+            error_string: String,
+            trackers: Vec<Tracker>,
+            #[serde(rename = "trackerStats", default)]
+            tracker_stats: Vec<TrackerStats>,
+            // Supported since Transmission 3.00
+            #[serde(default)]
+            labels: Vec<String>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Tracker {
+            announce: String,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct TrackerStats {
+            // -1 when unknown
+            #[serde(rename = "seederCount")]
+            seeder_count: i64,
         }
 
         #[derive(Debug, Deserialize)]
         struct File {
             name: String,
+            length: u64,
+            #[serde(rename = "bytesCompleted")]
+            bytes_completed: u64,
         }
 
         #[derive(Debug, Deserialize)]
@@ -178,13 +583,34 @@ impl TransmissionClient{
 
         let mut fields = vec![
             "hashString", "name", "downloadDir", "status", "addedDate", "wanted", "leftUntilDone", "doneDate",
-            "downloadLimit", "uploadRatio",
+            "downloadLimit", "uploadRatio", "sizeWhenDone", "isPrivate", "error", "errorString",
+            "trackers", "trackerStats", "labels", "bandwidthPriority", "rateDownload",
         ];
         if with_files {
             fields.push("files");
             fields.push("fileStats");
         }
 
+        fn get_files(torrent: &mut TransmissionTorrent) -> Result<Vec<TorrentFile>> {
+            let file_infos = torrent.files.take().ok_or_else(|| Protocol(s!(
+                "Got a torrent with missing `files`")))?;
+
+            let file_stats = torrent.file_stats.take().ok_or_else(|| Protocol(s!(
+                "Got a torrent with missing `fileStats`")))?;
+
+            if file_infos.len() != file_stats.len() {
+                return Err(Protocol(s!("Torrent's `files` and `fileStats` don't match")))
+            }
+
+            Ok(file_infos.iter().zip(&file_stats).map(|item| {
+                TorrentFile {
+                    name: item.0.name.to_owned(),
+                    selected: item.1.wanted,
+                    downloaded: item.0.bytes_completed >= item.0.length,
+                }
+            }).collect())
+        }
+
         let response: Response = self.call("torrent-get", &Request {
             ids: hashes,
             fields: fields,
@@ -192,27 +618,12 @@ impl TransmissionClient{
 
         let mut torrents = Vec::with_capacity(response.torrents.len());
 
-        for torrent in response.torrents {
-            let mut files = None;
-
-            if with_files {
-                let file_infos = torrent.files.ok_or_else(|| Protocol(s!(
-                    "Got a torrent with missing `files`")))?;
-
-                let file_stats = torrent.file_stats.ok_or_else(|| Protocol(s!(
-                    "Got a torrent with missing `fileStats`")))?;
-
-                if file_infos.len() != file_stats.len() {
-                    return Err(Protocol(s!("Torrent's `files` and `fileStats` don't match")))
-                }
-
-                files = Some(file_infos.iter().zip(&file_stats).map(|item| {
-                    TorrentFile {
-                        name: item.0.name.to_owned(),
-                        selected: item.1.wanted,
-                    }
-                }).collect());
-            }
+        for mut torrent in response.torrents {
+            let files = if with_files {
+                Some(get_files(&mut torrent)?)
+            } else {
+                None
+            };
 
             // It's not actually easy to determine when torrent is downloaded:
             // * doneDate is not reset when we add new files to download
@@ -232,21 +643,51 @@ impl TransmissionClient{
                 None
             };
 
-            torrents.push(Torrent {
-                hash:         torrent.hash_string,
-                name:         torrent.name.clone(),
-                status:       torrent.status,
-                files:        files,
-                download_dir: torrent.download_dir.clone(),
-                done:         done,
-                done_time:    done_time,
-                upload_ratio: if torrent.upload_ratio > 0.0 {
+            let tracker_hosts = torrent.trackers.iter().filter_map(|tracker| {
+                let url = Url::parse(&tracker.announce).ok()?;
+                url.host_str().map(|host| host.to_lowercase())
+            }).unique().collect();
+
+            let legacy_processed = torrent.download_limit == TORRENT_PROCESSED_MARKER;
+            let processed = match self.processed_marker {
+                ProcessedMarker::DownloadLimit => legacy_processed,
+                ProcessedMarker::File => self.get_processed_marker_path(&torrent.download_dir, &torrent.hash_string)
+                    .exists(),
+                ProcessedMarker::Label => torrent.labels.iter().any(|label| label == TORRENT_PROCESSED_LABEL),
+            };
+
+            let torrent = Torrent {
+                hash:          torrent.hash_string,
+                name:          torrent.name.clone(),
+                status:        torrent.status,
+                files:         files,
+                download_dir:  torrent.download_dir.clone(),
+                tracker_hosts: tracker_hosts,
+                labels:        torrent.labels,
+                size:          torrent.size_when_done,
+                added_time:    torrent.added_date,
+                done:          done,
+                done_time:     done_time,
+                left_until_done: torrent.left_until_done,
+                download_rate: torrent.rate_download,
+                upload_ratio:  if torrent.upload_ratio > 0.0 {
                     Some(torrent.upload_ratio)
                 } else {
                     None
                 },
-                processed:    torrent.download_limit == TORRENT_PROCESSED_MARKER,
-            });
+                private:       torrent.is_private,
+                missing_data:  torrent.error == TORRENT_LOCAL_ERROR &&
+                               torrent.error_string.starts_with(TORRENT_MISSING_DATA_ERROR),
+                tracker_error: torrent.error == TORRENT_TRACKER_WARNING || torrent.error == TORRENT_TRACKER_ERROR,
+                bandwidth_priority: BandwidthPriority::from_value(torrent.bandwidth_priority),
+                seeders:       torrent.tracker_stats.iter()
+                                   .filter_map(|stats| u64::try_from(stats.seeder_count).ok())
+                                   .max(),
+                processed:     processed || legacy_processed,
+                log_hash:      self.log_hashes,
+            };
+
+            torrents.push((torrent, legacy_processed && !processed));
         }
 
         Ok(torrents)
@@ -265,6 +706,19 @@ impl TransmissionClient{
         Ok(())
     }
 
+    pub fn reannounce(&self, hash: &str) -> EmptyResult {
+        #[derive(Serialize)]
+        struct Request {
+            ids: Vec<String>,
+        }
+
+        let _: EmptyResponse = self.call("torrent-reannounce", &Request {
+            ids: vec![s!(hash)]
+        })?;
+
+        Ok(())
+    }
+
     pub fn stop(&self, hash: &str) -> EmptyResult {
         #[derive(Serialize)]
         struct Request {
@@ -278,7 +732,77 @@ impl TransmissionClient{
         Ok(())
     }
 
-    pub fn set_processed(&self, hash: &str) -> EmptyResult {
+    pub fn verify(&self, hash: &str) -> EmptyResult {
+        #[derive(Serialize)]
+        struct Request {
+            ids: Vec<String>,
+        }
+
+        let _: EmptyResponse = self.call("torrent-verify", &Request {
+            ids: vec![s!(hash)]
+        })?;
+
+        Ok(())
+    }
+
+    pub fn set_processed(&self, torrent: &Torrent) -> EmptyResult {
+        match self.processed_marker {
+            ProcessedMarker::DownloadLimit => self.set_download_limit(&torrent.hash, TORRENT_PROCESSED_MARKER)?,
+
+            ProcessedMarker::File => self.create_processed_marker_file(&torrent.download_dir, &torrent.hash)?,
+
+            ProcessedMarker::Label => {
+                #[derive(Serialize)]
+                struct Request {
+                    ids: Vec<String>,
+                    labels: Vec<String>,
+                }
+
+                let mut labels = torrent.labels.clone();
+                if !labels.iter().any(|label| label == TORRENT_PROCESSED_LABEL) {
+                    labels.push(s!(TORRENT_PROCESSED_LABEL));
+                }
+
+                let _: EmptyResponse = self.call("torrent-set", &Request {
+                    ids: vec![torrent.hash.clone()],
+                    labels: labels,
+                })?;
+            },
+        }
+
+        Ok(())
+    }
+
+    pub fn reset_processed(&self, torrent: &Torrent) -> EmptyResult {
+        match self.processed_marker {
+            ProcessedMarker::DownloadLimit => {},
+
+            ProcessedMarker::File => self.remove_processed_marker_file(&torrent.download_dir, &torrent.hash)?,
+
+            ProcessedMarker::Label => {
+                #[derive(Serialize)]
+                struct Request {
+                    ids: Vec<String>,
+                    labels: Vec<String>,
+                }
+
+                let labels = torrent.labels.iter()
+                    .filter(|label| *label != TORRENT_PROCESSED_LABEL)
+                    .cloned().collect();
+
+                let _: EmptyResponse = self.call("torrent-set", &Request {
+                    ids: vec![torrent.hash.clone()],
+                    labels: labels,
+                })?;
+            },
+        }
+
+        // The legacy marker is cleared regardless of the marker type: otherwise the torrent would be
+        // migrated back to the processed state.
+        self.set_download_limit(&torrent.hash, 0)
+    }
+
+    fn set_download_limit(&self, hash: &str, limit: u64) -> EmptyResult {
         #[derive(Serialize)]
         struct Request {
             ids: Vec<String>,
@@ -288,13 +812,101 @@ impl TransmissionClient{
 
         let _: EmptyResponse = self.call("torrent-set", &Request {
             ids: vec![s!(hash)],
-            download_limit: TORRENT_PROCESSED_MARKER,
+            download_limit: limit,
+        })?;
+
+        Ok(())
+    }
+
+    pub fn set_bandwidth_priority(&self, hash: &str, priority: BandwidthPriority) -> EmptyResult {
+        #[derive(Serialize)]
+        struct Request {
+            ids: Vec<String>,
+            #[serde(rename = "bandwidthPriority")]
+            bandwidth_priority: i8,
+        }
+
+        let _: EmptyResponse = self.call("torrent-set", &Request {
+            ids: vec![s!(hash)],
+            bandwidth_priority: priority.value(),
+        })?;
+
+        Ok(())
+    }
+
+    pub fn set_seed_ratio_limit(&self, hash: &str, ratio: f64) -> EmptyResult {
+        #[derive(Serialize)]
+        struct Request {
+            ids: Vec<String>,
+            #[serde(rename = "seedRatioLimit")]
+            seed_ratio_limit: f64,
+            #[serde(rename = "seedRatioMode")]
+            seed_ratio_mode: u8,
+        }
+
+        let _: EmptyResponse = self.call("torrent-set", &Request {
+            ids: vec![s!(hash)],
+            seed_ratio_limit: ratio,
+            seed_ratio_mode: SEED_RATIO_MODE_SINGLE,
+        })?;
+
+        Ok(())
+    }
+
+    pub fn set_location(&self, torrent: &Torrent, location: &str, move_data: bool) -> EmptyResult {
+        #[derive(Serialize)]
+        struct Request<'a> {
+            ids: Vec<String>,
+            location: &'a str,
+            #[serde(rename = "move")]
+            move_data: bool,
+        }
+
+        // The marker file doesn't move with the data, so create it in the new location in advance
+        if self.processed_marker == ProcessedMarker::File && torrent.processed {
+            self.create_processed_marker_file(location, &torrent.hash)?;
+        }
+
+        let _: EmptyResponse = self.call("torrent-set-location", &Request {
+            ids: vec![torrent.hash.clone()],
+            location: location,
+            move_data: move_data,
         })?;
 
         Ok(())
     }
 
-    pub fn remove(&self, hash: &str) -> EmptyResult {
+    // Transmission's download directory is shared between the torrents, so the marker file name is
+    // unique for each torrent. The directory is remote, so it's remapped to the local path.
+    fn get_processed_marker_path(&self, download_dir: &str, hash: &str) -> PathBuf {
+        util::fs::remap_path(download_dir, &self.path_remaps).join(s!(TORRENT_PROCESSED_FILE_PREFIX) + hash)
+    }
+
+    fn create_processed_marker_file(&self, download_dir: &str, hash: &str) -> EmptyResult {
+        let path = self.get_processed_marker_path(download_dir, hash);
+        fs::write(&path, "").map_err(|e| Marker(format!(
+            "Failed to create '{}' processed marker file: {}", path.display(), e)))
+    }
+
+    fn remove_processed_marker_file(&self, download_dir: &str, hash: &str) -> EmptyResult {
+        let path = self.get_processed_marker_path(download_dir, hash);
+
+        match fs::remove_file(&path) {
+            Err(ref e) if e.kind() != io::ErrorKind::NotFound => Err(Marker(format!(
+                "Failed to remove '{}' processed marker file: {}", path.display(), e))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Removes the processed marker left in the torrent's previous location after its relocation.
+    pub fn remove_stale_processed_marker(&self, hash: &str, old_location: &str) -> EmptyResult {
+        if self.processed_marker == ProcessedMarker::File {
+            self.remove_processed_marker_file(old_location, hash)?;
+        }
+        Ok(())
+    }
+
+    pub fn remove(&self, torrent: &Torrent, delete_data: bool) -> EmptyResult {
         #[derive(Serialize)]
         struct Request {
             ids: Vec<String>,
@@ -303,14 +915,25 @@ impl TransmissionClient{
         }
 
         let _: EmptyResponse = self.call("torrent-remove", &Request {
-            ids: vec![s!(hash)],
-            delete_local_data: true,
+            ids: vec![torrent.hash.clone()],
+            delete_local_data: delete_data,
         })?;
 
+        if self.processed_marker == ProcessedMarker::File {
+            self.remove_processed_marker_file(&torrent.download_dir, &torrent.hash)?;
+        }
+
         Ok(())
     }
 
+    /// Returns the number of RPC calls made since the previous request grouped by method.
+    pub fn take_call_stats(&self) -> BTreeMap<String, usize> {
+        mem::take(&mut *self.call_stats.lock().unwrap())
+    }
+
     fn call<I: ser::Serialize, O: de::DeserializeOwned>(&self, method: &str, arguments: &I) -> Result<O> {
+        *self.call_stats.lock().unwrap().entry(s!(method)).or_default() += 1;
+
         self._call(method, arguments).map_err(|e| {
             trace!("RPC error: {}.", e);
             e
@@ -338,31 +961,31 @@ impl TransmissionClient{
         )))?;
 
         trace!("RPC call: {}", request_json);
-        let mut response = self.send_request(&request_json)?;
+        let session_id = self.session_id.read().unwrap().clone();
+        let mut response = self.send_request(&request_json, session_id.as_deref())?;
 
-        if response.status() == StatusCode::CONFLICT {
-            let session_id = response.headers().get(SESSION_ID_HEADER_NAME)
+        if response.status == StatusCode::CONFLICT {
+            let new_session_id = response.headers.get(SESSION_ID_HEADER_NAME)
                 .ok_or_else(|| Protocol(format!(
                     "Got {} HTTP status code without {} header",
-                    response.status(), SESSION_ID_HEADER_NAME)))
+                    response.status, SESSION_ID_HEADER_NAME)))
                 .and_then(|value| {
                     Ok(value.to_str().map_err(|_| Protocol(format!(
                         "Got an invalid {} header value: {:?}",
                         SESSION_ID_HEADER_NAME, value)))?.to_owned())
                 })?;
 
-            debug!("Session ID is expired. Got a new session ID.");
-            *self.session_id.write().unwrap() = Some(session_id);
-            response = self.send_request(&request_json)?;
+            let session_id = self.update_session_id(session_id.as_deref(), new_session_id);
+            response = self.send_request(&request_json, session_id.as_deref())?;
         }
 
-        if response.status() != StatusCode::OK {
-            return Err(Internal(format!("Got {} HTTP status code", response.status())));
+        if response.status != StatusCode::OK {
+            return Err(Internal(format!("Got {} HTTP status code", response.status)));
         }
 
-        response.headers().get(header::CONTENT_TYPE)
+        response.headers.get(header::CONTENT_TYPE)
             .ok_or_else(|| Protocol(format!(
-                "Server returned {} response without Content-Type", response.status())))
+                "Server returned {} response without Content-Type", response.status)))
             .and_then(|value| {
                 value.to_str().map_err(|_| Protocol(format!(
                     "Got an invalid Content-Type header value: {:?}", value)))
@@ -376,12 +999,22 @@ impl TransmissionClient{
                     }
                 }).ok_or_else(|| Protocol(format!(
                     "Server returned {} response with an invalid content type: {}",
-                    response.status(), content_type
+                    response.status, content_type
                 )))
             })?;
 
+        // Don't trust the server: read no more than the limit to not run out of memory on a
+        // misbehaving endpoint.
         let mut body = Vec::new();
-        response.copy_to(&mut body)?;
+        response.body.take(self.max_response_size.saturating_add(1)).read_to_end(&mut body).map_err(|e| {
+            Connection(format!("Failed to read the response: {}", e))
+        })?;
+
+        if body.len() as u64 > self.max_response_size {
+            return Err(Protocol(format!(
+                "Server returned a response which exceeds the maximum allowed size ({} bytes)",
+                self.max_response_size)));
+        }
 
         let body = String::from_utf8(body).map_err(|_| Protocol(s!(
             "Server returned an invalid UTF-8 response")))?;
@@ -400,7 +1033,22 @@ impl TransmissionClient{
         }
     }
 
-    fn send_request(&self, body: &str) -> Result<Response> {
+    // The session ID may be concurrently updated by other threads which got the same 409 response,
+    // so update it only if it hasn't been changed since the request to not overwrite a newer one.
+    fn update_session_id(&self, expired_session_id: Option<&str>, new_session_id: String) -> Option<String> {
+        let mut session_id = self.session_id.write().unwrap();
+
+        if session_id.as_deref() == expired_session_id {
+            debug!("Session ID is expired. Got a new session ID.");
+            *session_id = Some(new_session_id);
+        } else {
+            debug!("Session ID is expired. It has been already updated by a concurrent request.");
+        }
+
+        session_id.clone()
+    }
+
+    fn send_request(&self, body: &str, session_id: Option<&str>) -> Result<RpcResponse> {
         let mut request = self.client.request(Method::POST, &self.url)
             .header(header::CONTENT_TYPE, "application/json");
 
@@ -408,14 +1056,77 @@ impl TransmissionClient{
             request = request.basic_auth(user, Some(password));
         }
 
-        {
-            let session_id = self.session_id.read().unwrap();
-            if let Some(ref session_id) = *session_id {
-                request = request.header(SESSION_ID_HEADER_NAME, session_id.as_str());
-            }
+        if let Some(session_id) = session_id {
+            request = request.header(SESSION_ID_HEADER_NAME, session_id);
         }
 
-        Ok(request.body(body.to_owned()).send()?)
+        let request = request.body(body.to_owned()).build()?;
+        Ok(self.transport.send(&self.client, request)?)
+    }
+}
+
+pub struct RpcResponse {
+    pub status: StatusCode,
+    pub headers: header::HeaderMap,
+    pub body: Box<dyn Read + Send>,
+}
+
+/// Sends HTTP requests to Transmission daemon. Allows to test the protocol logic without a live
+/// daemon.
+pub trait RpcTransport: Send + Sync {
+    fn send(&self, client: &Client, request: Request) -> reqwest::Result<RpcResponse> {
+        let response = client.execute(request)?;
+
+        Ok(RpcResponse {
+            status: response.status(),
+            headers: response.headers().clone(),
+            body: Box::new(response),
+        })
+    }
+}
+
+pub struct HttpTransport;
+impl RpcTransport for HttpTransport {}
+
+fn build_client(
+    connect_timeout: Duration, timeout: Duration, proxy: Option<Proxy>, address_family: AddressFamily,
+) -> reqwest::Result<Client> {
+    let mut builder = Client::builder()
+        .connect_timeout(connect_timeout)
+        .timeout(timeout);
+
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(proxy);
+    }
+
+    if address_family != AddressFamily::Any {
+        builder = builder.dns_resolver(Arc::new(AddressFamilyResolver(address_family)));
+    }
+
+    builder.build()
+}
+
+// Resolves host names to the addresses of the specified family only
+struct AddressFamilyResolver(AddressFamily);
+
+impl Resolve for AddressFamilyResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let (family, host) = (self.0, s!(name.as_str()));
+
+        // Resolving is blocking, so it's done by tokio on its blocking thread pool to not stall the client's runtime
+        Box::pin(async move {
+            let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0)).await
+                .map_err(|e| format!("Unable to resolve {:?}: {}", host, e))?
+                .filter(|address| family.matches(&address.ip()))
+                .collect();
+
+            if addresses.is_empty() {
+                return Err(format!("{:?} has no {} addresses", host, family).into());
+            }
+            debug!("{} has been resolved to {}.", host, addresses.iter().map(|address| address.ip()).join(", "));
+
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
     }
 }
 
@@ -426,6 +1137,7 @@ pub enum TransmissionClientError {
     Internal(String),
     Protocol(String),
     Rpc(TransmissionRpcError),
+    Marker(String),
 }
 use self::TransmissionClientError::*;
 
@@ -444,13 +1156,30 @@ impl fmt::Display for TransmissionClientError {
                                                             "Error in communication with Transmission daemon: {}", err),
             Rpc(ref err) => write!(f,
                                    "Transmission daemon returned an error: {}", err),
+            Marker(ref err) => write!(f, "{}", err),
         }
     }
 }
 
 impl From<reqwest::Error> for TransmissionClientError {
-    fn from(err: reqwest::Error) -> TransmissionClientError {
-        Connection(err.to_string())
+    fn from(mut err: reqwest::Error) -> TransmissionClientError {
+        // The query string may contain auth tokens (see set_query_params()), so don't expose it in
+        // error messages
+        if let Some(url) = err.url_mut() {
+            url.set_query(None);
+        }
+
+        // reqwest doesn't include the underlying error (failed address resolution, refused connection, etc.)
+        // into its message, so add the whole chain.
+        let mut message = err.to_string();
+        let mut source = err.source();
+
+        while let Some(error) = source {
+            message += &format!(": {}", error);
+            source = error.source();
+        }
+
+        Connection(message)
     }
 }
 
@@ -476,4 +1205,470 @@ impl fmt::Display for TransmissionRpcError {
             TorrentNotFoundError(_) => write!(f, "The specified torrent doesn't exist"),
         }
     }
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+pub mod tests {
+    use std::collections::VecDeque;
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::json;
+
+    use crate::util::fs::tests::create_temp_dir;
+
+    use super::*;
+
+    pub type RequestLog = Arc<Mutex<Vec<(Option<String>, serde_json::Value)>>>;
+
+    struct RpcTransportMock {
+        responses: Mutex<VecDeque<(StatusCode, Option<&'static str>, String)>>,
+        requests: RequestLog,
+    }
+
+    impl RpcTransport for RpcTransportMock {
+        fn send(&self, _client: &Client, request: Request) -> reqwest::Result<RpcResponse> {
+            let session_id = request.headers().get(SESSION_ID_HEADER_NAME)
+                .map(|value| s!(value.to_str().unwrap()));
+            let body = serde_json::from_slice(request.body().unwrap().as_bytes().unwrap()).unwrap();
+            self.requests.lock().unwrap().push((session_id, body));
+
+            let (status, session_id, body) = self.responses.lock().unwrap().pop_front()
+                .expect("Got an unexpected request");
+
+            let mut headers = header::HeaderMap::new();
+            headers.insert(header::CONTENT_TYPE, header::HeaderValue::from_static("application/json"));
+            if let Some(session_id) = session_id {
+                headers.insert(SESSION_ID_HEADER_NAME, header::HeaderValue::from_static(session_id));
+            }
+
+            Ok(RpcResponse {
+                status: status,
+                headers: headers,
+                body: Box::new(Cursor::new(body.into_bytes())),
+            })
+        }
+    }
+
+    pub fn mock_client(responses: Vec<(StatusCode, Option<&'static str>, serde_json::Value)>) -> (TransmissionClient, RequestLog) {
+        let requests = RequestLog::default();
+
+        let mut client = TransmissionClient::new("http://localhost:9091/transmission/rpc");
+        client.transport = Box::new(RpcTransportMock {
+            responses: Mutex::new(responses.into_iter()
+                .map(|(status, session_id, body)| (status, session_id, body.to_string()))
+                .collect()),
+            requests: requests.clone(),
+        });
+
+        (client, requests)
+    }
+
+    /// Returns a downloaded unprocessed torrent.
+    pub fn mock_torrent(hash: &str) -> Torrent {
+        Torrent {
+            hash:          s!(hash),
+            name:          s!(hash),
+            status:        TorrentStatus::Seeding,
+            files:         None,
+            download_dir:  s!("/downloads"),
+            tracker_hosts: Vec::new(),
+            labels:        Vec::new(),
+            size:          0,
+            added_time:    0,
+            done:          true,
+            done_time:     Some(0),
+            left_until_done: 0,
+            download_rate: 0,
+            upload_ratio:  None,
+            private:       false,
+            missing_data:  false,
+            tracker_error: false,
+            bandwidth_priority: BandwidthPriority::Normal,
+            seeders:       None,
+            processed:     false,
+            log_hash:      false,
+        }
+    }
+
+    pub fn success(arguments: serde_json::Value) -> (StatusCode, Option<&'static str>, serde_json::Value) {
+        (StatusCode::OK, None, json!({"result": "success", "arguments": arguments}))
+    }
+
+    #[test]
+    fn test_start() {
+        let (client, requests) = mock_client(vec![success(json!({}))]);
+        client.start("some-hash").unwrap();
+
+        assert_eq!(*requests.lock().unwrap(), vec![
+            (None, json!({"method": "torrent-start", "arguments": {"ids": ["some-hash"]}})),
+        ]);
+    }
+
+    #[test]
+    fn test_verify() {
+        let (client, requests) = mock_client(vec![success(json!({}))]);
+        client.verify("some-hash").unwrap();
+
+        assert_eq!(*requests.lock().unwrap(), vec![
+            (None, json!({"method": "torrent-verify", "arguments": {"ids": ["some-hash"]}})),
+        ]);
+    }
+
+    #[test]
+    fn test_get_torrents() {
+        let (client, requests) = mock_client(vec![success(json!({"torrents": [{
+            "hashString": "some-hash",
+            "name": "Some torrent",
+            "downloadDir": "/downloads",
+            "status": 6,
+            "addedDate": 1000,
+            "wanted": [1, 0],
+            "leftUntilDone": 0,
+            "sizeWhenDone": 4096,
+            "doneDate": 2000,
+            "downloadLimit": 42,
+            "bandwidthPriority": 1,
+            "uploadRatio": 1.5,
+            "isPrivate": true,
+            "error": 3,
+            "errorString": "No data found! Ensure your drives are connected or use \"Set Location\".",
+            "trackers": [
+                {"announce": "http://Tracker.example.com/announce"},
+                {"announce": "udp://tracker.example.com:80"},
+            ],
+            "trackerStats": [{"seederCount": 5}, {"seederCount": -1}],
+        }]}))]);
+
+        let torrents = client.get_torrents().unwrap();
+        assert_eq!(torrents.len(), 1);
+
+        let torrent = &torrents[0];
+        assert_eq!(torrent.hash, "some-hash");
+        assert_eq!(torrent.status, TorrentStatus::Seeding);
+        assert_eq!(torrent.tracker_hosts, vec![s!("tracker.example.com")]);
+        assert!(torrent.labels.is_empty());
+        assert_eq!(torrent.size, 4096);
+        assert!(torrent.done);
+        assert_eq!(torrent.done_time, Some(2000));
+        assert_eq!(torrent.upload_ratio, Some(1.5));
+        assert!(torrent.private);
+        assert!(torrent.missing_data);
+        assert_eq!(torrent.bandwidth_priority, BandwidthPriority::High);
+        assert_eq!(torrent.seeders, Some(5));
+        assert!(torrent.processed);
+        assert!(torrent.files.is_none());
+
+        assert_eq!(*requests.lock().unwrap(), vec![
+            (None, json!({"method": "torrent-get", "arguments": {"fields": [
+                "hashString", "name", "downloadDir", "status", "addedDate", "wanted", "leftUntilDone", "doneDate",
+                "downloadLimit", "uploadRatio", "sizeWhenDone", "isPrivate", "error", "errorString",
+                "trackers", "trackerStats", "labels", "bandwidthPriority", "rateDownload",
+            ]}})),
+        ]);
+    }
+
+    #[test]
+    fn test_migrate_processed_markers() {
+        let torrent = |hash: &str, labels: serde_json::Value| json!({
+            "hashString": hash,
+            "name": "Some torrent",
+            "downloadDir": "/downloads",
+            "status": 6,
+            "addedDate": 1000,
+            "wanted": [1],
+            "leftUntilDone": 0,
+            "sizeWhenDone": 4096,
+            "doneDate": 2000,
+            "downloadLimit": 42,
+            "uploadRatio": 0,
+            "isPrivate": false,
+            "error": 0,
+            "errorString": "",
+            "trackers": [],
+            "labels": labels,
+        });
+
+        let (mut client, requests) = mock_client(vec![
+            success(json!({"torrents": [
+                torrent("legacy", json!(["movies"])),
+                torrent("migrated", json!(["movies", TORRENT_PROCESSED_LABEL])),
+            ]})),
+            success(json!({})),
+        ]);
+        client.set_processed_marker(ProcessedMarker::Label);
+        client.migrate_processed_markers().unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.iter().skip(1).map(|(_, request)| request.clone()).collect::<Vec<_>>(), vec![
+            json!({"method": "torrent-set", "arguments": {
+                "ids": ["legacy"], "labels": ["movies", TORRENT_PROCESSED_LABEL],
+            }}),
+        ]);
+    }
+
+    #[test]
+    fn test_processed_marker_file_remap() {
+        let temp_dir = create_temp_dir("processed-marker-file-remap");
+
+        let torrents = success(json!({"torrents": [{
+            "hashString": "some-hash",
+            "name": "Some torrent",
+            "downloadDir": "/remote/downloads",
+            "status": 6,
+            "addedDate": 1000,
+            "wanted": [1],
+            "leftUntilDone": 0,
+            "sizeWhenDone": 4096,
+            "doneDate": 2000,
+            "downloadLimit": 0,
+            "uploadRatio": 0,
+            "isPrivate": false,
+            "error": 0,
+            "errorString": "",
+            "trackers": [],
+        }]}));
+
+        let (mut client, _) = mock_client(vec![torrents.clone(), torrents, success(json!({}))]);
+        client.set_processed_marker(ProcessedMarker::File);
+        client.set_path_remaps(&[(PathBuf::from("/remote/downloads"), temp_dir.clone())]);
+
+        let torrent = client.get_torrents().unwrap().pop().unwrap();
+        assert!(!torrent.processed);
+
+        let marker_path = temp_dir.join(s!(TORRENT_PROCESSED_FILE_PREFIX) + "some-hash");
+        client.set_processed(&torrent).unwrap();
+        assert!(marker_path.exists());
+        assert!(client.get_torrents().unwrap().pop().unwrap().processed);
+
+        client.reset_processed(&torrent).unwrap();
+        assert!(!marker_path.exists());
+
+        fs::remove_dir_all(&temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_set_processed() {
+        let (client, requests) = mock_client(vec![success(json!({}))]);
+
+        client.set_processed(&mock_torrent("some-hash")).unwrap();
+
+        assert_eq!(*requests.lock().unwrap(), vec![
+            (None, json!({"method": "torrent-set", "arguments": {"ids": ["some-hash"], "downloadLimit": 42}})),
+        ]);
+    }
+
+    #[test]
+    fn test_reset_processed() {
+        let (mut client, requests) = mock_client(vec![success(json!({})); 3]);
+
+        let torrent = Torrent {
+            labels: vec![s!("movies"), s!(TORRENT_PROCESSED_LABEL)],
+            processed: true,
+            ..mock_torrent("some-hash")
+        };
+        client.reset_processed(&torrent).unwrap();
+
+        client.set_processed_marker(ProcessedMarker::Label);
+        client.reset_processed(&torrent).unwrap();
+
+        assert_eq!(*requests.lock().unwrap(), vec![
+            (None, json!({"method": "torrent-set", "arguments": {"ids": ["some-hash"], "downloadLimit": 0}})),
+            (None, json!({"method": "torrent-set", "arguments": {"ids": ["some-hash"], "labels": ["movies"]}})),
+            (None, json!({"method": "torrent-set", "arguments": {"ids": ["some-hash"], "downloadLimit": 0}})),
+        ]);
+    }
+
+    #[test]
+    fn test_set_bandwidth_priority() {
+        let (client, requests) = mock_client(vec![success(json!({}))]);
+        client.set_bandwidth_priority("some-hash", BandwidthPriority::Low).unwrap();
+
+        assert_eq!(*requests.lock().unwrap(), vec![
+            (None, json!({"method": "torrent-set", "arguments": {"ids": ["some-hash"], "bandwidthPriority": -1}})),
+        ]);
+    }
+
+    #[test]
+    fn test_set_seed_ratio_limit() {
+        let (client, requests) = mock_client(vec![success(json!({}))]);
+        client.set_seed_ratio_limit("some-hash", 2.5).unwrap();
+
+        assert_eq!(*requests.lock().unwrap(), vec![
+            (None, json!({"method": "torrent-set", "arguments": {
+                "ids": ["some-hash"], "seedRatioLimit": 2.5, "seedRatioMode": 1,
+            }})),
+        ]);
+    }
+
+    #[test]
+    fn test_call_stats() {
+        let (client, _) = mock_client(vec![success(json!({})), success(json!({})), success(json!({}))]);
+        client.start("some-hash").unwrap();
+        client.stop("some-hash").unwrap();
+        client.start("other-hash").unwrap();
+
+        assert_eq!(client.take_call_stats().into_iter().collect::<Vec<_>>(), vec![
+            (s!("torrent-start"), 2), (s!("torrent-stop"), 1),
+        ]);
+        assert!(client.take_call_stats().is_empty());
+    }
+
+    #[test]
+    fn test_session_id_renewal() {
+        let (client, requests) = mock_client(vec![
+            (StatusCode::CONFLICT, Some("new-session-id"), json!({})),
+            success(json!({})),
+            success(json!({})),
+        ]);
+
+        client.stop("some-hash").unwrap();
+        client.stop("some-hash").unwrap();
+
+        let request = json!({"method": "torrent-stop", "arguments": {"ids": ["some-hash"]}});
+        assert_eq!(*requests.lock().unwrap(), vec![
+            (None, request.clone()),
+            (Some(s!("new-session-id")), request.clone()),
+            (Some(s!("new-session-id")), request.clone()),
+        ]);
+    }
+
+    #[test]
+    fn test_is_manual_mode() {
+        let (client, _) = mock_client(vec![
+            success(json!({"alt-speed-enabled": true, "version": "3.00"})),
+            success(json!({"version": "5.00"})), success(json!({"version": "5.00"})),
+        ]);
+
+        assert!(client.is_manual_mode().unwrap());
+        assert!(!client.alt_speed_warned.load(Ordering::Relaxed));
+
+        assert!(!client.is_manual_mode().unwrap());
+        assert!(client.alt_speed_warned.load(Ordering::Relaxed));
+        assert!(!client.is_manual_mode().unwrap());
+    }
+
+    #[test]
+    fn test_concurrent_session_id_update() {
+        let (client, _) = mock_client(Vec::new());
+        *client.session_id.write().unwrap() = Some(s!("old-session-id"));
+
+        assert_eq!(client.update_session_id(Some("old-session-id"), s!("new-session-id")),
+                   Some(s!("new-session-id")));
+
+        // A concurrent request which used the expired session ID mustn't overwrite the new one
+        assert_eq!(client.update_session_id(Some("old-session-id"), s!("other-session-id")),
+                   Some(s!("new-session-id")));
+        assert_eq!(*client.session_id.read().unwrap(), Some(s!("new-session-id")));
+    }
+
+    #[test]
+    fn test_query_params() {
+        let (mut client, requests) = mock_client(vec![
+            (StatusCode::CONFLICT, Some("new-session-id"), json!({})),
+            success(json!({})),
+        ]);
+
+        client.set_query_params(&[(s!("token"), s!("some secret")), (s!("user"), s!("admin"))]).unwrap();
+        assert_eq!(client.url, "http://localhost:9091/transmission/rpc?token=some+secret&user=admin");
+
+        client.stop("some-hash").unwrap();
+
+        let request = json!({"method": "torrent-stop", "arguments": {"ids": ["some-hash"]}});
+        assert_eq!(*requests.lock().unwrap(), vec![
+            (None, request.clone()),
+            (Some(s!("new-session-id")), request.clone()),
+        ]);
+    }
+
+    #[test]
+    fn test_rpc_error() {
+        let (client, _) = mock_client(vec![(StatusCode::OK, None, json!({"result": "some error"}))]);
+        assert_eq!(
+            client.set_manual_mode(true).unwrap_err().to_string(),
+            "Transmission daemon returned an error: some error",
+        );
+    }
+
+    #[test]
+    fn test_torrent_id() {
+        let torrent = Torrent {name: s!("Some torrent"), ..mock_torrent("0123456789abcdef")};
+
+        let mut id = torrent.id();
+        id.log_hash = false;
+        assert_eq!(id.to_string(), "'Some torrent'");
+
+        id.log_hash = true;
+        assert_eq!(id.to_string(), "'Some torrent' (01234567)");
+    }
+
+    #[test]
+    fn test_torrent_eta() {
+        let mut torrent = Torrent {
+            status: TorrentStatus::Downloading,
+            size: 1000,
+            done: false,
+            done_time: None,
+            left_until_done: 1000,
+            download_rate: 300,
+            ..mock_torrent("some-hash")
+        };
+        assert_eq!(torrent.eta(), Some(Duration::from_secs(4)));
+
+        torrent.download_rate = 0;
+        assert_eq!(torrent.eta(), None);
+
+        torrent.left_until_done = 0;
+        assert_eq!(torrent.eta(), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_torrent_status_names() {
+        for status in TorrentStatus::ALL.iter() {
+            assert_eq!(status.to_string().parse::<TorrentStatus>().unwrap(), *status);
+            assert_eq!(status.to_string().to_lowercase().parse::<TorrentStatus>().unwrap(), *status);
+            assert_eq!(status.to_string().to_uppercase().parse::<TorrentStatus>().unwrap(), *status);
+        }
+
+        assert_eq!(TorrentStatus::Downloading.to_string(), "Downloading");
+        assert_eq!("download-wait".parse::<TorrentStatus>().unwrap(), TorrentStatus::DownloadWait);
+        assert_eq!("seed_wait".parse::<TorrentStatus>().unwrap(), TorrentStatus::SeedWait);
+
+        assert_eq!("unknown".parse::<TorrentStatus>().unwrap_err().to_string(), "Invalid torrent status: \"unknown\"");
+        assert!("".parse::<TorrentStatus>().is_err());
+    }
+
+    #[test]
+    fn test_address_family() {
+        for family in AddressFamily::ALL.iter() {
+            assert_eq!(family.to_string().parse::<AddressFamily>().unwrap(), *family);
+        }
+        assert!("inet6".parse::<AddressFamily>().is_err());
+
+        let (ipv4, ipv6): (IpAddr, IpAddr) = ("127.0.0.1".parse().unwrap(), "::1".parse().unwrap());
+        assert!(AddressFamily::Any.matches(&ipv4) && AddressFamily::Any.matches(&ipv6));
+        assert!(AddressFamily::Ipv4.matches(&ipv4) && !AddressFamily::Ipv4.matches(&ipv6));
+        assert!(!AddressFamily::Ipv6.matches(&ipv4) && AddressFamily::Ipv6.matches(&ipv6));
+    }
+
+    #[test]
+    fn test_query_params_redaction() {
+        let mut client = TransmissionClient::new("http://127.0.0.1:1/transmission/rpc");
+        client.set_query_params(&[(s!("token"), s!("secret"))]).unwrap();
+
+        let err = client.set_manual_mode(true).unwrap_err().to_string();
+        assert!(err.contains("127.0.0.1:1/transmission/rpc"), "{}", err);
+        assert!(!err.contains("secret"), "{}", err);
+    }
+
+    #[test]
+    fn test_processed_marker_names() {
+        for marker in ProcessedMarker::ALL.iter() {
+            assert_eq!(marker.to_string().parse::<ProcessedMarker>().unwrap(), *marker);
+        }
+
+        assert_eq!("download-limit".parse::<ProcessedMarker>().unwrap(), ProcessedMarker::DownloadLimit);
+        assert!("downloadLimit".parse::<ProcessedMarker>().is_err());
+    }
+}
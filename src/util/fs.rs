@@ -1,50 +1,352 @@
+use std::cmp;
+use std::error::Error;
+use std::ffi::{CString, OsString};
+use std::fmt;
 use std::fs::{self, File, OpenOptions};
-use std::io::{self, ErrorKind};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
 use std::time::{Instant, Duration};
 
 use regex::Regex;
 
-use crate::common::{EmptyResult, GenericResult};
+use crate::common::GenericResult;
 use crate::util::process::{RunCommandProvider, RunCommand};
 
-pub fn copy_downloaded_file<S: AsRef<Path>, D: AsRef<Path>>(src: S, dst: D) -> EmptyResult {
+#[derive(Debug)]
+pub enum FsError {
+    NotFound(String),
+    NoSpace(String),
+    Permission(String),
+    Other(String),
+}
+use self::FsError::*;
+
+pub type FsResult<T> = Result<T, FsError>;
+pub type EmptyFsResult = FsResult<()>;
+
+impl FsError {
+    pub fn new(err: &io::Error, message: String) -> FsError {
+        if is_no_such_file_error(err) || err.kind() == ErrorKind::NotFound {
+            return NotFound(message);
+        }
+
+        if err.kind() == ErrorKind::PermissionDenied {
+            return Permission(message);
+        }
+
+        if let Some(errno) = err.raw_os_error() {
+            if errno == libc::ENOSPC || errno == libc::EDQUOT {
+                return NoSpace(message);
+            }
+        }
+
+        Other(message)
+    }
+
+    /// Changes the error message preserving the error category.
+    pub fn map<F: FnOnce(String) -> String>(self, f: F) -> FsError {
+        match self {
+            NotFound(err) => NotFound(f(err)),
+            NoSpace(err) => NoSpace(f(err)),
+            Permission(err) => Permission(f(err)),
+            Other(err) => Other(f(err)),
+        }
+    }
+}
+
+impl Error for FsError {
+    fn description(&self) -> &str {
+        "file system error"
+    }
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NotFound(ref err) | NoSpace(ref err) | Permission(ref err) | Other(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// Options of the downloaded files copying.
+#[derive(Debug, Clone, Copy)]
+pub struct CopyOptions {
+    pub buffer_size: usize,
+    // Maximum copying speed in bytes per second
+    pub bandwidth: Option<u64>,
+}
+
+impl Default for CopyOptions {
+    fn default() -> CopyOptions {
+        CopyOptions {
+            // Matches the buffer size of io::copy()
+            buffer_size: 8 * 1024,
+            bandwidth: None,
+        }
+    }
+}
+
+/// Copies the file via a hidden partial file which is renamed to the destination path on success.
+///
+/// If the partial file is left from an interrupted copying, the copying is resumed from its end
+/// when its data matches the source file or restarted otherwise. An existing destination file with
+/// the same data as the source one is considered as already copied, so copying of a multi-file
+/// torrent may be safely retried.
+pub fn copy_downloaded_file<S: AsRef<Path>, D: AsRef<Path>>(src: S, dst: D, options: CopyOptions) -> EmptyFsResult {
+    let src = src.as_ref();
     let mut src_file = open_downloaded_file(src)?;
 
     let dst = dst.as_ref();
-    let mut dst_file = OpenOptions::new()
-        .create_new(true)
+    match fs::symlink_metadata(dst) {
+        Ok(metadata) => {
+            let map_compare_error = |e: io::Error| FsError::new(&e, format!(
+                "Failed to compare '{}' with '{}': {}", src.display(), dst.display(), e));
+
+            if metadata.is_file() && is_same_data(&mut src_file, dst).map_err(map_compare_error)? {
+                debug!("'{}' is already copied to '{}'.", src.display(), dst.display());
+                return Ok(());
+            }
+
+            return Err(Other(format!("Failed to create '{}': it already exists", dst.display())));
+        },
+        Err(ref e) if e.kind() == ErrorKind::NotFound => {},
+        Err(e) => return Err(FsError::new(&e, format!("Failed to stat() '{}': {}", dst.display(), e))),
+    }
+
+    let partial_path = get_partial_copy_path(dst).ok_or_else(|| Other(format!(
+        "Invalid destination path: '{}'", dst.display())))?;
+
+    let mut partial_file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .read(true)
         .write(true)
-        .open(dst)
-        .map_err(|e| format!("Failed to create '{}': {}", dst.display(), e))?;
+        .open(&partial_path)
+        .map_err(|e| FsError::new(&e, format!("Failed to create '{}': {}", partial_path.display(), e)))?;
+
+    let map_copy_error = |e: io::Error| FsError::new(&e, format!(
+        "Failed to copy '{}' to '{}': {}", src.display(), partial_path.display(), e));
 
-    io::copy(&mut src_file, &mut dst_file)?;
+    match get_copy_resume_offset(&mut src_file, &mut partial_file).map_err(map_copy_error)? {
+        0 => {
+            partial_file.set_len(0).map_err(map_copy_error)?;
+            copy_file(&mut src_file, &mut partial_file, options).map_err(map_copy_error)?;
+        },
+        offset => {
+            info!("Resuming copying of '{}' from {} bytes offset...", src.display(), offset);
+            src_file.seek(SeekFrom::Start(offset)).map_err(map_copy_error)?;
+            partial_file.seek(SeekFrom::Start(offset)).map_err(map_copy_error)?;
+            copy_data(&mut src_file, &mut partial_file, options).map_err(map_copy_error)?;
+        },
+    }
+
+    fs::rename(&partial_path, dst).map_err(|e| FsError::new(&e, format!(
+        "Failed to rename '{}' to '{}': {}", partial_path.display(), dst.display(), e)))?;
 
     Ok(())
 }
 
-pub fn check_directory<P: AsRef<Path>>(path: P) -> EmptyResult {
-    let path = path.as_ref();
+fn is_same_data(src: &mut File, dst: &Path) -> io::Result<bool> {
+    let mut dst = File::open(dst)?;
+    if src.metadata()?.len() != dst.metadata()?.len() {
+        return Ok(false);
+    }
 
-    let metadata = match fs::metadata(path) {
-        Ok(metadata) => Ok(metadata),
-        Err(err) => Err(
-            if is_no_such_file_error(&err) {
-                format!("'{}' doesn't exist", path.display())
-            } else {
-                format!("'{}': {}", path.display(), err)
+    let mut src_buffer = vec![0; 64 * 1024];
+    let mut dst_buffer = vec![0; 64 * 1024];
+
+    let same = loop {
+        let size = src.read(&mut src_buffer)?;
+        if size == 0 {
+            break true;
+        }
+
+        dst.read_exact(&mut dst_buffer[..size])?;
+        if src_buffer[..size] != dst_buffer[..size] {
+            break false;
+        }
+    };
+
+    src.seek(SeekFrom::Start(0))?;
+    Ok(same)
+}
+
+fn get_partial_copy_path(path: &Path) -> Option<PathBuf> {
+    let mut file_name = OsString::from(".");
+    file_name.push(path.file_name()?);
+    file_name.push(".tc-partial");
+    Some(path.with_file_name(file_name))
+}
+
+// We always write the data sequentially, so the partial file must be a prefix of the source file.
+// Check its tail to not resume copying to a file with some unrelated data.
+fn get_copy_resume_offset(src: &mut File, partial: &mut File) -> io::Result<u64> {
+    const CHECK_SIZE: u64 = 1024 * 1024;
+
+    let (src_size, partial_size) = (src.metadata()?.len(), partial.metadata()?.len());
+    if partial_size == 0 || partial_size > src_size {
+        return Ok(0);
+    }
+
+    let check_size = cmp::min(partial_size, CHECK_SIZE);
+    let mut src_data = vec![0; check_size as usize];
+    let mut partial_data = vec![0; check_size as usize];
+
+    src.seek(SeekFrom::Start(partial_size - check_size))?;
+    src.read_exact(&mut src_data)?;
+
+    partial.seek(SeekFrom::Start(partial_size - check_size))?;
+    partial.read_exact(&mut partial_data)?;
+
+    src.seek(SeekFrom::Start(0))?;
+    partial.seek(SeekFrom::Start(0))?;
+
+    if src_data != partial_data {
+        warn!("Partially copied data doesn't match the source file. Restarting the copying...");
+        return Ok(0);
+    }
+
+    Ok(partial_size)
+}
+
+#[cfg(target_os = "linux")]
+fn copy_file(src: &mut File, dst: &mut File, options: CopyOptions) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    // Reflink is instant and shares the extents, so holes are preserved automatically
+    if unsafe { libc::ioctl(dst.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) } == 0 {
+        return Ok(());
+    }
+
+    let err = io::Error::last_os_error();
+    if !is_unsupported_copy_error(&err) {
+        return Err(err);
+    }
+
+    // The data must pass through user space to be able to limit the bandwidth
+    if options.bandwidth.is_some() {
+        return copy_data(src, dst, options);
+    }
+
+    match copy_data_segments(src, dst) {
+        Err(ref err) if is_unsupported_copy_error(err) => {
+            debug!("Falling back to a regular copy: {}.", err);
+
+            src.seek(SeekFrom::Start(0))?;
+            dst.seek(SeekFrom::Start(0))?;
+            dst.set_len(0)?;
+
+            copy_data(src, dst, options)
+        },
+        result => result,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn copy_file(src: &mut File, dst: &mut File, options: CopyOptions) -> io::Result<()> {
+    copy_data(src, dst, options)
+}
+
+/// Copies the data in user space using a buffer of the specified size and pacing the throughput if the bandwidth is
+/// limited.
+fn copy_data<R: Read, W: Write>(src: &mut R, dst: &mut W, options: CopyOptions) -> io::Result<()> {
+    let mut buffer = vec![0; options.buffer_size];
+    let start_time = Instant::now();
+    let mut copied: u64 = 0;
+
+    loop {
+        let size = match src.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(size) => size,
+            Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+
+        dst.write_all(&buffer[..size])?;
+        copied += size as u64;
+
+        if let Some(bandwidth) = options.bandwidth {
+            let expected_time = Duration::from_secs_f64(copied as f64 / bandwidth as f64);
+            if let Some(delay) = expected_time.checked_sub(start_time.elapsed()) {
+                thread::sleep(delay);
+            }
+        }
+    }
+}
+
+/// Copies only data segments of the file via copy_file_range() leaving holes unallocated.
+#[cfg(target_os = "linux")]
+fn copy_data_segments(src: &File, dst: &File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let (src_fd, dst_fd) = (src.as_raw_fd(), dst.as_raw_fd());
+    let size = src.metadata()?.len() as libc::off_t;
+    let mut offset = 0;
+
+    while offset < size {
+        let data_start = unsafe { libc::lseek(src_fd, offset, libc::SEEK_DATA) };
+        if data_start < 0 {
+            let err = io::Error::last_os_error();
+
+            // There is only a hole till the end of the file
+            if err.raw_os_error() == Some(libc::ENXIO) {
+                break;
             }
-        )
-    }?;
+
+            return Err(err);
+        }
+
+        let data_end = unsafe { libc::lseek(src_fd, data_start, libc::SEEK_HOLE) };
+        if data_end < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let (mut src_offset, mut dst_offset) = (data_start, data_start);
+
+        while src_offset < data_end {
+            let copied = unsafe { libc::copy_file_range(
+                src_fd, &mut src_offset, dst_fd, &mut dst_offset, (data_end - src_offset) as usize, 0) };
+
+            match copied {
+                0 => return Err(io::Error::new(ErrorKind::UnexpectedEof, "The file has been truncated during copying")),
+                copied if copied < 0 => return Err(io::Error::last_os_error()),
+                _ => {},
+            }
+        }
+
+        offset = data_end;
+    }
+
+    dst.set_len(size as u64)
+}
+
+#[cfg(target_os = "linux")]
+fn is_unsupported_copy_error(error: &io::Error) -> bool {
+    matches!(error.raw_os_error(), Some(
+        libc::ENOSYS | libc::EXDEV | libc::EOPNOTSUPP | libc::ENOTTY | libc::EINVAL | libc::EBADF))
+}
+
+pub fn check_directory<P: AsRef<Path>>(path: P) -> EmptyFsResult {
+    let path = path.as_ref();
+
+    let metadata = fs::metadata(path).map_err(|err| {
+        if is_no_such_file_error(&err) {
+            FsError::new(&err, format!("'{}' doesn't exist", path.display()))
+        } else {
+            FsError::new(&err, format!("'{}': {}", path.display(), err))
+        }
+    })?;
 
     if !metadata.is_dir() {
-        return Err!("'{}' is not a directory", path.display());
+        return Err(Other(format!("'{}' is not a directory", path.display())));
     }
 
     Ok(())
 }
 
-pub fn check_existing_directory<P: AsRef<Path>>(path: P) -> GenericResult<bool> {
+pub fn check_existing_directory<P: AsRef<Path>>(path: P) -> FsResult<bool> {
     let path = path.as_ref();
 
     let exists = match fs::metadata(path) {
@@ -52,14 +354,14 @@ pub fn check_existing_directory<P: AsRef<Path>>(path: P) -> GenericResult<bool>
             if metadata.is_dir() {
                 true
             } else {
-                return Err!("It already exists and is not a directory");
+                return Err(Other(s!("It already exists and is not a directory")));
             }
         },
         Err(err) => {
             if err.kind() == io::ErrorKind::NotFound {
                 false
             } else {
-                return Err(From::from(err));
+                return Err(FsError::new(&err, err.to_string()));
             }
         }
     };
@@ -71,7 +373,7 @@ pub fn check_existing_directory<P: AsRef<Path>>(path: P) -> GenericResult<bool>
 ///
 /// Uses optimistic scenario optimized for the case when the directories already exist. If `path`
 /// is empty, only checks that `base` directory exists.
-pub fn create_all_dirs_from_base<B: AsRef<Path>, P: AsRef<Path>>(base: B, path: P) -> EmptyResult {
+pub fn create_all_dirs_from_base<B: AsRef<Path>, P: AsRef<Path>>(base: B, path: P) -> EmptyFsResult {
     let (base, mut path) = (base.as_ref(), path.as_ref());
 
     assert!(path.is_relative());
@@ -82,8 +384,8 @@ pub fn create_all_dirs_from_base<B: AsRef<Path>, P: AsRef<Path>>(base: B, path:
     while path.components().next().is_some() {
         let full_path = base.join(path);
 
-        if check_existing_directory(&full_path).map_err(|e| format!(
-            "Failed to create '{}' directory: {}", full_path.display(), e)
+        if check_existing_directory(&full_path).map_err(|e| e.map(|err| format!(
+            "Failed to create '{}' directory: {}", full_path.display(), err))
         )? {
             checked = true;
             break;
@@ -105,7 +407,8 @@ pub fn create_all_dirs_from_base<B: AsRef<Path>, P: AsRef<Path>>(base: B, path:
                 // We've got a race. Retry the attempt to create the directory.
                 io::ErrorKind::AlreadyExists => continue,
 
-                _ => return Err!("Failed to create '{}' directory: {}", full_path.display(), err),
+                _ => return Err(FsError::new(&err, format!(
+                    "Failed to create '{}' directory: {}", full_path.display(), err))),
             }
         } else {
             checked = true;
@@ -119,13 +422,131 @@ pub fn create_all_dirs_from_base<B: AsRef<Path>, P: AsRef<Path>>(base: B, path:
 
     for path in deferred_paths.iter().rev() {
         let full_path = base.join(path);
-        fs::create_dir(&full_path).map_err(|e| format!(
-            "Failed to create '{}' directory: {}", full_path.display(), e))?;
+        fs::create_dir(&full_path).map_err(|e| FsError::new(&e, format!(
+            "Failed to create '{}' directory: {}", full_path.display(), e)))?;
     }
 
     Ok(())
 }
 
+/// Owner to set for the created files.
+pub struct FileOwner {
+    uid: Option<u32>,
+    gid: Option<u32>,
+    permission_error_reported: AtomicBool,
+}
+
+impl FileOwner {
+    pub fn new(uid: Option<u32>, gid: Option<u32>) -> FileOwner {
+        FileOwner {
+            uid: uid,
+            gid: gid,
+            permission_error_reported: AtomicBool::new(false),
+        }
+    }
+
+    /// Changes owner of the file. Lack of privileges is reported only once and isn't considered as an error.
+    pub fn apply<P: AsRef<Path>>(&self, path: P) -> EmptyFsResult {
+        let path = path.as_ref();
+
+        match std::os::unix::fs::lchown(path, self.uid, self.gid) {
+            Ok(()) => Ok(()),
+            Err(err) if err.raw_os_error() == Some(libc::EPERM) => {
+                if !self.permission_error_reported.swap(true, Ordering::Relaxed) {
+                    warn!("Unable to change owner of the copied files: {}.", err);
+                }
+                Ok(())
+            },
+            Err(err) => Err(FsError::new(&err, format!("Failed to change owner of '{}': {}", path.display(), err))),
+        }
+    }
+}
+
+// Each clone reports lack of privileges on its own
+impl Clone for FileOwner {
+    fn clone(&self) -> FileOwner {
+        FileOwner::new(self.uid, self.gid)
+    }
+}
+
+/// Resolves user name or ID to user ID.
+pub fn get_user_id(user: &str) -> GenericResult<u32> {
+    if let Ok(uid) = user.parse() {
+        return Ok(uid);
+    }
+
+    let name = CString::new(user).map_err(|_| format!("Invalid user name: {:?}", user))?;
+    let passwd = unsafe { libc::getpwnam(name.as_ptr()) };
+    if passwd.is_null() {
+        return Err!("Unknown user: {:?}", user);
+    }
+
+    Ok(unsafe { (*passwd).pw_uid })
+}
+
+/// Resolves group name or ID to group ID.
+pub fn get_group_id(group: &str) -> GenericResult<u32> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+
+    let name = CString::new(group).map_err(|_| format!("Invalid group name: {:?}", group))?;
+    let group_info = unsafe { libc::getgrnam(name.as_ptr()) };
+    if group_info.is_null() {
+        return Err!("Unknown group: {:?}", group);
+    }
+
+    Ok(unsafe { (*group_info).gr_gid })
+}
+
+/// Maps the path on Transmission's host to the local one using the most specific of the prefix remaps.
+pub fn remap_path<P: AsRef<Path>>(path: P, remaps: &[(PathBuf, PathBuf)]) -> PathBuf {
+    let path = path.as_ref();
+
+    let remap = remaps.iter()
+        .filter(|(prefix, _)| path.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.components().count());
+
+    match remap {
+        Some((prefix, local_prefix)) => {
+            let suffix = path.strip_prefix(prefix).unwrap();
+            if suffix.as_os_str().is_empty() {
+                local_prefix.clone()
+            } else {
+                local_prefix.join(suffix)
+            }
+        },
+        None => path.to_path_buf(),
+    }
+}
+
+/// Checks whether the path resolves (following symlinks) to a location outside of the base
+/// directory. Nonexistent paths are considered safe: there is nothing to follow.
+pub fn escapes_directory<P: AsRef<Path>, B: AsRef<Path>>(path: P, base: B) -> FsResult<bool> {
+    let (path, base) = (path.as_ref(), base.as_ref());
+
+    let base = fs::canonicalize(base).map_err(|e| FsError::new(&e, format!(
+        "Unable to resolve '{}' path: {}", base.display(), e)))?;
+
+    let path = match fs::canonicalize(path) {
+        Ok(path) => path,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(FsError::new(&e, format!("Unable to resolve '{}' path: {}", path.display(), e))),
+    };
+
+    Ok(!path.starts_with(&base))
+}
+
+/// Source of the device usage information which is used to decide whether the cleanup is needed.
+pub trait FreeSpaceProvider {
+    fn get_device_usage(&self, path: &Path) -> GenericResult<(String, u8)> {
+        get_device_usage(path)
+    }
+}
+
+pub struct FreeSpace;
+impl FreeSpaceProvider for FreeSpace {}
+
 pub fn get_device_usage<P: AsRef<Path>>(path: P) -> GenericResult<(String, u8)> {
     _get_device_usage(path, &RunCommand)
 }
@@ -151,6 +572,11 @@ fn _get_device_usage<P: AsRef<Path>>(path: P, provider: &dyn RunCommandProvider)
         return get_parse_error()
     }
 
+    if !lines[0].contains("Use%") {
+        debug!("Got an unexpected `df` output header: {}", lines[0]);
+        return Err!("Got an unexpected output header from `df` (probably it's localized)");
+    }
+
     let output_re = Regex::new(r"(?x)^
         \s*(?P<device>.*?)    # Device
         (?:\s+\d+){3}         # Blocks, Used, Available
@@ -169,7 +595,7 @@ fn _get_device_usage<P: AsRef<Path>>(path: P, provider: &dyn RunCommandProvider)
 }
 
 // Transmission 4.X has a bug due to which torrents are marked as downloaded before their renaming from *.part files.
-fn open_downloaded_file<P: AsRef<Path>>(path: P) -> GenericResult<File> {
+fn open_downloaded_file<P: AsRef<Path>>(path: P) -> FsResult<File> {
     let path = path.as_ref();
     let start_time = Instant::now();
     let mut check_part_file = true;
@@ -179,7 +605,7 @@ fn open_downloaded_file<P: AsRef<Path>>(path: P) -> GenericResult<File> {
             Ok(file) => return Ok(file),
             Err(err) => {
                 if err.kind() != ErrorKind::NotFound || !check_part_file {
-                    return Err!("Failed to open '{}': {}", path.display(), err);
+                    return Err(FsError::new(&err, format!("Failed to open '{}': {}", path.display(), err)));
                 }
 
                 let part_path = {
@@ -191,7 +617,8 @@ fn open_downloaded_file<P: AsRef<Path>>(path: P) -> GenericResult<File> {
                 match fs::metadata(&part_path) {
                     Ok(_) => {
                         if start_time.elapsed().as_secs() >= 5 {
-                            return Err!("'{}' hasn't been downloaded ('{}' still exists)", path.display(), part_path.display());
+                            return Err(Other(format!(
+                                "'{}' hasn't been downloaded ('{}' still exists)", path.display(), part_path.display())));
                         }
                         std::thread::sleep(Duration::from_millis(100));
                     },
@@ -199,7 +626,7 @@ fn open_downloaded_file<P: AsRef<Path>>(path: P) -> GenericResult<File> {
                         ErrorKind::NotFound => {
                             check_part_file = false
                         },
-                        _ => return Err!("'{}': {}", part_path.display(), err)
+                        _ => return Err(FsError::new(&err, format!("'{}': {}", part_path.display(), err)))
                     }
                 }
             }
@@ -218,8 +645,173 @@ fn is_no_such_file_error(error: &io::Error) -> bool {
 }
 
 #[cfg(test)]
-mod tests {
+pub mod tests {
+    use std::fs::{self, OpenOptions};
+    use std::io::{self, Seek, SeekFrom, Write};
+    use std::path::PathBuf;
+
+    use std::time::{Duration, Instant};
+
     use crate::util::process::tests::RunCommandMock;
+    use super::{CopyOptions, FsError};
+
+    pub fn create_temp_dir(name: &str) -> PathBuf {
+        let temp_dir = std::env::temp_dir().join(format!(
+            "transmission-controller-{}-{}", name, std::process::id()));
+
+        let _ = fs::remove_dir_all(&temp_dir);
+        fs::create_dir_all(&temp_dir).unwrap();
+
+        temp_dir
+    }
+
+    #[test]
+    fn test_copy_sparse_file() {
+        let temp_dir = create_temp_dir("copy-sparse-file");
+
+        let (src, dst) = (temp_dir.join("src"), temp_dir.join("dst"));
+        let size = 10 * 1024 * 1024;
+
+        {
+            let mut file = OpenOptions::new().create_new(true).write(true).open(&src).unwrap();
+            file.set_len(size).unwrap();
+            file.seek(SeekFrom::Start(size / 2)).unwrap();
+            file.write_all(b"data in the middle").unwrap();
+        }
+
+        super::copy_downloaded_file(&src, &dst, CopyOptions::default()).unwrap();
+        assert_eq!(fs::read(&src).unwrap(), fs::read(&dst).unwrap());
+
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let blocks = |path| fs::metadata(path).unwrap().blocks();
+            assert!(blocks(&dst) <= blocks(&src));
+        }
+
+        fs::remove_dir_all(&temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_escapes_directory() {
+        let temp_dir = create_temp_dir("escapes-directory");
+
+        let (base, outside) = (temp_dir.join("base"), temp_dir.join("outside"));
+        fs::create_dir(&base).unwrap();
+        fs::create_dir(&outside).unwrap();
+        fs::write(base.join("file"), "data").unwrap();
+        std::os::unix::fs::symlink(&outside, base.join("link")).unwrap();
+        std::os::unix::fs::symlink(base.join("file"), base.join("file-link")).unwrap();
+
+        assert!(!super::escapes_directory(base.join("file"), &base).unwrap());
+        assert!(!super::escapes_directory(base.join("file-link"), &base).unwrap());
+        assert!(!super::escapes_directory(base.join("missing"), &base).unwrap());
+        assert!(super::escapes_directory(base.join("link"), &base).unwrap());
+
+        fs::remove_dir_all(&temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_copy_data() {
+        let data: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+
+        for buffer_size in [1, 7, 1000, 4096] {
+            let mut copy = Vec::new();
+            let options = CopyOptions { buffer_size: buffer_size, bandwidth: None };
+            super::copy_data(&mut io::Cursor::new(&data), &mut copy, options).unwrap();
+            assert_eq!(copy, data);
+        }
+    }
+
+    #[test]
+    fn test_copy_data_bandwidth() {
+        let data = vec![0; 3000];
+        let options = CopyOptions { buffer_size: 100, bandwidth: Some(10_000) };
+
+        let start_time = Instant::now();
+        let mut copy = Vec::new();
+        super::copy_data(&mut io::Cursor::new(&data), &mut copy, options).unwrap();
+
+        assert_eq!(copy, data);
+        assert!(start_time.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[test]
+    fn test_copy_resume() {
+        let temp_dir = create_temp_dir("copy-resume");
+
+        let (src, dst, partial) = (temp_dir.join("src"), temp_dir.join("dst"), temp_dir.join(".dst.tc-partial"));
+        let data: Vec<u8> = (0..3 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        fs::write(&src, &data).unwrap();
+
+        // Matching partial data
+        fs::write(&partial, &data[..2 * 1024 * 1024 + 1]).unwrap();
+        super::copy_downloaded_file(&src, &dst, CopyOptions::default()).unwrap();
+        assert_eq!(fs::read(&dst).unwrap(), data);
+        assert!(!partial.exists());
+
+        // Mismatching partial data
+        fs::remove_file(&dst).unwrap();
+        fs::write(&partial, &data[1..1024]).unwrap();
+        super::copy_downloaded_file(&src, &dst, CopyOptions::default()).unwrap();
+        assert_eq!(fs::read(&dst).unwrap(), data);
+
+        // Existing destination file with the same data is considered as already copied
+        super::copy_downloaded_file(&src, &dst, CopyOptions::default()).unwrap();
+        assert_eq!(fs::read(&dst).unwrap(), data);
+
+        // Existing destination file mustn't be overwritten
+        fs::write(&src, "new data").unwrap();
+        assert!(super::copy_downloaded_file(&src, &dst, CopyOptions::default()).is_err());
+        assert_eq!(fs::read(&dst).unwrap(), data);
+
+        fs::remove_dir_all(&temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_remap_path() {
+        let remaps = vec![
+            (PathBuf::from("/downloads"), PathBuf::from("/mnt/seedbox")),
+            (PathBuf::from("/downloads/incomplete"), PathBuf::from("/mnt/incomplete")),
+        ];
+
+        assert_eq!(super::remap_path("/downloads", &remaps), PathBuf::from("/mnt/seedbox"));
+        assert_eq!(super::remap_path("/downloads/", &remaps), PathBuf::from("/mnt/seedbox"));
+        assert_eq!(super::remap_path("/downloads/movies", &remaps), PathBuf::from("/mnt/seedbox/movies"));
+        assert_eq!(super::remap_path("/downloads/incomplete/a", &remaps), PathBuf::from("/mnt/incomplete/a"));
+        assert_eq!(super::remap_path("/downloads-other", &remaps), PathBuf::from("/downloads-other"));
+        assert_eq!(super::remap_path("/other", &[]), PathBuf::from("/other"));
+    }
+
+    #[test]
+    fn test_get_user_and_group_id() {
+        assert_eq!(super::get_user_id("1000").unwrap(), 1000);
+        assert_eq!(super::get_user_id("root").unwrap(), 0);
+        assert!(super::get_user_id("some-unknown-user").is_err());
+
+        assert_eq!(super::get_group_id("1000").unwrap(), 1000);
+        assert_eq!(super::get_group_id("root").unwrap(), 0);
+        assert!(super::get_group_id("some-unknown-group").is_err());
+    }
+
+    #[test]
+    fn test_fs_error_classification() {
+        let classify = |err: io::Error| match FsError::new(&err, s!("message")) {
+            FsError::NotFound(_) => "not-found",
+            FsError::NoSpace(_) => "no-space",
+            FsError::Permission(_) => "permission",
+            FsError::Other(_) => "other",
+        };
+
+        assert_eq!(classify(io::Error::from_raw_os_error(libc::ENOENT)), "not-found");
+        assert_eq!(classify(io::Error::from_raw_os_error(libc::ENOTDIR)), "not-found");
+        assert_eq!(classify(io::Error::from_raw_os_error(libc::ENOSPC)), "no-space");
+        assert_eq!(classify(io::Error::from_raw_os_error(libc::EDQUOT)), "no-space");
+        assert_eq!(classify(io::Error::from_raw_os_error(libc::EACCES)), "permission");
+        assert_eq!(classify(io::Error::from_raw_os_error(libc::EPERM)), "permission");
+        assert_eq!(classify(io::Error::from_raw_os_error(libc::EIO)), "other");
+        assert_eq!(classify(io::Error::new(io::ErrorKind::NotFound, "message")), "not-found");
+    }
 
     #[test]
     fn test_get_device_usage() {
@@ -242,6 +834,17 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_get_device_usage_localized() {
+        assert_eq!(
+            super::_get_device_usage("/some/path", &RunCommandMock::new("\
+                Dateisystem    1K-Blöcke     Benutzt Verfügbar Verw% Eingehängt auf\n\
+                /dev/sdb1      153836548    55183692  98636472   36% /mnt/var_data\n\
+            ")).unwrap_err().to_string(),
+            "Got an unexpected output header from `df` (probably it's localized)"
+        );
+    }
+
     #[test]
     fn test_get_device_usage_few_devices() {
         assert_eq!(
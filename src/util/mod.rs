@@ -1,4 +1,6 @@
 pub mod fs;
-pub mod helpers;
+pub(crate) mod helpers;
+pub mod net;
 pub mod process;
+pub mod size;
 pub mod time;
@@ -0,0 +1,122 @@
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+
+use crate::common::GenericResult;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Network {
+    address: IpAddr,
+    prefix_len: u8,
+}
+
+impl Network {
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self.address, address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_len as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            },
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_len as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            },
+            _ => false,
+        }
+    }
+}
+
+/// Parses a network in CIDR notation (ADDRESS/PREFIX_LEN). A single address is treated as a
+/// network with the maximum prefix length.
+pub fn parse_network(string: &str) -> GenericResult<Network> {
+    let error = || format!("Invalid network specification: {}", string);
+
+    let (address, prefix_len) = match string.split_once('/') {
+        Some((address, prefix_len)) => (address, Some(prefix_len)),
+        None => (string, None),
+    };
+
+    let address: IpAddr = address.parse().map_err(|_| error())?;
+    let max_prefix_len = if address.is_ipv4() { 32 } else { 128 };
+
+    let prefix_len = match prefix_len {
+        Some(prefix_len) => {
+            if prefix_len.is_empty() || !prefix_len.chars().all(|c| c.is_ascii_digit()) {
+                return Err(error().into());
+            }
+            prefix_len.parse::<u8>().ok().filter(|&prefix_len| prefix_len <= max_prefix_len).ok_or_else(error)?
+        },
+        None => max_prefix_len,
+    };
+
+    Ok(Network { address, prefix_len })
+}
+
+/// Appends the default port to the address in HOST[:PORT] format if it doesn't have one. IPv6 addresses must be
+/// enclosed in square brackets to specify the port.
+pub fn with_default_port(address: &str, default_port: u16) -> String {
+    if address.parse::<SocketAddr>().is_ok() {
+        return s!(address);
+    }
+
+    let ip_address = address.parse::<IpAddr>().ok().or_else(|| {
+        address.strip_prefix('[')?.strip_suffix(']')?.parse::<Ipv6Addr>().ok().map(IpAddr::V6)
+    });
+    if let Some(ip_address) = ip_address {
+        return SocketAddr::new(ip_address, default_port).to_string();
+    }
+
+    match address.rsplit_once(':') {
+        Some((_, port)) if port.parse::<u16>().is_ok() => s!(address),
+        _ => format!("{}:{}", address, default_port),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_network() {
+        let contains = |network: &str, address: &str| {
+            parse_network(network).unwrap().contains(address.parse().unwrap())
+        };
+
+        assert!(contains("192.168.1.0/24", "192.168.1.10"));
+        assert!(!contains("192.168.1.0/24", "192.168.2.10"));
+        assert!(contains("10.0.0.0/8", "10.255.0.1"));
+        assert!(contains("0.0.0.0/0", "8.8.8.8"));
+        assert!(contains("127.0.0.1", "127.0.0.1"));
+        assert!(!contains("127.0.0.1", "127.0.0.2"));
+        assert!(contains("192.168.1.0/24", "::ffff:192.168.1.10"));
+
+        assert!(contains("fd00::/8", "fd12::1"));
+        assert!(!contains("fd00::/8", "fe80::1"));
+        assert!(contains("::/0", "2001:db8::1"));
+        assert!(!contains("::/0", "127.0.0.1"));
+    }
+
+    #[test]
+    fn test_with_default_port() {
+        for (address, expected) in [
+            ("localhost", "localhost:1883"),
+            ("localhost:1884", "localhost:1884"),
+            ("127.0.0.1", "127.0.0.1:1883"),
+            ("127.0.0.1:1884", "127.0.0.1:1884"),
+            ("::1", "[::1]:1883"),
+            ("[::1]", "[::1]:1883"),
+            ("[::1]:1884", "[::1]:1884"),
+        ] {
+            assert_eq!(with_default_port(address, 1883), expected);
+        }
+    }
+
+    #[test]
+    fn test_parse_network_invalid() {
+        for string in ["", "/24", "192.168.1.0/", "192.168.1.0/33", "192.168.1.0/-1", "192.168.1.0/+8", "::/129",
+                       "192.168.1/24", "localhost"] {
+            assert_eq!(
+                parse_network(string).unwrap_err().to_string(),
+                format!("Invalid network specification: {}", string)
+            );
+        }
+    }
+}
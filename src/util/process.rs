@@ -18,7 +18,8 @@ pub fn run_command(command: &str, args: &[String]) -> GenericResult<String> {
         command_string.push_str(arg);
     }
 
-    let output = Command::new(command).args(args).output()
+    // Force the default locale to get a stable output which can be parsed
+    let output = Command::new(command).args(args).env("LC_ALL", "C").output()
         .map_err(|e| format!("Failed to execute `{}`: {}", command_string, e))?;
 
     if !output.status.success() {
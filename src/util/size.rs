@@ -0,0 +1,53 @@
+use regex::Regex;
+
+use crate::common::GenericResult;
+
+pub type Size = u64;
+
+pub fn parse_size(string: &str) -> GenericResult<Size> {
+    let re = Regex::new(r"^(?P<number>\d+)(?P<unit>[KMGT]?)B?$").unwrap();
+    let captures = re.captures(string).ok_or(format!(
+        "Invalid size specification: {}", string))?;
+
+    let number = captures.name("number").unwrap().as_str().parse::<Size>().map_err(|_| format!(
+        "Invalid size specification: {}", string))?;
+
+    let multiplier: Size = match captures.name("unit").unwrap().as_str() {
+        "" => 1,
+        "K" => 1024,
+        "M" => 1024 * 1024,
+        "G" => 1024 * 1024 * 1024,
+        "T" => 1024 * 1024 * 1024 * 1024,
+        _ => unreachable!(),
+    };
+
+    Ok(number.checked_mul(multiplier).ok_or(format!(
+        "Invalid size specification: {}", string))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("0").unwrap(), 0);
+        assert_eq!(parse_size("100").unwrap(), 100);
+        assert_eq!(parse_size("100B").unwrap(), 100);
+        assert_eq!(parse_size("10K").unwrap(), 10 * 1024);
+        assert_eq!(parse_size("10KB").unwrap(), 10 * 1024);
+        assert_eq!(parse_size("3M").unwrap(), 3 * 1024 * 1024);
+        assert_eq!(parse_size("2G").unwrap(), 2 * 1024 * 1024 * 1024);
+        assert_eq!(parse_size("1T").unwrap(), 1024 * 1024 * 1024 * 1024);
+    }
+
+    #[test]
+    fn test_parse_size_invalid() {
+        for string in ["", "K", "1.5G", "-1", "10k", "10 M", "10X", "16777216T", "18446744073709551616"] {
+            assert_eq!(
+                parse_size(string).unwrap_err().to_string(),
+                format!("Invalid size specification: {}", string)
+            );
+        }
+    }
+}
@@ -1,4 +1,7 @@
-use std::cmp::Ordering;
+use std::cmp::{self, Ordering};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Instant;
 
 use legacy_time::Tm;
 use regex::Regex;
@@ -21,12 +24,30 @@ pub type Duration = i64;
 pub type Timestamp = i64;
 pub type DayPeriods = Vec<Period>;
 pub type WeekPeriods = Vec<DayPeriods>;
+// Maps dates to whether the torrents should be active on them regardless of the weekly periods
+pub type DateOverrides = HashMap<::time::Date, bool>;
+
+/// Source of the current time: all time-dependent decisions must get it from here to be testable.
+pub trait Clock {
+    /// Current UNIX time
+    fn timestamp(&self) -> Timestamp {
+        ::time::OffsetDateTime::now_utc().unix_timestamp()
+    }
 
-#[allow(clippy::ptr_arg)]
-pub fn is_now_in(periods: &WeekPeriods) -> bool {
-    is_in(periods, &legacy_time::now())
+    /// Monotonic time for measuring intervals
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    /// Current local time for matching against the periods
+    fn local_time(&self) -> Tm {
+        legacy_time::now()
+    }
 }
 
+pub struct SystemClock;
+impl Clock for SystemClock {}
+
 #[allow(clippy::ptr_arg)]
 pub fn is_in(periods: &WeekPeriods, now: &Tm) -> bool {
     let cur = Time{
@@ -47,22 +68,102 @@ pub fn is_in(periods: &WeekPeriods, now: &Tm) -> bool {
     false
 }
 
+/// Returns the override for the current date (if any) which takes precedence over the weekly periods.
+pub fn get_date_override(overrides: &DateOverrides, now: &Tm) -> Option<bool> {
+    let month = ::time::Month::try_from((now.tm_mon + 1) as u8).ok()?;
+    let date = ::time::Date::from_calendar_date(now.tm_year + 1900, month, now.tm_mday as u8).ok()?;
+    overrides.get(&date).cloned()
+}
+
+/// Splits the whole day into consecutive intervals, marking whether each of them is covered by the
+/// periods.
+pub fn split_day(periods: &DayPeriods) -> Vec<(Period, bool)> {
+    const DAY_END: u32 = 23 * 60 + 59;
+
+    let to_minutes = |time: Time| cmp::min(time.hour as u32 * 60 + time.minute as u32, DAY_END);
+    let from_minutes = |minutes: u32| Time { hour: (minutes / 60) as u8, minute: (minutes % 60) as u8 };
+    let interval = |start: u32, end: u32| Period { start: from_minutes(start), end: from_minutes(end) };
+
+    let mut intervals = Vec::new();
+    let mut cur = 0;
+
+    for period in periods {
+        let (start, end) = (to_minutes(period.start), to_minutes(period.end));
+        if cur > DAY_END {
+            break;
+        }
+
+        if start > cur {
+            intervals.push((interval(cur, start - 1), false));
+        }
+
+        intervals.push((interval(start, end), true));
+        cur = end + 1;
+    }
+
+    if cur <= DAY_END {
+        intervals.push((interval(cur, DAY_END), false));
+    }
+
+    intervals
+}
+
 pub fn parse_duration(string: &str) -> GenericResult<Duration> {
-    let re = Regex::new(r"^(?P<number>[1-9]\d*)(?P<unit>[mhd])$").unwrap();
-    let captures = re.captures(string).ok_or(format!(
-        "Invalid time specification: {}", string))?;
-
-    let mut duration = captures.name("number").unwrap().as_str().parse::<Duration>().unwrap();
-    duration *= match captures.name("unit").unwrap().as_str() {
-        "m" => 60,
-        "h" => 60 * 60,
-        "d" => 60 * 60 * 24,
-        _ => unreachable!(),
-    };
+    let error = || format!("Invalid time specification: {}", string);
+
+    let validation_re = Regex::new(r"^(?:[1-9]\d*[smhdw])+$").unwrap();
+    if !validation_re.is_match(string) {
+        return Err!("Invalid time specification: {}", string);
+    }
+
+    let segment_re = Regex::new(r"(?P<number>[1-9]\d*)(?P<unit>[smhdw])").unwrap();
+
+    let mut units = String::new();
+    let mut duration: Duration = 0;
+
+    for captures in segment_re.captures_iter(string) {
+        let unit = captures.name("unit").unwrap().as_str();
+        if units.contains(unit) {
+            return Err!("Invalid time specification: {}", string);
+        }
+        units.push_str(unit);
+
+        let number = captures.name("number").unwrap().as_str().parse::<Duration>().map_err(|_| error())?;
+        let multiplier = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 60 * 60 * 24,
+            "w" => 60 * 60 * 24 * 7,
+            _ => unreachable!(),
+        };
+
+        duration = number.checked_mul(multiplier)
+            .and_then(|segment| duration.checked_add(segment))
+            .ok_or_else(error)?;
+    }
 
     Ok(duration)
 }
 
+pub fn parse_date_override(string: &str) -> GenericResult<(::time::Date, bool)> {
+    let error = || format!("Invalid date override specification: {}", string);
+
+    let override_re = Regex::new(
+        r"^\s*(?P<year>\d{4})-(?P<month>\d{2})-(?P<day>\d{2})\s*:\s*(?P<state>active|paused)\s*$").unwrap();
+    let captures = override_re.captures(string).ok_or_else(error)?;
+
+    let year = captures.name("year").unwrap().as_str().parse::<i32>().unwrap();
+    let month = captures.name("month").unwrap().as_str().parse::<u8>().unwrap();
+    let day = captures.name("day").unwrap().as_str().parse::<u8>().unwrap();
+
+    let date = ::time::Month::try_from(month).ok()
+        .and_then(|month| ::time::Date::from_calendar_date(year, month, day).ok())
+        .ok_or_else(|| format!("Invalid date in '{}' date override", string))?;
+
+    Ok((date, captures.name("state").unwrap().as_str() == "active"))
+}
+
 pub fn parse_periods(period_strings: &[String]) -> GenericResult<WeekPeriods> {
     let mut week_periods = Vec::with_capacity(7);
     for _ in 0..7 {
@@ -148,6 +249,12 @@ pub fn parse_periods(period_strings: &[String]) -> GenericResult<WeekPeriods> {
 }
 
 
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.hour, self.minute)
+    }
+}
+
 impl PartialOrd for Time {
     fn partial_cmp(&self, other: &Time) -> Option<Ordering> {
         Some(Ord::cmp(self, other))
@@ -225,6 +332,79 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_split_day() {
+        assert_eq!(split_day(&vec![]), vec![
+            (Period::new(Time::new(0, 0), Time::new(23, 59)), false),
+        ]);
+
+        assert_eq!(split_day(&vec![Period::new(Time::new(0, 0), Time::new(24, 0))]), vec![
+            (Period::new(Time::new(0, 0), Time::new(23, 59)), true),
+        ]);
+
+        assert_eq!(split_day(&vec![
+            Period::new(Time::new(0, 0), Time::new(5, 19)),
+            Period::new(Time::new(6, 20), Time::new(7, 9)),
+            Period::new(Time::new(7, 10), Time::new(7, 30)),
+        ]), vec![
+            (Period::new(Time::new(0, 0), Time::new(5, 19)), true),
+            (Period::new(Time::new(5, 20), Time::new(6, 19)), false),
+            (Period::new(Time::new(6, 20), Time::new(7, 9)), true),
+            (Period::new(Time::new(7, 10), Time::new(7, 30)), true),
+            (Period::new(Time::new(7, 31), Time::new(23, 59)), false),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90m").unwrap(), 90 * 60);
+        assert_eq!(parse_duration("12h").unwrap(), 12 * 60 * 60);
+        assert_eq!(parse_duration("3d").unwrap(), 3 * 24 * 60 * 60);
+        assert_eq!(parse_duration("1d6h").unwrap(), (24 + 6) * 60 * 60);
+        assert_eq!(parse_duration("1d12h30m").unwrap(), ((24 + 12) * 60 + 30) * 60);
+        assert_eq!(parse_duration("30m1d").unwrap(), (24 * 60 + 30) * 60);
+    }
+
+    #[test]
+    fn test_parse_duration_seconds_and_weeks() {
+        assert_eq!(parse_duration("1s").unwrap(), 1);
+        assert_eq!(parse_duration("30s").unwrap(), 30);
+        assert_eq!(parse_duration("1w").unwrap(), 7 * 24 * 60 * 60);
+        assert_eq!(parse_duration("2w").unwrap(), 14 * 24 * 60 * 60);
+        assert_eq!(parse_duration("1w1d1h1m1s").unwrap(), (((7 + 1) * 24 + 1) * 60 + 1) * 60 + 1);
+        assert_eq!(parse_duration("15250284452471w").unwrap(), 15250284452471 * 7 * 24 * 60 * 60);
+    }
+
+    #[test]
+    fn test_parse_duration_invalid() {
+        for string in ["", "d", "1", "0d", "1d0h", "01d", "1d1d", "1d 12h", "1d12", "1x", "-1d", "0s", "0w", "1w0s", "1s1s",
+                       "15250284452472w", "9223372036854775808s"] {
+            assert_eq!(
+                parse_duration(string).unwrap_err().to_string(),
+                format!("Invalid time specification: {}", string)
+            );
+        }
+    }
+
+    #[test]
+    fn test_date_overrides() {
+        let holiday = parse_date_override("2024-12-25: paused").unwrap();
+        let workday = parse_date_override("2024-02-29:active").unwrap();
+        assert_eq!(holiday, (::time::Date::from_calendar_date(2024, ::time::Month::December, 25).unwrap(), false));
+        assert_eq!(workday, (::time::Date::from_calendar_date(2024, ::time::Month::February, 29).unwrap(), true));
+
+        for string in ["2024-12-25", "2024-12-25:stopped", "24-12-25:paused", "2023-02-29:paused", "2024-13-01:active"] {
+            assert!(parse_date_override(string).is_err(), "{}", string);
+        }
+
+        let overrides: DateOverrides = vec![holiday, workday].into_iter().collect();
+        let now = Tm { tm_year: 124, tm_mon: 11, tm_mday: 25, .. legacy_time::empty_tm() };
+        assert_eq!(get_date_override(&overrides, &now), Some(false));
+
+        let now = Tm { tm_mday: 24, .. now };
+        assert_eq!(get_date_override(&overrides, &now), None);
+    }
+
     #[test]
     fn test_parse_periods() {
         let period_strings = ["1-5/6:20-7:09", "1-5/0:00-5:19", "6-7/0:00-8:59"]